@@ -0,0 +1,58 @@
+// Compares file size and load time of a 64³ context stored as CBOR, JSON and raw bytes.
+// Run with `cargo bench --bench context_formats`
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use gradwork_ca::ca::CAContext;
+
+const SIZE: usize = 64;
+const ROUNDS: u32 = 5;
+
+fn time_loads(rounds: u32, mut load: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..rounds {
+        load();
+    }
+    start.elapsed() / rounds
+}
+
+fn report(name: &str, path: &Path, load_time: Duration) {
+    let bytes = fs::metadata(path).expect("Failed to stat output").len();
+    println!("{name:<6} {bytes:>10} bytes  {load_time:>12.2?} per load");
+}
+
+fn main() {
+    let ctx = CAContext::random(SIZE, SIZE, SIZE, 0, 0.5);
+    let dir = std::env::temp_dir().join("gradwork_ca_context_formats");
+    fs::create_dir_all(&dir).expect("Failed to create bench directory");
+
+    let cbor: PathBuf = dir.join("context.cbor");
+    ctx.save_cbor(&cbor).expect("Failed to write CBOR");
+    let t = time_loads(ROUNDS, || {
+        CAContext::load_cbor(&cbor).expect("Failed to read CBOR");
+    });
+    report("cbor", &cbor, t);
+
+    let json = dir.join("context.json");
+    fs::write(
+        &json,
+        serde_json::to_vec(&ctx).expect("Failed to encode JSON"),
+    )
+    .expect("Failed to write JSON");
+    let t = time_loads(ROUNDS, || {
+        let text = fs::read(&json).expect("Failed to read JSON");
+        let _: CAContext = serde_json::from_slice(&text).expect("Failed to decode JSON");
+    });
+    report("json", &json, t);
+
+    let raw = dir.join("context.raw");
+    let bytes: Vec<u8> = ctx.cells().iter().map(|c| c.0).collect();
+    fs::write(&raw, bytes).expect("Failed to write raw");
+    let t = time_loads(ROUNDS, || {
+        fs::read(&raw).expect("Failed to read raw");
+    });
+    report("raw", &raw, t);
+}
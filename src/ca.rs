@@ -1,20 +1,93 @@
-use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+use crate::metrics::Connectivity;
+use noise::{NoiseFn, Perlin};
 use rayon::prelude::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
+    cmp::Ordering,
     collections::VecDeque,
-    ops::{Index, IndexMut},
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    ops::{Index, IndexMut, Range},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub(crate) const FACE_OFFSETS: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+// Text output caps, anything beyond is replaced with "..."
+const DISPLAY_MAX_LAYERS: usize = 16;
+const DISPLAY_MAX_ROWS: usize = 16;
+const DISPLAY_MAX_COLUMNS: usize = 80;
+
+// Grids with fewer cells than this are counted on a single thread
+const PARALLEL_COUNT_THRESHOLD: usize = 1 << 16;
+
+// Auto mode switches to incremental counting above this many neighbors (Moore)
+const AUTO_INCREMENTAL_NEIGHBORS: usize = 26;
+
+// RNG iteration key of the random walks, far past any iteration a run reaches
+const WALK_RNG_ITERATION: u64 = u64::MAX;
+
+// RNG iteration key of the entrance positions, next to the walks
+const ENTRANCE_RNG_ITERATION: u64 = u64::MAX - 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum Axis {
     X,
     Y,
     Z,
 }
 
+// One of the six outer faces of the grid
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Face {
+    MinX,
+    MaxX,
+    MinY,
+    MaxY,
+    MinZ,
+    MaxZ,
+}
+
+impl Face {
+    // The two faces an axis runs between, min first
+    #[must_use]
+    pub fn of_axis(axis: Axis) -> (Face, Face) {
+        match axis {
+            Axis::X => (Face::MinX, Face::MaxX),
+            Axis::Y => (Face::MinY, Face::MaxY),
+            Axis::Z => (Face::MinZ, Face::MaxZ),
+        }
+    }
+
+    #[must_use]
+    pub fn contains(self, ctx: &CAContext, x: usize, y: usize, z: usize) -> bool {
+        match self {
+            Face::MinX => x == 0,
+            Face::MaxX => x + 1 == ctx.width(),
+            Face::MinY => y == 0,
+            Face::MaxY => y + 1 == ctx.height(),
+            Face::MinZ => z == 0,
+            Face::MaxZ => z + 1 == ctx.depth(),
+        }
+    }
+}
+
 // A cell for a cellular automation engine. Currently just boolean based, use a u8 to avoid bitpacking for performance
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[repr(transparent)]
 pub struct CACell(pub u8);
 
@@ -34,6 +107,125 @@ impl CACell {
     }
 }
 
+// Counter-based generator keyed by (seed, iteration, cell index). Every cell gets its own
+// stream, so parallel code draws the same numbers no matter how the work is split over threads
+#[derive(Copy, Clone, Debug)]
+pub struct CellRng {
+    state: u64,
+}
+
+impl CellRng {
+    #[must_use]
+    pub fn new(seed: u64, iteration: u64, index: u64) -> Self {
+        let mut state = splitmix64(seed);
+        state = splitmix64(state ^ iteration);
+        state = splitmix64(state ^ index);
+        Self { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        splitmix64(self.state)
+    }
+
+    // Uniform in [0, 1) with 53 bits of precision
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    pub fn bool_with_prob(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    pub fn uniform_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+// The key at index `n` of the sorted keys. A parallel histogram over the top bits narrows
+// the search to one bucket, only that bucket is selected from serially
+fn nth_smallest_key(keys: &[u64], n: usize) -> u64 {
+    const BUCKET_BITS: u32 = 16;
+    let bucket = |key: u64| (key >> (64 - BUCKET_BITS)) as usize;
+
+    let counts = (keys.par_iter())
+        .fold(
+            || vec![0usize; 1 << BUCKET_BITS],
+            |mut counts, &key| {
+                counts[bucket(key)] += 1;
+                counts
+            },
+        )
+        .reduce_with(|mut a, b| {
+            a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+            a
+        })
+        .unwrap_or_default();
+
+    let mut below = 0;
+    for (b, &count) in counts.iter().enumerate() {
+        if n < below + count {
+            let mut inside: Vec<u64> = (keys.par_iter().copied())
+                .filter(|&key| bucket(key) == b)
+                .collect();
+            return *inside.select_nth_unstable(n - below).1;
+        }
+        below += count;
+    }
+    panic!("key {n} requested from {} keys", keys.len())
+}
+
+// Maps a float to a key with the same order as f64::total_cmp
+fn float_key(v: f64) -> u64 {
+    let bits = v.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | 1 << 63
+    }
+}
+
+// Piecewise linear lookup in (x, y) points sorted by x, clamped to the end values
+fn interpolate(points: &[(f64, f64)], x: f64) -> f64 {
+    let Some(upper) = points.iter().position(|&(px, _)| px >= x) else {
+        return points.last().map_or(0.0, |&(_, y)| y);
+    };
+    if upper == 0 {
+        return points[0].1;
+    }
+
+    let (x0, y0) = points[upper - 1];
+    let (x1, y1) = points[upper];
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+// Bijective 64-bit mixer, every input bit affects every output bit
+#[must_use]
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// FNV-1a over the concatenated parts. FNV alone would leave inputs that differ in their last
+// byte evenly spaced, the final mix spreads them over the whole range
+#[must_use]
+pub fn hash_seed(parts: &[&[u8]]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    splitmix64(
+        parts
+            .iter()
+            .copied()
+            .flatten()
+            .fold(FNV_OFFSET, |hash, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+            }),
+    )
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CANeighborhood {
     pub name: String,
@@ -91,370 +283,6033 @@ impl CANeighborhood {
             offsets,
         }
     }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum CARuleType {
-    Standard {
-        birth: Vec<usize>,
-        survival: Vec<usize>,
-    },
-    Threshold(usize),
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CARule {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub rule_type: CARuleType,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CAConfig {
-    pub neighborhood: CANeighborhood,
-    pub rule: CARule,
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct IterationStats {
-    pub iter: usize,
-    pub alive: usize,
-    pub min_neighbors: usize,
-    pub max_neighbors: usize,
-    pub mean_neighbors: f64,
-}
-
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct CAContext {
-    width: usize,
-    height: usize,
-    depth: usize,
-    cells: Vec<CACell>,
-}
 
-impl CAContext {
+    // All offsets within Manhattan distance `radius`, excluding the origin
     #[must_use]
-    pub fn new(width: usize, height: usize, depth: usize) -> Self {
-        let cells = vec![CACell::new(0); width * height * depth];
+    pub fn extended_von_neumann(radius: i32) -> Self {
+        let mut offsets = Vec::new();
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let d = x.abs() + y.abs() + z.abs();
+                    if d != 0 && d <= radius {
+                        offsets.push((x, y, z));
+                    }
+                }
+            }
+        }
         Self {
-            width,
-            height,
-            depth,
-            cells,
+            name: format!("extended_von_neumann_{radius}"),
+            offsets,
         }
     }
 
+    // In-plane Moore offsets plus the cells directly above and below
     #[must_use]
-    pub fn random(
-        width: usize,
-        height: usize,
-        depth: usize,
-        seed: u64,
-        air_percentage: f64,
-    ) -> Self {
-        let n = width * height * depth;
-        let air_cells = (n as f64 * air_percentage).round() as usize;
-        let solid_cells = n - air_cells;
-
-        // Create vector with exact counts
-        let mut cells = Vec::with_capacity(n);
-        cells.extend(std::iter::repeat_n(CACell::new(1), air_cells)); // air
-        cells.extend(std::iter::repeat_n(CACell::new(0), solid_cells)); // solid
-
-        // Shuffle to randomize positions
-        let mut rng = SmallRng::seed_from_u64(seed);
-        cells.shuffle(&mut rng);
+    pub fn layered_moore() -> Self {
+        let mut offsets = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                if x != 0 || y != 0 {
+                    offsets.push((x, y, 0));
+                }
+            }
+        }
+        offsets.push((0, 0, 1));
+        offsets.push((0, 0, -1));
 
         Self {
-            width,
-            height,
-            depth,
-            cells,
+            name: "layered_moore".to_string(),
+            offsets,
         }
     }
 
+    // In-plane von Neumann offsets only
     #[must_use]
-    pub fn idx(&self, x: usize, y: usize, z: usize) -> usize {
-        x + self.width * (y + self.height * z)
+    pub fn cross_2d() -> Self {
+        Self {
+            name: "cross_2d".to_string(),
+            offsets: vec![(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0)],
+        }
     }
 
+    // The 12 face-centered cubic nearest neighbors, (±1, ±1, 0) and permutations
     #[must_use]
-    pub fn pos(&self, index: usize) -> (usize, usize, usize) {
-        let z = index / (self.width * self.height);
-        let y = (index % (self.width * self.height)) / self.width;
-        let x = index % self.width;
-        (x, y, z)
+    pub fn fcc12() -> Self {
+        let mut offsets = Vec::new();
+        for x in -1..=1i32 {
+            for y in -1..=1i32 {
+                for z in -1..=1i32 {
+                    if x.abs() + y.abs() + z.abs() == 2 {
+                        offsets.push((x, y, z));
+                    }
+                }
+            }
+        }
+        Self {
+            name: "fcc".to_string(),
+            offsets,
+        }
     }
 
+    // The 8 body-centered cubic corners, (±1, ±1, ±1)
     #[must_use]
-    pub fn get(&self, x: usize, y: usize, z: usize) -> CACell {
-        self.cells[self.idx(x, y, z)]
-    }
-
-    pub fn set(&mut self, x: usize, y: usize, z: usize, value: CACell) {
-        let i = self.idx(x, y, z);
-        self.cells[i] = value;
+    pub fn bcc8() -> Self {
+        let mut offsets = Vec::new();
+        for x in [-1, 1] {
+            for y in [-1, 1] {
+                for z in [-1, 1] {
+                    offsets.push((x, y, z));
+                }
+            }
+        }
+        Self {
+            name: "bcc".to_string(),
+            offsets,
+        }
     }
 
+    // Arbitrary offset list, duplicates are dropped
     #[must_use]
-    pub fn count_air_neighbors(&self, x: usize, y: usize, z: usize, nb: &CANeighborhood) -> usize {
-        let mut count = 0;
-
-        for &(dx, dy, dz) in &nb.offsets {
-            let nx = x as i32 + dx;
-            let ny = y as i32 + dy;
-            let nz = z as i32 + dz;
-
-            if nx < 0 || ny < 0 || nz < 0 {
-                continue;
-            }
-            let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
-
-            if nx >= self.width || ny >= self.height || nz >= self.depth {
-                continue;
+    pub fn custom(name: impl Into<String>, offsets: &[(i32, i32, i32)]) -> Self {
+        let mut unique = Vec::with_capacity(offsets.len());
+        for &o in offsets {
+            if !unique.contains(&o) {
+                unique.push(o);
             }
-
-            count += self.get(nx, ny, nz).0 as usize;
         }
 
-        count
+        Self {
+            name: name.into(),
+            offsets: unique,
+        }
     }
 
     #[must_use]
-    pub fn total_air_cells(&self) -> usize {
-        self.cells.iter().filter(|cell| cell.is_air()).count()
+    pub fn union(&self, other: &CANeighborhood) -> Self {
+        let offsets: Vec<_> = self.offsets.iter().chain(&other.offsets).copied().collect();
+        Self::custom(format!("{}+{}", self.name, other.name), &offsets)
     }
 
     #[must_use]
-    pub fn total_solid_cells(&self) -> usize {
-        self.cells.iter().filter(|cell| !cell.is_air()).count()
+    pub fn difference(&self, other: &CANeighborhood) -> Self {
+        let mut result = self.without(&other.offsets);
+        result.name = format!("{}-{}", self.name, other.name);
+        result
     }
 
     #[must_use]
-    pub fn width(&self) -> usize {
-        self.width
+    pub fn without(&self, offsets: &[(i32, i32, i32)]) -> Self {
+        let kept: Vec<_> = (self.offsets.iter())
+            .copied()
+            .filter(|o| !offsets.contains(o))
+            .collect();
+        Self::custom(format!("{}-custom", self.name), &kept)
     }
 
     #[must_use]
-    pub fn height(&self) -> usize {
-        self.height
+    pub fn offsets(&self) -> &[(i32, i32, i32)] {
+        &self.offsets
     }
 
     #[must_use]
-    pub fn depth(&self) -> usize {
-        self.depth
+    pub fn len(&self) -> usize {
+        self.offsets.len()
     }
 
     #[must_use]
-    pub fn cells(&self) -> &[CACell] {
-        &self.cells
-    }
-
-    pub fn cells_mut(&mut self) -> &mut [CACell] {
-        &mut self.cells
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
     }
 
+    // Whether the offsets are exactly the six face neighbors, in any order
     #[must_use]
-    pub fn connected_components(&self) -> Vec<Vec<usize>> {
-        let n = self.width() * self.height() * self.depth();
-        let mut visited = vec![false; n];
-        let mut components = Vec::new();
+    pub fn is_faces(&self) -> bool {
+        let mut offsets = self.offsets.clone();
+        let mut faces = FACE_OFFSETS;
+        offsets.sort_unstable();
+        faces.sort_unstable();
+        offsets == faces
+    }
+}
 
-        let dirs = [
-            (1, 0, 0),
-            (-1, 0, 0),
-            (0, 1, 0),
-            (0, -1, 0),
-            (0, 0, 1),
-            (0, 0, -1),
+// Built-in neighborhoods print as the string FromStr parses back, anything else as
+// "custom(N offsets)". The offsets decide, not the name
+impl fmt::Display for CANeighborhood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fixed = [
+            ("von_neumann", CANeighborhood::von_neumann()),
+            ("moore", CANeighborhood::moore()),
+            ("layered_moore", CANeighborhood::layered_moore()),
+            ("cross_2d", CANeighborhood::cross_2d()),
+            ("fcc", CANeighborhood::fcc12()),
+            ("bcc", CANeighborhood::bcc8()),
         ];
+        if let Some((name, _)) = fixed.iter().find(|(_, nb)| nb.offsets == self.offsets) {
+            return write!(f, "{name}");
+        }
 
-        for i in 0..n {
-            if visited[i] || !self[i].is_air() {
-                continue;
+        let radius = (self.offsets.iter())
+            .map(|&(x, y, z)| x.abs().max(y.abs()).max(z.abs()))
+            .max()
+            .unwrap_or(0);
+        if radius > 1 {
+            if self.offsets == CANeighborhood::extended_moore(radius).offsets {
+                return write!(f, "extended_moore({radius})");
+            }
+            if self.offsets == CANeighborhood::extended_von_neumann(radius).offsets {
+                return write!(f, "extended_von_neumann({radius})");
             }
+        }
 
-            let mut queue = VecDeque::new();
-            let mut component = Vec::new();
+        write!(f, "custom({} offsets)", self.offsets.len())
+    }
+}
 
-            visited[i] = true;
-            queue.push_back(i);
+impl FromStr for CANeighborhood {
+    type Err = ParseNeighborhoodError;
 
-            while let Some(idx) = queue.pop_front() {
-                component.push(idx);
-                let (x, y, z) = self.pos(idx);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseNeighborhoodError {
+            input: s.to_string(),
+        };
 
-                for (dx, dy, dz) in dirs {
-                    let nx = x as i32 + dx;
-                    let ny = y as i32 + dy;
-                    let nz = z as i32 + dz;
+        let s = s.trim();
+        if let Some((kind, rest)) = s.split_once('(') {
+            let radius: i32 = (rest.strip_suffix(')'))
+                .and_then(|r| r.trim().parse().ok())
+                .filter(|&r| r >= 1)
+                .ok_or_else(err)?;
 
-                    if nx < 0 || ny < 0 || nz < 0 {
-                        continue;
-                    }
+            return match kind {
+                "extended_moore" => Ok(CANeighborhood::extended_moore(radius)),
+                "extended_von_neumann" => Ok(CANeighborhood::extended_von_neumann(radius)),
+                _ => Err(err()),
+            };
+        }
 
-                    let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+        match s {
+            "von_neumann" => Ok(CANeighborhood::von_neumann()),
+            "moore" => Ok(CANeighborhood::moore()),
+            "layered_moore" => Ok(CANeighborhood::layered_moore()),
+            "cross_2d" => Ok(CANeighborhood::cross_2d()),
+            "fcc" => Ok(CANeighborhood::fcc12()),
+            "bcc" => Ok(CANeighborhood::bcc8()),
+            _ => Err(err()),
+        }
+    }
+}
 
-                    if nx >= self.width() || ny >= self.height() || nz >= self.depth() {
-                        continue;
-                    }
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseNeighborhoodError {
+    pub input: String,
+}
 
-                    let nidx = self.idx(nx, ny, nz);
-                    if !visited[nidx] && self[nidx].is_air() {
-                        visited[nidx] = true;
-                        queue.push_back(nidx);
-                    }
-                }
-            }
+impl fmt::Display for ParseNeighborhoodError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown neighborhood {:?}, expected von_neumann, moore, layered_moore, cross_2d, \
+             fcc, bcc, extended_moore(R) or extended_von_neumann(R)",
+            self.input
+        )
+    }
+}
 
-            components.push(component);
-        }
+impl std::error::Error for ParseNeighborhoodError {}
 
-        components
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CARuleType {
+    Standard {
+        birth: Vec<usize>,
+        survival: Vec<usize>,
+    },
+    Threshold(usize),
+    // Rock turns to air with at least this many air neighbors, air always stays air. Only valid
+    // with von_neumann, so the neighbors are exactly the exposed faces
+    Erosion(usize),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CARule {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub rule_type: CARuleType,
+    // Softens the rule into a logistic probability, None or 0 keeps the exact integer rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+}
+
+impl CARule {
+    // Finishing pass that wears away rock with at least `exposed` air faces
+    #[must_use]
+    pub fn erosion(exposed: usize) -> Self {
+        CARule {
+            name: format!("E{exposed}"),
+            rule_type: CARuleType::Erosion(exposed),
+            temperature: None,
+        }
     }
 
+    // Largest neighbor count the rule refers to
     #[must_use]
-    pub fn percolates(&self, components: &[Vec<usize>], axis: Axis) -> bool {
-        for comp in components {
-            let mut min = usize::MAX;
-            let mut max = 0;
+    pub fn max_count(&self) -> usize {
+        match &self.rule_type {
+            CARuleType::Standard { birth, survival } => {
+                birth.iter().chain(survival).copied().max().unwrap_or(0)
+            }
+            CARuleType::Threshold(threshold) => *threshold,
+            CARuleType::Erosion(exposed) => *exposed,
+        }
+    }
 
-            for &idx in comp {
-                let (x, y, z) = self.pos(idx);
-                let v = match axis {
-                    Axis::X => x,
-                    Axis::Y => y,
-                    Axis::Z => z,
-                };
+    // Checks the rule can actually fire under the neighborhood, plus the cell itself with
+    // include_self. Unreachable counts silently leave parts of the transition table dead, empty
+    // sets and out of range thresholds make the whole grid collapse to one state
+    pub fn validate(
+        &self,
+        neighborhood: &CANeighborhood,
+        include_self: bool,
+    ) -> Result<(), RuleValidationError> {
+        let max = neighborhood.len() + usize::from(include_self);
+        let mut problems = Vec::new();
 
-                min = min.min(v);
-                max = max.max(v);
+        match &self.rule_type {
+            CARuleType::Standard { birth, survival } => {
+                for (kind, counts) in [("birth", birth), ("survival", survival)] {
+                    if counts.is_empty() {
+                        problems.push(RuleProblem::EmptySet { kind });
+                    }
+
+                    let unreachable: Vec<usize> = counts
+                        .iter()
+                        .copied()
+                        .filter(|&count| count > max)
+                        .collect();
+                    if !unreachable.is_empty() {
+                        problems.push(RuleProblem::UnreachableCounts {
+                            kind,
+                            counts: unreachable,
+                        });
+                    }
+                }
+            }
+            CARuleType::Threshold(threshold) if *threshold == 0 || *threshold > max => {
+                problems.push(RuleProblem::ThresholdOutOfRange {
+                    threshold: *threshold,
+                });
+            }
+            CARuleType::Threshold(_) => {}
+            CARuleType::Erosion(exposed) if *exposed == 0 || *exposed > max => {
+                problems.push(RuleProblem::ExposureOutOfRange { exposed: *exposed });
             }
+            CARuleType::Erosion(_) => {}
+        }
+        if matches!(self.rule_type, CARuleType::Erosion(_)) && !neighborhood.is_faces() {
+            problems.push(RuleProblem::ErosionNeighborhood);
+        }
 
-            let limit = match axis {
-                Axis::X => self.width() - 1,
-                Axis::Y => self.height() - 1,
-                Axis::Z => self.depth() - 1,
-            };
+        if problems.is_empty() {
+            return Ok(());
+        }
 
-            if min == 0 && max == limit {
-                return true;
+        Err(RuleValidationError {
+            ruleset: self.name.clone(),
+            neighborhood: neighborhood.name.clone(),
+            neighbors: max,
+            problems,
+        })
+    }
+
+    #[must_use]
+    pub fn is_soft(&self) -> bool {
+        self.temperature.is_some_and(|t| t > 0.0)
+    }
+
+    // Chance of becoming air at temperature t. A threshold uses sigmoid((threshold - 1/2 - n) / t),
+    // a birth/survival set uses sigmoid((1/2 - d) / t) with d the distance from n to the nearest
+    // listed count. Both turn into the crisp rule as t goes to 0
+    #[must_use]
+    pub fn air_probability(&self, alive: bool, alive_neighbors: usize, temperature: f64) -> f64 {
+        let margin = match &self.rule_type {
+            CARuleType::Standard { birth, survival } => {
+                let counts = if alive { survival } else { birth };
+                let Some(distance) = (counts.iter())
+                    .map(|&count| count.abs_diff(alive_neighbors))
+                    .min()
+                else {
+                    return 0.0;
+                };
+                0.5 - distance as f64
             }
+            CARuleType::Threshold(threshold) => *threshold as f64 - 0.5 - alive_neighbors as f64,
+            CARuleType::Erosion(_) if alive => return 1.0,
+            CARuleType::Erosion(exposed) => alive_neighbors as f64 + 0.5 - *exposed as f64,
+        };
+
+        1.0 / (1.0 + (-margin / temperature).exp())
+    }
+
+    // Crisp rules take the integer path, soft ones draw from the cell's own RNG stream so the
+    // outcome only depends on (seed, iteration, index)
+    #[must_use]
+    pub fn next_state_seeded(
+        &self,
+        alive: bool,
+        alive_neighbors: usize,
+        seed: u64,
+        iteration: u64,
+        index: usize,
+    ) -> bool {
+        match self.temperature {
+            Some(t) if t > 0.0 => CellRng::new(seed, iteration, index as u64)
+                .bool_with_prob(self.air_probability(alive, alive_neighbors, t)),
+            _ => self.next_state(alive, alive_neighbors),
         }
+    }
 
-        false
+    #[must_use]
+    pub fn next_state(&self, alive: bool, alive_neighbors: usize) -> bool {
+        match &self.rule_type {
+            CARuleType::Standard { birth, survival } => {
+                if alive {
+                    survival.contains(&alive_neighbors)
+                } else {
+                    birth.contains(&alive_neighbors)
+                }
+            }
+            CARuleType::Threshold(threshold) => alive_neighbors < *threshold,
+            CARuleType::Erosion(exposed) => alive || alive_neighbors >= *exposed,
+        }
     }
+}
 
+// Primitives for evolutionary rule search
+#[cfg(feature = "search")]
+impl CARule {
+    // Stable name derived from the rule alone: "T3" for thresholds, "B5.6.7_S4.5" for
+    // birth/survival sets, with "_t0.5" appended for a soft rule so rules that only differ in
+    // temperature get their own run directory. The part before that parses back with
+    // CARuleType::from_str and the whole name is safe in run directories
     #[must_use]
-    pub fn neighbor_stats(&self, nb: &CANeighborhood) -> (usize, usize, f64) {
-        let mut min = usize::MAX;
-        let mut max = 0;
-        let mut sum = 0usize;
-        let mut count = 0usize;
+    pub fn canonical_name(&self) -> String {
+        match self.temperature {
+            Some(t) if self.is_soft() => format!("{}_t{t}", self.rule_type),
+            _ => self.rule_type.to_string(),
+        }
+    }
 
-        for z in 0..self.depth {
-            for y in 0..self.height {
-                for x in 0..self.width {
-                    let n = self.count_air_neighbors(x, y, z, nb);
-                    min = min.min(n);
-                    max = max.max(n);
-                    sum += n;
-                    count += 1;
+    // Toggles random counts in birth or survival, or shifts a threshold, keeping every count in
+    // 0..=max_count. `strength` in 0..=1 scales how many counts change, at least one step is taken
+    #[must_use]
+    pub fn mutate(&self, rng: &mut impl rand::Rng, strength: f64, max_count: usize) -> CARule {
+        let steps = ((strength.clamp(0.0, 1.0) * (max_count + 1) as f64).round() as usize).max(1);
+
+        let rule_type = match &self.rule_type {
+            CARuleType::Standard { birth, survival } => {
+                let mut sets = [birth.clone(), survival.clone()];
+                for set in &mut sets {
+                    set.retain(|&count| count <= max_count);
+                }
+
+                for _ in 0..steps {
+                    let set = &mut sets[usize::from(rng.random_bool(0.5))];
+                    let count = rng.random_range(0..=max_count);
+                    match set.iter().position(|&c| c == count) {
+                        Some(i) => {
+                            set.remove(i);
+                        }
+                        None => set.push(count),
+                    }
+                }
+
+                let [mut birth, mut survival] = sets;
+                birth.sort_unstable();
+                survival.sort_unstable();
+                CARuleType::Standard { birth, survival }
+            }
+            CARuleType::Threshold(count) | CARuleType::Erosion(count) => {
+                let shift = rng.random_range(1..=steps);
+                let count = (*count).min(max_count);
+                let count = if rng.random_bool(0.5) {
+                    (count + shift).min(max_count)
+                } else {
+                    count.saturating_sub(shift)
+                };
+                match self.rule_type {
+                    CARuleType::Erosion(_) => CARuleType::Erosion(count),
+                    _ => CARuleType::Threshold(count),
+                }
+            }
+        };
+
+        CARule::from_search(rule_type, self.temperature)
+    }
+
+    // Uniform crossover, every count comes from either parent with equal chance. Parents of
+    // different rule types can't mix, the child is then a copy of one of them
+    #[must_use]
+    pub fn crossover(&self, other: &CARule, rng: &mut impl rand::Rng) -> CARule {
+        let temperature = if rng.random_bool(0.5) {
+            self.temperature
+        } else {
+            other.temperature
+        };
+
+        let rule_type = match (&self.rule_type, &other.rule_type) {
+            (
+                CARuleType::Standard { birth, survival },
+                CARuleType::Standard {
+                    birth: other_birth,
+                    survival: other_survival,
+                },
+            ) => {
+                let mut mix = |a: &[usize], b: &[usize]| -> Vec<usize> {
+                    let max = a.iter().chain(b).copied().max().unwrap_or(0);
+                    (0..=max)
+                        .filter(|count| {
+                            let parent = if rng.random_bool(0.5) { a } else { b };
+                            parent.contains(count)
+                        })
+                        .collect()
+                };
+                CARuleType::Standard {
+                    birth: mix(birth, other_birth),
+                    survival: mix(survival, other_survival),
                 }
             }
+            (CARuleType::Threshold(a), CARuleType::Threshold(b)) => {
+                CARuleType::Threshold(rng.random_range(*a.min(b)..=*a.max(b)))
+            }
+            (CARuleType::Erosion(a), CARuleType::Erosion(b)) => {
+                CARuleType::Erosion(rng.random_range(*a.min(b)..=*a.max(b)))
+            }
+            _ if rng.random_bool(0.5) => self.rule_type.clone(),
+            _ => other.rule_type.clone(),
+        };
+
+        CARule::from_search(rule_type, temperature)
+    }
+
+    fn from_search(rule_type: CARuleType, temperature: Option<f64>) -> CARule {
+        let mut rule = CARule {
+            name: String::new(),
+            rule_type,
+            temperature,
+        };
+        rule.name = rule.canonical_name();
+        rule
+    }
+}
+
+#[cfg(feature = "search")]
+impl fmt::Display for CARuleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |counts: &[usize]| {
+            let mut counts = counts.to_vec();
+            counts.sort_unstable();
+            counts.dedup();
+            (counts.iter().map(ToString::to_string))
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+
+        match self {
+            CARuleType::Standard { birth, survival } => {
+                write!(f, "B{}_S{}", join(birth), join(survival))
+            }
+            CARuleType::Threshold(threshold) => write!(f, "T{threshold}"),
+            CARuleType::Erosion(exposed) => write!(f, "E{exposed}"),
         }
+    }
+}
 
-        (min, max, sum as f64 / count as f64)
+#[cfg(feature = "search")]
+impl FromStr for CARuleType {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseRuleError {
+            input: s.to_string(),
+        };
+        let counts = |list: &str| -> Result<Vec<usize>, ParseRuleError> {
+            if list.is_empty() {
+                return Ok(Vec::new());
+            }
+            list.split('.')
+                .map(|count| count.parse().map_err(|_| err()))
+                .collect()
+        };
+
+        if let Some(threshold) = s.strip_prefix('T') {
+            return threshold
+                .parse()
+                .map(CARuleType::Threshold)
+                .map_err(|_| err());
+        }
+        if let Some(exposed) = s.strip_prefix('E') {
+            return exposed.parse().map(CARuleType::Erosion).map_err(|_| err());
+        }
+
+        let (birth, survival) = (s.strip_prefix('B'))
+            .and_then(|rest| rest.split_once("_S"))
+            .ok_or_else(err)?;
+        Ok(CARuleType::Standard {
+            birth: counts(birth)?,
+            survival: counts(survival)?,
+        })
     }
 }
 
-impl Index<usize> for CAContext {
-    type Output = CACell;
+#[cfg(feature = "search")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRuleError {
+    pub input: String,
+}
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.cells[index]
+#[cfg(feature = "search")]
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid rule {:?}, expected T<n>, E<n> or B<counts>_S<counts> with counts joined by '.'",
+            self.input
+        )
     }
 }
 
-impl IndexMut<usize> for CAContext {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.cells[index]
+#[cfg(feature = "search")]
+impl std::error::Error for ParseRuleError {}
+
+// How one side (birth or survival) of a rule sweep is enumerated
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CountSweep {
+    // A single fixed set of counts
+    Fixed(Vec<usize>),
+    // Every subset of from..=to whose size is listed in `sizes`
+    Subsets {
+        from: usize,
+        to: usize,
+        sizes: Vec<usize>,
+    },
+}
+
+impl CountSweep {
+    fn count(&self) -> usize {
+        match self {
+            CountSweep::Fixed(_) => 1,
+            CountSweep::Subsets { from, to, sizes } => {
+                let n = (to + 1).saturating_sub(*from);
+                sizes
+                    .iter()
+                    .fold(0usize, |acc, &k| acc.saturating_add(binomial(n, k)))
+            }
+        }
+    }
+
+    fn expand(&self) -> Vec<Vec<usize>> {
+        match self {
+            CountSweep::Fixed(counts) => vec![counts.clone()],
+            CountSweep::Subsets { from, to, sizes } => {
+                let pool: Vec<usize> = (*from..=*to).collect();
+                let mut sets = Vec::new();
+                for &k in sizes {
+                    subsets_of_size(&pool, k, &mut Vec::new(), &mut sets);
+                }
+                sets
+            }
+        }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct CAEngine {
-    pub config: CAConfig,
-    pub context: CAContext,
-    buffer: CAContext,
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k.min(n - k)).fold(1usize, |acc, i| acc.saturating_mul(n - i) / (i + 1))
 }
 
-impl CAEngine {
+fn subsets_of_size(pool: &[usize], k: usize, current: &mut Vec<usize>, out: &mut Vec<Vec<usize>>) {
+    if current.len() == k {
+        out.push(current.clone());
+        return;
+    }
+
+    for (i, &v) in pool.iter().enumerate() {
+        if pool.len() - i < k - current.len() {
+            break;
+        }
+        current.push(v);
+        subsets_of_size(&pool[i + 1..], k, current, out);
+        current.pop();
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RuleSweep {
+    pub birth: CountSweep,
+    pub survival: CountSweep,
+    #[serde(default = "RuleSweep::default_max_rules")]
+    pub max_rules: usize,
+}
+
+impl RuleSweep {
+    fn default_max_rules() -> usize {
+        1000
+    }
+
+    // Expands the sweep into one standard rule per birth/survival combination, named like
+    // B3-4_S2-3-4. Fails before generating anything if the combination count exceeds max_rules
+    pub fn expand(&self) -> Result<Vec<CARule>, RuleSweepError> {
+        let count = self.birth.count().saturating_mul(self.survival.count());
+        if count > self.max_rules {
+            return Err(RuleSweepError::TooManyRules {
+                count,
+                max: self.max_rules,
+            });
+        }
+
+        let survivals = self.survival.expand();
+        let mut rules = Vec::with_capacity(count);
+
+        for birth in self.birth.expand() {
+            for survival in &survivals {
+                rules.push(CARule {
+                    name: format!("B{}_S{}", join_counts(&birth), join_counts(survival)),
+                    rule_type: CARuleType::Standard {
+                        birth: birth.clone(),
+                        survival: survival.clone(),
+                    },
+                    temperature: None,
+                });
+            }
+        }
+
+        Ok(rules)
+    }
+}
+
+fn join_counts(counts: &[usize]) -> String {
+    counts
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[derive(Clone, Debug)]
+pub enum RuleSweepError {
+    TooManyRules { count: usize, max: usize },
+}
+
+impl fmt::Display for RuleSweepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleSweepError::TooManyRules { count, max } => {
+                write!(
+                    f,
+                    "rule sweep expands to {count} rules, more than the cap of {max}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleSweepError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleProblem {
+    // Birth or survival counts above the neighborhood size
+    UnreachableCounts {
+        kind: &'static str,
+        counts: Vec<usize>,
+    },
+    // No birth or survival counts at all
+    EmptySet {
+        kind: &'static str,
+    },
+    // Threshold 0 never keeps air, one above the neighborhood size never makes rock
+    ThresholdOutOfRange {
+        threshold: usize,
+    },
+    // Erosion needing 0 faces wipes out all rock, more faces than neighbors never erodes
+    ExposureOutOfRange {
+        exposed: usize,
+    },
+    // Erosion counts exposed faces, any other neighborhood counts something else
+    ErosionNeighborhood,
+}
+
+impl fmt::Display for RuleProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleProblem::UnreachableCounts { kind, counts } => {
+                write!(f, "{kind} counts {counts:?} are unreachable")
+            }
+            RuleProblem::EmptySet { kind } => write!(f, "{kind} set is empty"),
+            RuleProblem::ThresholdOutOfRange { threshold } => {
+                write!(f, "threshold {threshold} maps every cell to the same state")
+            }
+            RuleProblem::ExposureOutOfRange { exposed } => {
+                write!(
+                    f,
+                    "erosion at {exposed} exposed faces maps all rock to the same state"
+                )
+            }
+            RuleProblem::ErosionNeighborhood => {
+                write!(f, "erosion counts exposed faces and needs von_neumann")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RuleValidationError {
+    pub ruleset: String,
+    pub neighborhood: String,
+    pub neighbors: usize,
+    pub problems: Vec<RuleProblem>,
+}
+
+impl fmt::Display for RuleValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ruleset {} with {} ({} neighbors): ",
+            self.ruleset, self.neighborhood, self.neighbors
+        )?;
+
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuleValidationError {}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryMode {
+    // Cells outside the grid count as solid rock
+    #[default]
+    Solid,
+    // The axis wraps around, the last cell neighbors the first
+    Periodic,
+}
+
+impl BoundaryMode {
+    // Maps a possibly out-of-range coordinate back into 0..len, None if it falls off a solid edge
     #[must_use]
-    pub fn new(config: CAConfig, context: CAContext) -> Self {
-        let buffer = context.clone();
+    pub fn wrap(self, v: i32, len: usize) -> Option<usize> {
+        if v >= 0 && (v as usize) < len {
+            return Some(v as usize);
+        }
 
-        Self {
-            config,
-            context,
-            buffer,
+        match self {
+            BoundaryMode::Solid => None,
+            BoundaryMode::Periodic if len > 0 => Some(v.rem_euclid(len as i32) as usize),
+            BoundaryMode::Periodic => None,
         }
     }
+}
 
-    pub fn run(&mut self, iterations: usize, log: &mut Vec<String>) {
-        for iter in 0..iterations {
-            // LOGGING
-            let alive = self.context.total_air_cells();
-            let (min_n, max_n, mean_n) = self.context.neighbor_stats(&self.config.neighborhood);
+// Per-axis edge handling for neighbor lookups
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Boundary {
+    pub x: BoundaryMode,
+    pub y: BoundaryMode,
+    pub z: BoundaryMode,
+}
 
-            log.push(format!(
-                "iter={iter} alive={alive} min_n={min_n} max_n={max_n} mean_n={mean_n:.2}"
-            ));
+impl Boundary {
+    pub const PRESETS: [&'static str; 8] = [
+        "solid", "periodic", "tube_x", "tube_y", "tube_z", "slab_x", "slab_y", "slab_z",
+    ];
 
-            self.run_iteration();
+    #[must_use]
+    pub fn new(x: BoundaryMode, y: BoundaryMode, z: BoundaryMode) -> Self {
+        Self { x, y, z }
+    }
+
+    // "tube_a" wraps only axis a, "slab_a" wraps the other two and caps a with rock
+    #[must_use]
+    pub fn preset(name: &str) -> Option<Self> {
+        use BoundaryMode::{Periodic, Solid};
+
+        let boundary = match name {
+            "solid" => Self::new(Solid, Solid, Solid),
+            "periodic" => Self::new(Periodic, Periodic, Periodic),
+            "tube_x" => Self::new(Periodic, Solid, Solid),
+            "tube_y" => Self::new(Solid, Periodic, Solid),
+            "tube_z" => Self::new(Solid, Solid, Periodic),
+            "slab_x" => Self::new(Solid, Periodic, Periodic),
+            "slab_y" => Self::new(Periodic, Solid, Periodic),
+            "slab_z" => Self::new(Periodic, Periodic, Solid),
+            _ => return None,
+        };
+
+        Some(boundary)
+    }
+
+    // Preset name when one matches, otherwise the per-axis modes
+    #[must_use]
+    pub fn name(&self) -> String {
+        if let Some(preset) = Self::PRESETS
+            .iter()
+            .find(|&&preset| Self::preset(preset) == Some(*self))
+        {
+            return (*preset).to_string();
         }
 
-        let alive = self.context.total_air_cells();
-        let (min_n, max_n, mean_n) = self.context.neighbor_stats(&self.config.neighborhood);
+        let mode = |m: BoundaryMode| match m {
+            BoundaryMode::Solid => "s",
+            BoundaryMode::Periodic => "p",
+        };
+        format!("x{}y{}z{}", mode(self.x), mode(self.y), mode(self.z))
+    }
 
-        log.push(format!(
-            "iter={iterations} alive={alive} min_n={min_n} max_n={max_n} mean_n={mean_n:.2}"
-        ));
+    // The same boundary with `axis` capped by rock
+    #[must_use]
+    fn with_solid(mut self, axis: Axis) -> Self {
+        match axis {
+            Axis::X => self.x = BoundaryMode::Solid,
+            Axis::Y => self.y = BoundaryMode::Solid,
+            Axis::Z => self.z = BoundaryMode::Solid,
+        }
+        self
     }
 
-    pub fn run_iteration(&mut self) {
-        let nb = &self.config.neighborhood;
-        let rule = &self.config.rule;
+    #[must_use]
+    pub fn is_periodic(&self, axis: Axis) -> bool {
+        let mode = match axis {
+            Axis::X => self.x,
+            Axis::Y => self.y,
+            Axis::Z => self.z,
+        };
+        mode == BoundaryMode::Periodic
+    }
+}
 
-        // SAFELY split mutable borrows
-        let (old, new) = (&self.context, &mut self.buffer);
+// Axis-aligned box of cells, min inclusive and max exclusive on every axis
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Region {
+    pub min: (usize, usize, usize),
+    pub max: (usize, usize, usize),
+}
 
-        new.cells_mut()
-            //.iter_mut()
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(i, cell)| {
-                let (x, y, z) = old.pos(i);
+impl Region {
+    #[must_use]
+    pub fn new(min: (usize, usize, usize), max: (usize, usize, usize)) -> Self {
+        Self { min, max }
+    }
 
-                let alive_neighbors = old.count_air_neighbors(x, y, z, nb);
-                let alive = old[i].is_air();
+    // Checks the box is non-empty and fits inside a width x height x depth grid
+    pub fn validate(&self, width: usize, height: usize, depth: usize) -> Result<(), RegionError> {
+        let axes = [
+            (Axis::X, self.min.0, self.max.0, width),
+            (Axis::Y, self.min.1, self.max.1, height),
+            (Axis::Z, self.min.2, self.max.2, depth),
+        ];
 
-                let next = match &rule.rule_type {
-                    CARuleType::Standard { birth, survival } => {
-                        if alive {
-                            survival.contains(&alive_neighbors)
-                        } else {
-                            birth.contains(&alive_neighbors)
-                        }
-                    }
-                    CARuleType::Threshold(threshold) => alive_neighbors < *threshold,
-                };
+        for (axis, min, max, len) in axes {
+            if min >= max {
+                return Err(RegionError::Empty { axis, min, max });
+            }
+            if max > len {
+                return Err(RegionError::OutOfBounds { axis, max, len });
+            }
+        }
 
-                cell.set_state(u8::from(next));
-            });
+        Ok(())
+    }
 
-        // Swap buffers — O(1)
-        std::mem::swap(&mut self.context, &mut self.buffer);
+    // Cells in both boxes, empty along an axis where they don't overlap
+    #[must_use]
+    pub fn intersect(&self, other: &Region) -> Region {
+        Region::new(
+            (
+                self.min.0.max(other.min.0),
+                self.min.1.max(other.min.1),
+                self.min.2.max(other.min.2),
+            ),
+            (
+                self.max.0.min(other.max.0),
+                self.max.1.min(other.max.1),
+                self.max.2.min(other.max.2),
+            ),
+        )
+    }
+
+    #[must_use]
+    pub fn contains(&self, x: usize, y: usize, z: usize) -> bool {
+        (self.min.0..self.max.0).contains(&x)
+            && (self.min.1..self.max.1).contains(&y)
+            && (self.min.2..self.max.2).contains(&z)
+    }
+}
+
+// Which cell states count as alive, both for neighbor counts and for a cell's own state
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CountStates {
+    // States at or above the threshold count, 1 is the binary "non-zero is air" default
+    Threshold(u8),
+    // Only the listed states count
+    States(Vec<u8>),
+}
+
+impl Default for CountStates {
+    fn default() -> Self {
+        CountStates::Threshold(1)
+    }
+}
+
+impl CountStates {
+    #[must_use]
+    pub fn counts(&self, cell: CACell) -> bool {
+        match self {
+            CountStates::Threshold(threshold) => cell.0 >= *threshold,
+            CountStates::States(states) => states.contains(&cell.0),
+        }
+    }
+
+    // Lookup table indexed by state, cheaper than matching per neighbor in the update loops
+    #[must_use]
+    pub fn mask(&self) -> [bool; 256] {
+        std::array::from_fn(|state| self.counts(CACell::new(state as u8)))
+    }
+}
+
+// Fractal Perlin noise for CAContext::from_noise. Coordinates are normalized to
+// [0, frequency] along every axis, every further octave doubles the frequency at half the
+// amplitude
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NoiseParams {
+    #[serde(alias = "scale")]
+    pub frequency: f64,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    // Fixed cutoff in [-1, 1], None derives it from the air fraction
+    #[serde(default)]
+    pub threshold: Option<f64>,
+}
+
+fn default_octaves() -> u32 {
+    1
+}
+
+impl NoiseParams {
+    // Noise at a point in [0, 1]^3, normalized back to roughly [-1, 1] over all octaves
+    fn sample(&self, perlin: &Perlin, point: [f64; 3]) -> f64 {
+        let mut value = 0.0;
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+
+        for _ in 0..self.octaves.max(1) {
+            value += amplitude * perlin.get(point.map(|c| c * frequency));
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        value / total
+    }
+}
+
+// A box with its own initial air probability, see CAContext::from_regions
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DensityRegion {
+    #[serde(flatten)]
+    pub region: Region,
+    pub air_prob: f64,
+}
+
+// A horizontal band of CAContext::random_layers from z_from down to z_to, exclusive. A frozen
+// layer keeps its initial cells, the evolved region shrinks to the layers that aren't frozen
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Layer {
+    pub z_from: f64,
+    pub z_to: f64,
+    pub air_prob: f64,
+    #[serde(default)]
+    pub frozen: bool,
+    // Fraction bounds are fractions of the grid depth
+    #[serde(default)]
+    pub units: StampUnits,
+}
+
+impl Layer {
+    // The slices the layer covers in a grid `depth` cells deep, fractions are rounded to the
+    // nearest slice boundary
+    #[must_use]
+    pub fn z_range(&self, depth: usize) -> Range<usize> {
+        let scale = match self.units {
+            StampUnits::Cells => 1.0,
+            StampUnits::Fraction => depth as f64,
+        };
+        let slice = |z: f64| (z * scale).round().max(0.0) as usize;
+        slice(self.z_from)..slice(self.z_to)
+    }
+
+    // Checks the layers stack into the whole depth without gaps or overlaps, in any order.
+    // Frozen layers have to sit at the top or bottom so the evolved cells stay a single box
+    pub fn check_stack(layers: &[Layer], depth: usize) -> Result<(), LayerError> {
+        let mut ranges: Vec<_> = layers
+            .iter()
+            .map(|l| (l.z_range(depth), l.frozen))
+            .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
+
+        let mut z = 0;
+        for (range, _) in &ranges {
+            if range.is_empty() {
+                return Err(LayerError::Empty(range.clone()));
+            }
+            match range.start.cmp(&z) {
+                Ordering::Greater => return Err(LayerError::Gap(z..range.start)),
+                Ordering::Less => return Err(LayerError::Overlap(range.start..z)),
+                Ordering::Equal => z = range.end,
+            }
+        }
+        if z != depth {
+            return Err(LayerError::Coverage { end: z, depth });
+        }
+
+        let live: Vec<bool> = ranges.iter().map(|(_, frozen)| !frozen).collect();
+        let first = live.iter().position(|&l| l).ok_or(LayerError::AllFrozen)?;
+        let last = live.iter().rposition(|&l| l).unwrap_or(first);
+        if let Some(i) = (first..=last).find(|&i| !live[i]) {
+            return Err(LayerError::FrozenBetween(ranges[i].0.clone()));
+        }
+        Ok(())
+    }
+
+    // Slices from the top of the first layer that isn't frozen to the bottom of the last one,
+    // None without frozen layers
+    #[must_use]
+    pub fn live_slices(layers: &[Layer], depth: usize) -> Option<Range<usize>> {
+        if !layers.iter().any(|l| l.frozen) {
+            return None;
+        }
+        let live = || {
+            layers
+                .iter()
+                .filter(|l| !l.frozen)
+                .map(|l| l.z_range(depth))
+        };
+        let start = live().map(|r| r.start).min().unwrap_or(0);
+        let end = live().map(|r| r.end).max().unwrap_or(0);
+        Some(start..end.max(start))
+    }
+}
+
+// Solid border of the initial grid, in cells per face. z = 0 is the top face
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShellSpec {
+    pub min_x: usize,
+    pub max_x: usize,
+    pub min_y: usize,
+    pub max_y: usize,
+    pub min_z: usize,
+    pub max_z: usize,
+    // Only the interior evolves, so the CA cannot erode the shell
+    pub frozen: bool,
+}
+
+impl ShellSpec {
+    #[must_use]
+    pub fn uniform(thickness: usize) -> Self {
+        Self {
+            min_x: thickness,
+            max_x: thickness,
+            min_y: thickness,
+            max_y: thickness,
+            min_z: thickness,
+            max_z: thickness,
+            frozen: false,
+        }
+    }
+
+    #[must_use]
+    pub fn thickness(&self, face: Face) -> usize {
+        match face {
+            Face::MinX => self.min_x,
+            Face::MaxX => self.max_x,
+            Face::MinY => self.min_y,
+            Face::MaxY => self.max_y,
+            Face::MinZ => self.min_z,
+            Face::MaxZ => self.max_z,
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        [
+            self.min_x, self.max_x, self.min_y, self.max_y, self.min_z, self.max_z,
+        ] == [0; 6]
+    }
+
+    // Cells inside the shell of a width x height x depth grid, empty when the shell fills it
+    #[must_use]
+    pub fn interior(&self, width: usize, height: usize, depth: usize) -> Region {
+        Region::new(
+            (self.min_x, self.min_y, self.min_z),
+            (
+                width.saturating_sub(self.max_x),
+                height.saturating_sub(self.max_y),
+                depth.saturating_sub(self.max_z),
+            ),
+        )
+    }
+}
+
+// Drunkard's walk tunnels for CAContext::carve_walks. Walks start at `starts` in turn, or at
+// random cells when it is empty. A horizontal bias of 0 picks all six directions equally, 1
+// never steps along z
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WalkParams {
+    pub n_walks: usize,
+    pub walk_len: usize,
+    // Cells within this distance of every step are carved, 0 carves the step cell alone
+    #[serde(default)]
+    pub step_radius: f64,
+    #[serde(default)]
+    pub horizontal_bias: f64,
+    #[serde(default)]
+    pub starts: Vec<(usize, usize, usize)>,
+    // Independent air probability of every cell before the walks
+    #[serde(default)]
+    pub background_air_prob: f64,
+}
+
+// Vertical air shafts of CAContext::carve_entrances, opened from the top face (z = 0) down
+// `depth` slices. Frozen shafts are held open while the rest of the grid evolves
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct EntranceParams {
+    pub count: usize,
+    // Cells within this horizontal distance of the shaft axis are carved
+    pub radius: usize,
+    pub depth: usize,
+    #[serde(default)]
+    pub frozen: bool,
+}
+
+impl EntranceParams {
+    // Whether a shaft fits between the side faces of a width x height x depth grid
+    #[must_use]
+    pub fn fits(&self, width: usize, height: usize, depth: usize) -> bool {
+        2 * self.radius < width && 2 * self.radius < height && self.depth <= depth
+    }
+
+    // Shaft axes drawn from `seed`. Axes closer than `radius` to a side face are redrawn, so
+    // every shaft lies inside the grid. Empty when no shaft fits
+    #[must_use]
+    pub fn positions(&self, width: usize, height: usize, seed: u64) -> Vec<(usize, usize)> {
+        if 2 * self.radius >= width || 2 * self.radius >= height {
+            return Vec::new();
+        }
+        let r = self.radius;
+        let mut rng = CellRng::new(seed, ENTRANCE_RNG_ITERATION, 0);
+        let mut positions = Vec::with_capacity(self.count);
+        while positions.len() < self.count {
+            let x = (rng.next_u64() % width as u64) as usize;
+            let y = (rng.next_u64() % height as u64) as usize;
+            if x >= r && y >= r && x + r < width && y + r < height {
+                positions.push((x, y));
+            }
+        }
+        positions
+    }
+}
+
+// A hand-authored starting grid. The file must span the configured grid, see
+// CAContext::from_vox_sized for how it is placed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VoxInit {
+    pub path: PathBuf,
+    // State of the cells the file holds a voxel for, every other cell gets the other state
+    #[serde(default)]
+    pub voxels: VoxelState,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VoxelState {
+    // Matches grid.vox, which draws the rock
+    #[default]
+    Solid,
+    Air,
+}
+
+impl VoxInit {
+    pub fn load(
+        &self,
+        width: usize,
+        height: usize,
+        depth: usize,
+    ) -> Result<CAContext, VoxLoadError> {
+        CAContext::from_vox_sized(&self.path, width, height, depth, self.voxels)
+    }
+}
+
+// A shape stamped onto the initial grid with CAContext::stamp. Boxes are min inclusive and
+// max exclusive like Region, spheres and ellipsoids hold the cells within the radius of the
+// center cell
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StampShape {
+    Sphere { center: [f64; 3], radius: f64 },
+    Box { min: [f64; 3], max: [f64; 3] },
+    Ellipsoid { center: [f64; 3], radii: [f64; 3] },
+}
+
+impl StampShape {
+    // Corners of a box enclosing the shape, no cell outside them is contained
+    #[must_use]
+    pub fn bounds(&self) -> ([f64; 3], [f64; 3]) {
+        match *self {
+            StampShape::Sphere { center, radius } => {
+                (center.map(|c| c - radius), center.map(|c| c + radius))
+            }
+            StampShape::Box { min, max } => (min, max),
+            StampShape::Ellipsoid { center, radii } => (
+                std::array::from_fn(|a| center[a] - radii[a]),
+                std::array::from_fn(|a| center[a] + radii[a]),
+            ),
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, p: [f64; 3]) -> bool {
+        match *self {
+            StampShape::Sphere { center, radius } => {
+                (0..3).map(|a| (p[a] - center[a]).powi(2)).sum::<f64>() <= radius * radius
+            }
+            StampShape::Box { min, max } => (0..3).all(|a| min[a] <= p[a] && p[a] < max[a]),
+            StampShape::Ellipsoid { center, radii } => {
+                (0..3)
+                    .map(|a| ((p[a] - center[a]) / radii[a]).powi(2))
+                    .sum::<f64>()
+                    <= 1.0
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StampUnits {
+    #[default]
+    Cells,
+    // Fractions of the grid size per axis, a sphere radius is a fraction of the smallest side
+    Fraction,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Stamp {
+    #[serde(flatten)]
+    pub shape: StampShape,
+    #[serde(default)]
+    pub units: StampUnits,
+}
+
+impl Stamp {
+    // The shape in cells for a width x height x depth grid
+    #[must_use]
+    pub fn in_cells(&self, width: usize, height: usize, depth: usize) -> StampShape {
+        if self.units == StampUnits::Cells {
+            return self.shape;
+        }
+
+        let size = [width, height, depth].map(|len| len as f64);
+        let scale = |v: [f64; 3]| std::array::from_fn(|axis| v[axis] * size[axis]);
+        match self.shape {
+            StampShape::Sphere { center, radius } => StampShape::Sphere {
+                center: scale(center),
+                radius: radius * size.into_iter().fold(f64::INFINITY, f64::min),
+            },
+            StampShape::Box { min, max } => StampShape::Box {
+                min: scale(min),
+                max: scale(max),
+            },
+            StampShape::Ellipsoid { center, radii } => StampShape::Ellipsoid {
+                center: scale(center),
+                radii: scale(radii),
+            },
+        }
+    }
+
+    // Negative or non-finite sizes, and ellipsoids with a zero radius
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        match self.shape {
+            StampShape::Sphere { center, radius } => {
+                center.iter().all(|c| c.is_finite()) && radius.is_finite() && radius >= 0.0
+            }
+            StampShape::Box { min, max } => (0..3).all(|axis| {
+                min[axis].is_finite() && max[axis].is_finite() && min[axis] <= max[axis]
+            }),
+            StampShape::Ellipsoid { center, radii } => (0..3).all(|axis| {
+                center[axis].is_finite() && radii[axis].is_finite() && radii[axis] > 0.0
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CAConfig {
+    pub neighborhood: CANeighborhood,
+    pub rule: CARule,
+    #[serde(default)]
+    pub boundary: Boundary,
+    #[serde(default)]
+    pub count_states: CountStates,
+    // Adds the cell's own state to its neighbor count before the rule reads it
+    #[serde(default)]
+    pub include_self: bool,
+}
+
+impl CAConfig {
+    // The count the rule sees for a cell with `alive_neighbors` counted neighbors
+    #[must_use]
+    pub fn rule_count(&self, alive: bool, alive_neighbors: usize) -> usize {
+        alive_neighbors + usize::from(self.include_self && alive)
+    }
+}
+
+/// Arbitrary transition logic for [`CAEngine::run_iteration_with`]. Every cell reads the
+/// previous generation through `ctx` and returns its next state.
+///
+/// A "drip" rule where a cell becomes air if the cell directly above it is air:
+///
+/// ```
+/// use gradwork_ca::ca::{CACell, CAConfig, CAContext, CAEngine, CANeighborhood, CARule, CARuleType, TransitionRule};
+///
+/// struct Drip;
+///
+/// impl TransitionRule for Drip {
+///     fn next(&self, ctx: &CAContext, x: usize, y: usize, z: usize, current: CACell) -> CACell {
+///         if z + 1 < ctx.depth() && ctx.get(x, y, z + 1).is_air() {
+///             CACell::new(1)
+///         } else {
+///             current
+///         }
+///     }
+/// }
+///
+/// let mut ctx = CAContext::new(1, 1, 4);
+/// ctx.set(0, 0, 3, CACell::new(1));
+///
+/// let config = CAConfig {
+///     neighborhood: CANeighborhood::von_neumann(),
+///     rule: CARule { name: "T3".to_string(), rule_type: CARuleType::Threshold(3), temperature: None },
+///     boundary: Default::default(),
+///     count_states: Default::default(),
+///     include_self: false,
+/// };
+/// let mut engine = CAEngine::new(config, ctx);
+/// engine.run_iteration_with(&Drip);
+/// engine.run_iteration_with(&Drip);
+///
+/// assert_eq!(engine.context().total_air_cells(), 3);
+/// ```
+pub trait TransitionRule: Send + Sync {
+    fn next(&self, ctx: &CAContext, x: usize, y: usize, z: usize, current: CACell) -> CACell;
+}
+
+// Always the crisp rule, soft rules need the engine's seed and iteration to draw from
+impl TransitionRule for CAConfig {
+    fn next(&self, ctx: &CAContext, x: usize, y: usize, z: usize, current: CACell) -> CACell {
+        let counted = |cell: CACell| self.count_states.counts(cell);
+        let alive_neighbors =
+            ctx.count_neighbors_where(x, y, z, &self.neighborhood, &self.boundary, counted);
+        let alive = counted(current);
+        CACell::new(u8::from(
+            self.rule
+                .next_state(alive, self.rule_count(alive, alive_neighbors)),
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IterationStats {
+    pub iter: usize,
+    pub alive: usize,
+    // Cells flipped by the iteration that produced this grid, 0 for the initial grid
+    pub changed: usize,
+    pub min_neighbors: usize,
+    pub max_neighbors: usize,
+    pub mean_neighbors: f64,
+}
+
+impl fmt::Display for IterationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "iter={} alive={} changed={} min_n={} max_n={} mean_n={:.2}",
+            self.iter,
+            self.alive,
+            self.changed,
+            self.min_neighbors,
+            self.max_neighbors,
+            self.mean_neighbors
+        )
+    }
+}
+
+// Equal and hashed by dimensions and cell states, usable for grid state deduplication
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct CAContext {
+    width: usize,
+    height: usize,
+    depth: usize,
+    cells: Vec<CACell>,
+}
+
+impl CAContext {
+    #[must_use]
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        let cells = vec![CACell::new(0); width * height * depth];
+        Self {
+            width,
+            height,
+            depth,
+            cells,
+        }
+    }
+
+    #[must_use]
+    pub fn random(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_percentage: f64,
+    ) -> Self {
+        let n = width * height * depth;
+        let air_cells = (n as f64 * air_percentage).round() as usize;
+        Self::with_air_count(width, height, depth, seed, air_cells)
+    }
+
+    // Random fill that is invariant under `symmetry`. Every cell copies the cell with the lowest
+    // index among its images, so only that fundamental domain is drawn. On odd sizes the middle
+    // plane is its own image and stays random. The air fraction holds in expectation only.
+    // Evolution keeps the symmetry exactly as long as the boundary, neighborhood and rule are
+    // symmetric too, e.g. a solid boundary with a stock neighborhood and a deterministic rule
+    #[must_use]
+    pub fn random_symmetric(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_percentage: f64,
+        symmetry: Symmetry,
+    ) -> Self {
+        let base = Self::random(width, height, depth, seed, air_percentage);
+        let mut ctx = Self::new(width, height, depth);
+        ctx.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let (x, y, z) = base.pos(i);
+            let source = (symmetry.images(x, y, width, height).into_iter())
+                .map(|(ix, iy)| base.idx(ix, iy, z))
+                .fold(i, usize::min);
+            *cell = base.cells[source];
+        });
+        ctx
+    }
+
+    // True when the grid equals its own image under `symmetry`
+    #[must_use]
+    pub fn is_symmetric(&self, symmetry: Symmetry) -> bool {
+        (0..self.cells.len()).into_par_iter().all(|i| {
+            let (x, y, z) = self.pos(i);
+            (symmetry.images(x, y, self.width, self.height).into_iter())
+                .all(|(ix, iy)| self.cells[self.idx(ix, iy, z)] == self.cells[i])
+        })
+    }
+
+    // Random fill of the cells inside the shell, which stays solid. The air percentage applies
+    // to the interior alone. An empty shell gives the same grid as CAContext::random
+    #[must_use]
+    pub fn random_with_shell(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_percentage: f64,
+        shell: &ShellSpec,
+    ) -> Self {
+        let interior = shell.interior(width, height, depth);
+        let (x0, y0, z0) = interior.min;
+        let [iw, ih, id] = [
+            interior.max.0.saturating_sub(x0),
+            interior.max.1.saturating_sub(y0),
+            interior.max.2.saturating_sub(z0),
+        ];
+        if iw * ih * id == 0 {
+            return Self::new(width, height, depth);
+        }
+        let filled = Self::random(iw, ih, id, seed, air_percentage);
+
+        let mut ctx = Self::new(width, height, depth);
+        for z in 0..id {
+            for y in 0..ih {
+                let src = filled.idx(0, y, z);
+                let dst = ctx.idx(x0, y0 + y, z0 + z);
+                ctx.cells[dst..dst + iw].copy_from_slice(&filled.cells[src..src + iw]);
+            }
+        }
+
+        ctx
+    }
+
+    // Copy of the cells inside `region`, which must fit inside the grid
+    #[must_use]
+    pub fn crop(&self, region: &Region) -> Self {
+        let (x0, y0, z0) = region.min;
+        let [w, h, d] = [region.max.0 - x0, region.max.1 - y0, region.max.2 - z0];
+
+        let mut ctx = Self::new(w, h, d);
+        for z in 0..d {
+            for y in 0..h {
+                let src = self.idx(x0, y0 + y, z0 + z);
+                let dst = ctx.idx(0, y, z);
+                ctx.cells[dst..dst + w].copy_from_slice(&self.cells[src..src + w]);
+            }
+        }
+        ctx
+    }
+
+    // Turns every cell outside the shell's interior solid
+    pub fn apply_shell(&mut self, shell: &ShellSpec) {
+        let interior = shell.interior(self.width, self.height, self.depth);
+        let (width, height) = (self.width, self.height);
+
+        self.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let (x, y, z) = (i % width, (i / width) % height, i / (width * height));
+            if !interior.contains(x, y, z) {
+                cell.set_state(0);
+            }
+        });
+    }
+
+    // Sets every cell of the shape to `state`, parts outside the grid are clipped. Returns the
+    // number of cells inside the shape
+    pub fn stamp(&mut self, stamp: &Stamp, state: CACell) -> usize {
+        self.stamp_within(stamp, state, None)
+    }
+
+    // stamp limited to `region`, so the cells an engine holds there keep their state. Returns
+    // the number of cells set
+    pub fn stamp_within(&mut self, stamp: &Stamp, state: CACell, region: Option<Region>) -> usize {
+        let shape = stamp.in_cells(self.width, self.height, self.depth);
+        let (lo, hi) = shape.bounds();
+
+        // Cell range of the bounding box clipped to the grid, empty when it misses the grid
+        let sizes = [self.width, self.height, self.depth];
+        let range = |axis: usize| {
+            let start = lo[axis].ceil().max(0.0) as usize;
+            let end = (hi[axis].floor() + 1.0).clamp(0.0, sizes[axis] as f64) as usize;
+            start..end
+        };
+
+        let mut stamped = 0;
+        for z in range(2) {
+            for y in range(1) {
+                for x in range(0) {
+                    if shape.contains([x as f64, y as f64, z as f64])
+                        && region.is_none_or(|r| r.contains(x, y, z))
+                    {
+                        self.set(x, y, z, state);
+                        stamped += 1;
+                    }
+                }
+            }
+        }
+
+        stamped
+    }
+
+    // Background noise at `background_air_prob` with the walks carved through it
+    #[must_use]
+    pub fn from_walks(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        params: &WalkParams,
+    ) -> Self {
+        let points = [(0.0, params.background_air_prob)];
+        let mut ctx = Self::random_gradient(width, height, depth, seed, &points);
+        ctx.carve_walks(seed, params);
+        ctx
+    }
+
+    // Carves every walk of `params` into the grid. Steps that would leave the grid stay put.
+    // Walk w draws from CellRng(seed, WALK_RNG_ITERATION, w), a stream the cell fills and the
+    // engine never use. Returns the number of cells turned into air
+    pub fn carve_walks(&mut self, seed: u64, params: &WalkParams) -> usize {
+        if self.cells.is_empty() {
+            return 0;
+        }
+
+        let air_before = self.total_air_cells();
+        let vertical = (1.0 - params.horizontal_bias.clamp(0.0, 1.0)) / 3.0;
+        let brush = |(x, y, z): (usize, usize, usize)| Stamp {
+            shape: StampShape::Sphere {
+                center: [x as f64, y as f64, z as f64],
+                radius: params.step_radius,
+            },
+            units: StampUnits::Cells,
+        };
+
+        for walk in 0..params.n_walks {
+            let mut rng = CellRng::new(seed, WALK_RNG_ITERATION, walk as u64);
+            let mut pos = match params.starts.get(walk % params.starts.len().max(1)) {
+                Some(&start) => start,
+                None => self.pos((rng.next_u64() % self.cells.len() as u64) as usize),
+            };
+            self.stamp(&brush(pos), CACell::new(1));
+
+            for _ in 0..params.walk_len {
+                let r = rng.next_f64();
+                let (dx, dy, dz) = if r < vertical {
+                    FACE_OFFSETS[4 + usize::from(r < vertical / 2.0)]
+                } else {
+                    let horizontal = (r - vertical) / (1.0 - vertical);
+                    FACE_OFFSETS[((horizontal * 4.0) as usize).min(3)]
+                };
+
+                let (x, y, z) = (pos.0 as i32 + dx, pos.1 as i32 + dy, pos.2 as i32 + dz);
+                if x >= 0
+                    && y >= 0
+                    && z >= 0
+                    && (x as usize) < self.width
+                    && (y as usize) < self.height
+                    && (z as usize) < self.depth
+                {
+                    pos = (x as usize, y as usize, z as usize);
+                }
+                self.stamp(&brush(pos), CACell::new(1));
+            }
+        }
+
+        self.total_air_cells() - air_before
+    }
+
+    // Turns the shafts of `params` around `positions` into air. Returns a mask of the carved
+    // cells, which CAEngine::set_frozen takes for frozen entrances
+    pub fn carve_entrances(
+        &mut self,
+        params: &EntranceParams,
+        positions: &[(usize, usize)],
+    ) -> Vec<bool> {
+        let mut carved = vec![false; self.cells.len()];
+        let r = params.radius;
+        for &(cx, cy) in positions {
+            let xs = cx.saturating_sub(r)..(cx + r + 1).min(self.width);
+            let ys = cy.saturating_sub(r)..(cy + r + 1).min(self.height);
+            for z in 0..params.depth.min(self.depth) {
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        if x.abs_diff(cx).pow(2) + y.abs_diff(cy).pow(2) <= r * r {
+                            self.set(x, y, z, CACell::new(1));
+                            carved[self.idx(x, y, z)] = true;
+                        }
+                    }
+                }
+            }
+        }
+        carved
+    }
+
+    // Cells the .vox file holds a voxel for get the `voxels` state, every other cell the
+    // opposite one. The grid covers the boxes of all models, so a single MagicaVoxel model
+    // keeps its size
+    pub fn from_vox(path: &Path, voxels: VoxelState) -> Result<Self, VoxLoadError> {
+        let models = crate::data::read_vox_models(path).map_err(VoxLoadError::Io)?;
+        let (min, max) = vox_bounds(&models).ok_or(VoxLoadError::Empty)?;
+        let size = [0, 1, 2].map(|axis| (max[axis] - min[axis] + 1) as usize);
+        Ok(Self::place_vox(&models, min, size, voxels))
+    }
+
+    // Like from_vox, but the grid is width x height x depth from the corner of the model boxes.
+    // Files written by this crate split the grid into 126-cell models that overhang it when the
+    // size is not a multiple of 126, so larger boxes load as long as every voxel lies inside
+    pub fn from_vox_sized(
+        path: &Path,
+        width: usize,
+        height: usize,
+        depth: usize,
+        voxels: VoxelState,
+    ) -> Result<Self, VoxLoadError> {
+        let models = crate::data::read_vox_models(path).map_err(VoxLoadError::Io)?;
+        let (min, max) = vox_bounds(&models).ok_or(VoxLoadError::Empty)?;
+        let found = [0, 1, 2].map(|axis| (max[axis] - min[axis] + 1) as usize);
+        let size = [width, height, depth];
+
+        let inside = |p: [i32; 3]| (0..3).all(|axis| ((p[axis] - min[axis]) as usize) < size[axis]);
+        let fits = (0..3).all(|axis| found[axis] >= size[axis])
+            && (models.iter()).all(|model| model.positions().all(inside));
+        if !fits {
+            return Err(VoxLoadError::Size(DimensionMismatch {
+                expected: (width, height, depth),
+                found: (found[0], found[1], found[2]),
+            }));
+        }
+
+        Ok(Self::place_vox(&models, min, size, voxels))
+    }
+
+    // A `size` grid with the voxels of `models` placed relative to `min`
+    fn place_vox(
+        models: &[crate::data::VoxModel],
+        min: [i32; 3],
+        size: [usize; 3],
+        voxels: VoxelState,
+    ) -> Self {
+        let state = u8::from(voxels == VoxelState::Air);
+        let mut ctx = Self::new(size[0], size[1], size[2]);
+        ctx.cells.fill(CACell::new(1 - state));
+        for p in models.iter().flat_map(crate::data::VoxModel::positions) {
+            let pos = [0, 1, 2].map(|axis| (p[axis] - min[axis]) as usize);
+            ctx.set(pos[0], pos[1], pos[2], CACell::new(state));
+        }
+        ctx
+    }
+
+    // Exactly `n_points` random air cells in solid rock, something for growth rules to start from
+    #[must_use]
+    pub fn random_points(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        n_points: usize,
+    ) -> Self {
+        let n = width * height * depth;
+        Self::with_air_count(width, height, depth, seed, n_points.min(n))
+    }
+
+    // Air wherever fractal Perlin noise exceeds the threshold. A fixed threshold decides alone
+    // and `air_fraction` is ignored, otherwise the cutoff is the noise quantile that makes about
+    // `air_fraction` of the cells air
+    #[must_use]
+    pub fn from_noise(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_fraction: f64,
+        params: &NoiseParams,
+    ) -> Self {
+        let perlin = Perlin::new((seed ^ (seed >> 32)) as u32);
+        let dims = (width as f64, height as f64, depth as f64);
+        let n = width * height * depth;
+
+        // Noise values as keys that sort like the floats, so the quantile goes through the same
+        // histogram selection as the random fill
+        let keys: Vec<u64> = (0..n)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y, z) = (i % width, (i / width) % height, i / (width * height));
+                let point = [x as f64 / dims.0, y as f64 / dims.1, z as f64 / dims.2];
+                float_key(params.sample(&perlin, point))
+            })
+            .collect();
+
+        // Cells at or above the cutoff become air. A derived cutoff is the value of the
+        // air_cells-th largest cell, ties with it included
+        let cutoff = match params.threshold {
+            Some(threshold) => Some(float_key(threshold.next_up())),
+            None => {
+                let air_cells = (n as f64 * air_fraction.clamp(0.0, 1.0)).round() as usize;
+                (air_cells > 0).then(|| nth_smallest_key(&keys, n - air_cells))
+            }
+        };
+
+        Self {
+            width,
+            height,
+            depth,
+            cells: (keys.par_iter())
+                .map(|&k| CACell::new(u8::from(cutoff.is_some_and(|c| k >= c))))
+                .collect(),
+        }
+    }
+
+    // Every cell is air with a probability interpolated linearly from `prob_top` at z = 0 to
+    // `prob_bottom` at z = depth - 1, for strata that open up with depth
+    #[must_use]
+    pub fn from_gradient(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        prob_top: f64,
+        prob_bottom: f64,
+    ) -> Self {
+        let points = [(0.0, prob_top), (1.0, prob_bottom)];
+        Self::random_gradient(width, height, depth, seed, &points)
+    }
+
+    // Piecewise linear air probability over depth. `points` are (z fraction, probability)
+    // control points in any order, z fraction 0 is the top slice and 1 the bottom one. Slices
+    // above the first or below the last point take that point's probability
+    #[must_use]
+    pub fn random_gradient(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        points: &[(f64, f64)],
+    ) -> Self {
+        let mut points = points.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let span = depth.saturating_sub(1).max(1) as f64;
+        let slice_probs: Vec<f64> = (0..depth)
+            .map(|z| interpolate(&points, z as f64 / span))
+            .collect();
+
+        let mut ctx = Self::new(width, height, depth);
+        let slab = (width * height).max(1);
+        ctx.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let prob = slice_probs[i / slab];
+            let air = CellRng::new(seed, 0, i as u64).bool_with_prob(prob);
+            cell.set_state(u8::from(air));
+        });
+
+        ctx
+    }
+
+    // Every cell is air with the probability of the layer holding its slice, slices outside all
+    // layers stay solid. Layers listed later win where they overlap
+    #[must_use]
+    pub fn random_layers(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        layers: &[Layer],
+    ) -> Self {
+        let slice_probs: Vec<f64> = (0..depth)
+            .map(|z| {
+                (layers.iter().rev())
+                    .find(|l| l.z_range(depth).contains(&z))
+                    .map_or(0.0, |l| l.air_prob)
+            })
+            .collect();
+
+        let mut ctx = Self::new(width, height, depth);
+        let slab = (width * height).max(1);
+        ctx.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let air = CellRng::new(seed, 0, i as u64).bool_with_prob(slice_probs[i / slab]);
+            cell.set_state(u8::from(air));
+        });
+
+        ctx
+    }
+
+    // Every cell is air with the probability of the last region containing it, later regions
+    // paint over earlier ones. Cells outside all regions use `air_prob`
+    #[must_use]
+    pub fn from_regions(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_prob: f64,
+        regions: &[DensityRegion],
+    ) -> Self {
+        let mut ctx = Self::new(width, height, depth);
+
+        ctx.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let (x, y, z) = (i % width, (i / width) % height, i / (width * height));
+            let prob = (regions.iter().rev())
+                .find(|r| r.region.contains(x, y, z))
+                .map_or(air_prob, |r| r.air_prob);
+            let air = CellRng::new(seed, 0, i as u64).bool_with_prob(prob);
+            cell.set_state(u8::from(air));
+        });
+
+        ctx
+    }
+
+    fn with_air_count(
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_cells: usize,
+    ) -> Self {
+        let n = width * height * depth;
+
+        // Give every cell an independent random key and make the `air_cells` smallest keys air.
+        // Keys only depend on (seed, index), so the grid is the same for any rayon thread count
+        let keys: Vec<u64> = (0..n)
+            .into_par_iter()
+            .map(|i| CellRng::new(seed, 0, i as u64).next_u64())
+            .collect();
+
+        let threshold = if air_cells == 0 {
+            0
+        } else {
+            nth_smallest_key(&keys, air_cells - 1)
+        };
+
+        let mut cells: Vec<CACell> = keys
+            .par_iter()
+            .map(|&k| CACell::new(u8::from(air_cells > 0 && k < threshold)))
+            .collect();
+
+        // Settle ties on the threshold key in index order so the air count stays exact
+        let mut remaining = air_cells - cells.par_iter().filter(|c| c.is_air()).count();
+        for (cell, &k) in cells.iter_mut().zip(&keys) {
+            if remaining == 0 {
+                break;
+            }
+            if k == threshold {
+                cell.set_state(1);
+                remaining -= 1;
+            }
+        }
+
+        Self {
+            width,
+            height,
+            depth,
+            cells,
+        }
+    }
+
+    #[must_use]
+    pub fn idx(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.width * (y + self.height * z)
+    }
+
+    #[must_use]
+    pub fn pos(&self, index: usize) -> (usize, usize, usize) {
+        let z = index / (self.width * self.height);
+        let y = (index % (self.width * self.height)) / self.width;
+        let x = index % self.width;
+        (x, y, z)
+    }
+
+    #[must_use]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> CACell {
+        self.cells[self.idx(x, y, z)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: CACell) {
+        let i = self.idx(x, y, z);
+        self.cells[i] = value;
+    }
+
+    #[must_use]
+    pub fn count_air_neighbors(&self, x: usize, y: usize, z: usize, nb: &CANeighborhood) -> usize {
+        self.count_air_neighbors_with(x, y, z, nb, &Boundary::default())
+    }
+
+    #[must_use]
+    pub fn count_air_neighbors_with(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize,
+        nb: &CANeighborhood,
+        boundary: &Boundary,
+    ) -> usize {
+        self.count_neighbors_where(x, y, z, nb, boundary, |cell| cell.is_air())
+    }
+
+    // Neighbors whose state passes `counted`, e.g. only state 1 in a multi-state grid
+    #[must_use]
+    pub fn count_neighbors_where(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize,
+        nb: &CANeighborhood,
+        boundary: &Boundary,
+        counted: impl Fn(CACell) -> bool,
+    ) -> usize {
+        let mut count = 0;
+
+        for &(dx, dy, dz) in &nb.offsets {
+            let Some(nx) = boundary.x.wrap(x as i32 + dx, self.width) else {
+                continue;
+            };
+            let Some(ny) = boundary.y.wrap(y as i32 + dy, self.height) else {
+                continue;
+            };
+            let Some(nz) = boundary.z.wrap(z as i32 + dz, self.depth) else {
+                continue;
+            };
+
+            count += usize::from(counted(self.get(nx, ny, nz)));
+        }
+
+        count
+    }
+
+    // Picks the parallel count for grids large enough to amortize the thread overhead
+    #[must_use]
+    pub fn total_air_cells(&self) -> usize {
+        if self.cells.len() >= PARALLEL_COUNT_THRESHOLD {
+            self.par_total_air_cells()
+        } else {
+            self.serial_total_air_cells()
+        }
+    }
+
+    #[must_use]
+    pub fn serial_total_air_cells(&self) -> usize {
+        self.cells.iter().filter(|cell| cell.is_air()).count()
+    }
+
+    #[must_use]
+    pub fn par_total_air_cells(&self) -> usize {
+        self.cells.par_iter().filter(|cell| cell.is_air()).count()
+    }
+
+    // Cells whose state passes `counted`, parallel on large grids like total_air_cells
+    #[must_use]
+    pub fn count_cells_with(&self, counted: impl Fn(CACell) -> bool + Sync) -> usize {
+        if self.cells.len() >= PARALLEL_COUNT_THRESHOLD {
+            self.cells.par_iter().filter(|&&cell| counted(cell)).count()
+        } else {
+            self.cells.iter().filter(|&&cell| counted(cell)).count()
+        }
+    }
+
+    #[must_use]
+    pub fn total_solid_cells(&self) -> usize {
+        self.cells.len() - self.total_air_cells()
+    }
+
+    fn isolated_cells(&self) -> Vec<usize> {
+        let nb = CANeighborhood::von_neumann();
+        (0..self.cells.len())
+            .into_par_iter()
+            .filter(|&i| {
+                let (x, y, z) = self.pos(i);
+                self.cells[i].is_air() && self.count_air_neighbors(x, y, z, &nb) == 0
+            })
+            .collect()
+    }
+
+    // Air cells without a single air face-neighbor
+    #[must_use]
+    pub fn isolated_cell_count(&self) -> usize {
+        self.isolated_cells().len()
+    }
+
+    // Turns every isolated air cell for which `held` is false into solid rock, returns how many
+    // were removed
+    pub fn remove_isolated_cells(&mut self, held: impl Fn(usize) -> bool) -> usize {
+        let mut isolated = self.isolated_cells();
+        isolated.retain(|&i| !held(i));
+        for &i in &isolated {
+            self.cells[i].set_state(0);
+        }
+        isolated.len()
+    }
+
+    // Air cells with exactly one air neighbor under `connectivity`, the grid edge counts as rock
+    #[must_use]
+    pub fn dead_end_cells(&self, connectivity: Connectivity) -> Vec<usize> {
+        let offsets = connectivity.offsets();
+        let solid = Boundary::default();
+        (0..self.cells.len())
+            .into_par_iter()
+            .filter(|&i| {
+                let (x, y, z) = self.pos(i);
+                self.cells[i].is_air()
+                    && (offsets.iter())
+                        .filter_map(|&d| self.offset_index(x, y, z, d, &solid))
+                        .filter(|&j| self.cells[j].is_air())
+                        .take(2)
+                        .count()
+                        == 1
+            })
+            .collect()
+    }
+
+    // Fills face-connected dead ends with rock for up to `passes` rounds, each round catches the
+    // cells the previous one left as new tips. Cells for which `held` is true stay open but still
+    // count as neighbors. Returns how many cells were filled
+    pub fn remove_dead_ends(&mut self, passes: usize, held: impl Fn(usize) -> bool) -> usize {
+        let mut removed = 0;
+        for _ in 0..passes {
+            let mut dead_ends = self.dead_end_cells(Connectivity::Six);
+            dead_ends.retain(|&i| !held(i));
+            if dead_ends.is_empty() {
+                break;
+            }
+            for &i in &dead_ends {
+                self.cells[i].set_state(0);
+            }
+            removed += dead_ends.len();
+        }
+        removed
+    }
+
+    // Connects the two faces with the 6-connected path through the fewest rock cells and turns
+    // those into air, so existing tunnels are reused wherever possible. Returns how many cells
+    // were carved, 0 when the faces were already connected or every path needs a rock cell for
+    // which `held` is true
+    pub fn carve_path(&mut self, from: Face, to: Face, held: impl Fn(usize) -> bool) -> usize {
+        let on = |face: Face, i: usize| {
+            let (x, y, z) = self.pos(i);
+            face.contains(self, x, y, z)
+        };
+        let path = self.cheapest_path(|i| on(from, i), |i| on(to, i), &Boundary::default(), &held);
+        path.map_or(0, |path| self.carve(&path))
+    }
+
+    // 0-1 BFS from every cell in `from` to the first cell in `to`, air steps are free and rock
+    // steps cost one carved cell. The path runs from start to end, None when held rock blocks
+    // every way
+    fn cheapest_path(
+        &self,
+        from: impl Fn(usize) -> bool,
+        to: impl Fn(usize) -> bool,
+        boundary: &Boundary,
+        held: &impl Fn(usize) -> bool,
+    ) -> Option<Vec<usize>> {
+        let n = self.cells.len();
+        let cost = |i: usize| {
+            if self[i].is_air() {
+                Some(0)
+            } else if held(i) {
+                None
+            } else {
+                Some(1)
+            }
+        };
+
+        let mut dist = vec![u32::MAX; n];
+        let mut parent = vec![usize::MAX; n];
+        let mut queue = VecDeque::new();
+        for (i, d) in dist.iter_mut().enumerate() {
+            if let Some(step) = cost(i).filter(|_| from(i)) {
+                *d = step;
+                if step == 0 {
+                    queue.push_front(i);
+                } else {
+                    queue.push_back(i);
+                }
+            }
+        }
+
+        let mut settled = vec![false; n];
+        while let Some(i) = queue.pop_front() {
+            if std::mem::replace(&mut settled[i], true) {
+                continue;
+            }
+
+            if to(i) {
+                let mut path = vec![i];
+                let mut cur = i;
+                while parent[cur] != usize::MAX {
+                    cur = parent[cur];
+                    path.push(cur);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let (x, y, z) = self.pos(i);
+            for d in FACE_OFFSETS {
+                let Some(j) = self.offset_index(x, y, z, d, boundary) else {
+                    continue;
+                };
+                let Some(step) = cost(j) else {
+                    continue;
+                };
+                if dist[i] + step < dist[j] {
+                    dist[j] = dist[i] + step;
+                    parent[j] = i;
+                    if step == 0 {
+                        queue.push_front(j);
+                    } else {
+                        queue.push_back(j);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    // Turns the rock cells of `path` into air, returns how many there were
+    fn carve(&mut self, path: &[usize]) -> usize {
+        let mut carved = 0;
+        for &i in path {
+            if !self.cells[i].is_air() {
+                self.cells[i].set_state(1);
+                carved += 1;
+            }
+        }
+        carved
+    }
+
+    // Shortest walkable path through air, see metrics::astar_path
+    #[must_use]
+    pub fn find_path(
+        &self,
+        start: (usize, usize, usize),
+        goal: (usize, usize, usize),
+        connectivity: Connectivity,
+    ) -> Option<Vec<(usize, usize, usize)>> {
+        crate::metrics::astar_path(self, start, goal, connectivity)
+    }
+
+    // Carves a path between the two faces of `axis`, or a loop around it when `axis` is periodic,
+    // unless the air already percolates along it. Returns how many cells were carved, never
+    // through rock for which `held` is true
+    pub fn ensure_percolates(
+        &mut self,
+        axis: Axis,
+        boundary: &Boundary,
+        held: impl Fn(usize) -> bool,
+    ) -> usize {
+        let components = self.connected_components_with(boundary);
+        if self.percolates_with(&components, axis, boundary) {
+            return 0;
+        }
+
+        // Searches may wrap on the other periodic axes but never cross the seam of `axis`
+        let inner = boundary.with_solid(axis);
+        let on = |face: Face, i: usize| {
+            let (x, y, z) = self.pos(i);
+            face.contains(self, x, y, z)
+        };
+        let (from, to) = Face::of_axis(axis);
+        let Some(path) = self.cheapest_path(|i| on(from, i), |i| on(to, i), &inner, &held) else {
+            return 0;
+        };
+        let (start, end) = (path[0], path[path.len() - 1]);
+        let mut carved = self.carve(&path);
+
+        // A periodic axis has no faces to reach, the air has to wrap around instead. Stepping
+        // from the end across the seam lands on the min face, joining that cell back to the
+        // start without crossing the seam again closes a loop that crosses it exactly once
+        if boundary.is_periodic(axis) {
+            let (x, y, z) = self.pos(end);
+            let step = match axis {
+                Axis::X => (1, 0, 0),
+                Axis::Y => (0, 1, 0),
+                Axis::Z => (0, 0, 1),
+            };
+            let Some(seam) = self.offset_index(x, y, z, step, boundary) else {
+                return carved;
+            };
+            if let Some(back) = self.cheapest_path(|i| i == seam, |i| i == start, &inner, &held) {
+                carved += self.carve(&back);
+            }
+        }
+
+        carved
+    }
+
+    // Copy of the grid shifted by `shift` cells along `axis`, cells pushed off the end come back
+    // in at the start
+    #[must_use]
+    pub fn rolled(&self, axis: Axis, shift: usize) -> CAContext {
+        let mut rolled = self.clone();
+
+        for (i, &cell) in self.cells.iter().enumerate() {
+            let (x, y, z) = self.pos(i);
+            let (x, y, z) = match axis {
+                Axis::X => ((x + shift) % self.width, y, z),
+                Axis::Y => (x, (y + shift) % self.height, z),
+                Axis::Z => (x, y, (z + shift) % self.depth),
+            };
+            rolled.set(x, y, z, cell);
+        }
+
+        rolled
+    }
+
+    // Stable 64-bit FNV-1a hash of the dimensions and cell states
+    #[must_use]
+    pub fn fingerprint(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let dims = [self.width, self.height, self.depth].map(|d| d as u64);
+        let bytes =
+            (dims.iter().flat_map(|d| d.to_le_bytes())).chain(self.cells.iter().map(|c| c.0));
+
+        bytes.fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    #[must_use]
+    pub fn cells(&self) -> &[CACell] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [CACell] {
+        &mut self.cells
+    }
+
+    // One z-layer as rows of '#' (air) and '.' (rock), capped like the Display output
+    #[must_use]
+    pub fn display_layer(&self, z: usize) -> String {
+        let mut out = String::new();
+
+        for y in 0..self.height.min(DISPLAY_MAX_ROWS) {
+            for x in 0..self.width.min(DISPLAY_MAX_COLUMNS) {
+                out.push(if self.get(x, y, z).is_air() { '#' } else { '.' });
+            }
+            if self.width > DISPLAY_MAX_COLUMNS {
+                out.push_str("...");
+            }
+            out.push('\n');
+        }
+        if self.height > DISPLAY_MAX_ROWS {
+            out.push_str("...\n");
+        }
+
+        out
+    }
+
+    #[must_use]
+    pub fn iter(&self) -> CAContextIter<'_> {
+        self.into_iter()
+    }
+
+    #[must_use]
+    pub fn iter_mut(&mut self) -> CAContextIterMut<'_> {
+        self.into_iter()
+    }
+
+    // Index of the cell at (x, y, z) + d, wrapping on periodic axes. None when it falls off a
+    // solid edge
+    #[must_use]
+    pub fn offset_index(
+        &self,
+        x: usize,
+        y: usize,
+        z: usize,
+        (dx, dy, dz): (i32, i32, i32),
+        boundary: &Boundary,
+    ) -> Option<usize> {
+        let nx = boundary.x.wrap(x as i32 + dx, self.width)?;
+        let ny = boundary.y.wrap(y as i32 + dy, self.height)?;
+        let nz = boundary.z.wrap(z as i32 + dz, self.depth)?;
+        Some(self.idx(nx, ny, nz))
+    }
+
+    #[must_use]
+    pub fn connected_components(&self) -> Vec<Vec<usize>> {
+        self.connected_components_with(&Boundary::default())
+    }
+
+    // 6-connected air components, faces on periodic axes connect to the opposite side.
+    // Components come in order of their lowest cell index. Picks the parallel labeling for
+    // grids large enough to amortize the thread overhead
+    #[must_use]
+    pub fn connected_components_with(&self, boundary: &Boundary) -> Vec<Vec<usize>> {
+        if self.cells.len() >= PARALLEL_COUNT_THRESHOLD {
+            self.par_connected_components_with(boundary)
+        } else {
+            self.serial_connected_components_with(boundary)
+        }
+    }
+
+    // Breadth-first flood fill from every unvisited air cell
+    #[must_use]
+    pub fn serial_connected_components_with(&self, boundary: &Boundary) -> Vec<Vec<usize>> {
+        self.flood_components(&FACE_OFFSETS, boundary)
+    }
+
+    // Air components where cells are connected through the offsets of `nb` instead of shared
+    // faces, so they match what the rule sees. An offset connects both ways even when the
+    // neighborhood lacks its opposite
+    #[must_use]
+    pub fn connected_components_via(
+        &self,
+        nb: &CANeighborhood,
+        boundary: &Boundary,
+    ) -> Vec<Vec<usize>> {
+        self.flood_components(&symmetric_offsets(nb), boundary)
+    }
+
+    fn flood_components(
+        &self,
+        offsets: &[(i32, i32, i32)],
+        boundary: &Boundary,
+    ) -> Vec<Vec<usize>> {
+        let n = self.width() * self.height() * self.depth();
+        let mut visited = vec![false; n];
+        let mut components = Vec::new();
+
+        for i in 0..n {
+            if visited[i] || !self[i].is_air() {
+                continue;
+            }
+
+            let mut queue = VecDeque::new();
+            let mut component = Vec::new();
+
+            visited[i] = true;
+            queue.push_back(i);
+
+            while let Some(idx) = queue.pop_front() {
+                component.push(idx);
+                let (x, y, z) = self.pos(idx);
+
+                for &d in offsets {
+                    let Some(nidx) = self.offset_index(x, y, z, d, boundary) else {
+                        continue;
+                    };
+
+                    if !visited[nidx] && self[nidx].is_air() {
+                        visited[nidx] = true;
+                        queue.push_back(nidx);
+                    }
+                }
+            }
+
+            components.push(component);
+        }
+
+        components
+    }
+
+    // Hoshen-Kopelman style union-find labeling. Runs of z-slabs are labeled in parallel, then
+    // the equivalences across their borders are merged. Roots are always the lowest index in
+    // their set, so the result matches the serial flood fill up to the order of cells within
+    // a component, which is ascending here
+    #[must_use]
+    pub fn par_connected_components_with(&self, boundary: &Boundary) -> Vec<Vec<usize>> {
+        let n = self.cells.len();
+        let slab = (self.width * self.height).max(1);
+        let layers = self.depth.div_ceil(rayon::current_num_threads()).max(1);
+        let chunk = slab * layers;
+        let back = [(-1, 0, 0), (0, -1, 0), (0, 0, -1)];
+
+        let mut parent: Vec<usize> = (0..n).collect();
+
+        // Label every run of slabs on its own, only linking cells inside the run
+        parent
+            .par_chunks_mut(chunk)
+            .enumerate()
+            .for_each(|(c, parent)| {
+                let base = c * chunk;
+                let inside = base..base + parent.len();
+
+                for i in inside.clone() {
+                    if !self.cells[i].is_air() {
+                        continue;
+                    }
+
+                    let (x, y, z) = self.pos(i);
+                    for d in back {
+                        let Some(j) = self.offset_index(x, y, z, d, boundary) else {
+                            continue;
+                        };
+                        if inside.contains(&j) && self.cells[j].is_air() {
+                            union_roots(parent, base, i, j);
+                        }
+                    }
+                }
+            });
+
+        // Merge across the first layer of every run, including the periodic wrap in z
+        for z in (0..self.depth).step_by(layers) {
+            for i in z * slab..(z + 1) * slab {
+                if !self.cells[i].is_air() {
+                    continue;
+                }
+
+                let (x, y, z) = self.pos(i);
+                if let Some(j) = self.offset_index(x, y, z, (0, 0, -1), boundary)
+                    && self.cells[j].is_air()
+                {
+                    union_roots(&mut parent, 0, i, j);
+                }
+            }
+        }
+
+        // Links only ever point to a lower index, so walking up in index order finds every
+        // parent already replaced by its component's slot. The first cell seen of every
+        // component is its root
+        let mut components: Vec<Vec<usize>> = Vec::new();
+        for i in 0..n {
+            if !self.cells[i].is_air() {
+                continue;
+            }
+
+            if parent[i] == i {
+                parent[i] = components.len();
+                components.push(Vec::new());
+            } else {
+                parent[i] = parent[parent[i]];
+            }
+            components[parent[i]].push(i);
+        }
+
+        components
+    }
+
+    #[must_use]
+    pub fn percolates(&self, components: &[Vec<usize>], axis: Axis) -> bool {
+        self.percolates_with(components, axis, &Boundary::default())
+    }
+
+    // On a solid axis a component percolates when it touches both opposite faces. A periodic
+    // axis has no faces, there a component percolates when it wraps all the way around, i.e.
+    // walking through it can reach a cell with a different number of trips across the seam
+    #[must_use]
+    pub fn percolates_with(
+        &self,
+        components: &[Vec<usize>],
+        axis: Axis,
+        boundary: &Boundary,
+    ) -> bool {
+        self.percolates_via(components, axis, boundary, &FACE_OFFSETS)
+    }
+
+    // percolates_with for components from connected_components_via with the same neighborhood
+    #[must_use]
+    pub fn percolates_in(
+        &self,
+        components: &[Vec<usize>],
+        axis: Axis,
+        boundary: &Boundary,
+        nb: &CANeighborhood,
+    ) -> bool {
+        self.percolates_via(components, axis, boundary, &symmetric_offsets(nb))
+    }
+
+    fn percolates_via(
+        &self,
+        components: &[Vec<usize>],
+        axis: Axis,
+        boundary: &Boundary,
+        offsets: &[(i32, i32, i32)],
+    ) -> bool {
+        if boundary.is_periodic(axis) {
+            return self.wraps_around(components, axis, boundary, offsets);
+        }
+
+        for comp in components {
+            let mut min = usize::MAX;
+            let mut max = 0;
+
+            for &idx in comp {
+                let (x, y, z) = self.pos(idx);
+                let v = match axis {
+                    Axis::X => x,
+                    Axis::Y => y,
+                    Axis::Z => z,
+                };
+
+                min = min.min(v);
+                max = max.max(v);
+            }
+
+            let limit = match axis {
+                Axis::X => self.width() - 1,
+                Axis::Y => self.height() - 1,
+                Axis::Z => self.depth() - 1,
+            };
+
+            if min == 0 && max == limit {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Floods every component while tracking the unwrapped coordinate along the axis. Meeting a
+    // visited cell at a different unwrapped coordinate means there is a loop around the axis
+    fn wraps_around(
+        &self,
+        components: &[Vec<usize>],
+        axis: Axis,
+        boundary: &Boundary,
+        offsets: &[(i32, i32, i32)],
+    ) -> bool {
+        let mut unwrapped: Vec<Option<i64>> = vec![None; self.cells.len()];
+
+        for comp in components {
+            let Some(&start) = comp.first() else {
+                continue;
+            };
+
+            let mut queue = VecDeque::from([start]);
+            unwrapped[start] = Some(0);
+
+            while let Some(idx) = queue.pop_front() {
+                let u = unwrapped[idx].unwrap_or_default();
+                let (x, y, z) = self.pos(idx);
+
+                for &(dx, dy, dz) in offsets {
+                    let d = (dx, dy, dz);
+                    let Some(nidx) = self.offset_index(x, y, z, d, boundary) else {
+                        continue;
+                    };
+                    if !self[nidx].is_air() {
+                        continue;
+                    }
+
+                    let step = match axis {
+                        Axis::X => dx,
+                        Axis::Y => dy,
+                        Axis::Z => dz,
+                    };
+                    let expected = u + i64::from(step);
+
+                    match unwrapped[nidx] {
+                        Some(seen) if seen != expected => return true,
+                        Some(_) => {}
+                        None => {
+                            unwrapped[nidx] = Some(expected);
+                            queue.push_back(nidx);
+                        }
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    #[must_use]
+    pub fn neighbor_stats(&self, nb: &CANeighborhood, boundary: &Boundary) -> (usize, usize, f64) {
+        self.neighbor_stats_where(nb, boundary, |cell| cell.is_air())
+    }
+
+    #[must_use]
+    pub fn neighbor_stats_where(
+        &self,
+        nb: &CANeighborhood,
+        boundary: &Boundary,
+        counted: impl Fn(CACell) -> bool,
+    ) -> (usize, usize, f64) {
+        let mut min = usize::MAX;
+        let mut max = 0;
+        let mut sum = 0usize;
+        let mut count = 0usize;
+
+        for z in 0..self.depth {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let n = self.count_neighbors_where(x, y, z, nb, boundary, &counted);
+                    min = min.min(n);
+                    max = max.max(n);
+                    sum += n;
+                    count += 1;
+                }
+            }
+        }
+
+        (min, max, sum as f64 / count as f64)
+    }
+
+    pub fn save_cbor(&self, path: &Path) -> std::io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        ciborium::into_writer(self, file).map_err(std::io::Error::other)
+    }
+
+    pub fn load_cbor(path: &Path) -> std::io::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        ciborium::from_reader(file).map_err(std::io::Error::other)
+    }
+
+    // NumPy .npy (format 1.0) of the cell states as uint8 with shape (depth, height, width) in
+    // C order, so arr[z, y, x] is the cell at (x, y, z) and the bytes are the cells in index order
+    pub fn to_npy(&self, path: &Path) -> std::io::Result<()> {
+        let mut header = format!(
+            "{{'descr': '|u1', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            self.depth, self.height, self.width
+        );
+        // Magic, version and length take 10 bytes, the data starts 64-byte aligned
+        let padded = (10 + header.len() + 1).next_multiple_of(64) - 10;
+        header.push_str(&" ".repeat(padded - header.len() - 1));
+        header.push('\n');
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(NPY_MAGIC)?;
+        file.write_all(&[1, 0])?;
+        file.write_all(&(header.len() as u16).to_le_bytes())?;
+        file.write_all(header.as_bytes())?;
+        file.write_all(&self.cells.iter().map(|c| c.0).collect::<Vec<_>>())?;
+        file.flush()
+    }
+
+    // Reads a 3D uint8 (or bool) C-order .npy, see to_npy for the axis order
+    pub fn from_npy(path: &Path) -> Result<Self, GridFileError> {
+        let NpyArray {
+            descr,
+            dims: (width, height, depth),
+            data,
+        } = read_npy(path)?;
+        if !["|u1", "<u1", ">u1", "u1", "|b1"].contains(&descr.as_str()) {
+            return Err(GridFileError::Header(format!(
+                "dtype {descr} is not uint8 or bool"
+            )));
+        }
+        Self::from_bytes(width, height, depth, &data)
+    }
+
+    // Every cell is air with its own probability from `map`, laid out in index order like
+    // to_npy. Values outside [0, 1] act as the nearest bound, NaN as 0
+    pub fn random_from_probability_map(
+        map: &[f32],
+        (width, height, depth): (usize, usize, usize),
+        seed: u64,
+    ) -> Result<Self, GridFileError> {
+        let expected = cell_count(width, height, depth)?;
+        if map.len() != expected {
+            return Err(GridFileError::Length {
+                expected,
+                found: map.len(),
+            });
+        }
+
+        let mut ctx = Self::new(width, height, depth);
+        ctx.cells.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let prob = f64::from(map[i]);
+            let prob = if prob.is_nan() {
+                0.0
+            } else {
+                prob.clamp(0.0, 1.0)
+            };
+            let air = CellRng::new(seed, 0, i as u64).bool_with_prob(prob);
+            cell.set_state(u8::from(air));
+        });
+
+        Ok(ctx)
+    }
+
+    // The cell states in index order (x fastest, then y, then z), with the grid size in a JSON
+    // sidecar at `<path>.json`
+    pub fn to_raw(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.cells.iter().map(|c| c.0).collect::<Vec<_>>())?;
+        let dims = RawDims {
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+        };
+        let sidecar = File::create(raw_sidecar(path))?;
+        serde_json::to_writer_pretty(sidecar, &dims).map_err(std::io::Error::other)
+    }
+
+    pub fn from_raw(path: &Path) -> Result<Self, GridFileError> {
+        let sidecar = BufReader::new(File::open(raw_sidecar(path)).map_err(GridFileError::Io)?);
+        let dims: RawDims = serde_json::from_reader(sidecar)
+            .map_err(|e| GridFileError::Header(format!("bad sidecar: {e}")))?;
+        let bytes = std::fs::read(path).map_err(GridFileError::Io)?;
+        Self::from_bytes(dims.width, dims.height, dims.depth, &bytes)
+    }
+
+    fn from_bytes(
+        width: usize,
+        height: usize,
+        depth: usize,
+        bytes: &[u8],
+    ) -> Result<Self, GridFileError> {
+        let expected = cell_count(width, height, depth)?;
+        if bytes.len() != expected {
+            return Err(GridFileError::Length {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+            cells: bytes.iter().map(|&b| CACell::new(b)).collect(),
+        })
+    }
+}
+
+impl Index<usize> for CAContext {
+    type Output = CACell;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.cells[index]
+    }
+}
+
+impl IndexMut<usize> for CAContext {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.cells[index]
+    }
+}
+
+impl fmt::Display for CAContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for z in 0..self.depth.min(DISPLAY_MAX_LAYERS) {
+            writeln!(f, "--- Z={z} ---")?;
+            write!(f, "{}", self.display_layer(z))?;
+        }
+        if self.depth > DISPLAY_MAX_LAYERS {
+            writeln!(f, "...")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Walks the grid in index order yielding (x, y, z, cell)
+pub struct CAContextIter<'a> {
+    ctx: &'a CAContext,
+    front: usize,
+    back: usize,
+}
+
+impl Iterator for CAContextIter<'_> {
+    type Item = (usize, usize, usize, CACell);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+
+        let i = self.front;
+        self.front += 1;
+        let (x, y, z) = self.ctx.pos(i);
+        Some((x, y, z, self.ctx.cells[i]))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl DoubleEndedIterator for CAContextIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+
+        self.back -= 1;
+        let (x, y, z) = self.ctx.pos(self.back);
+        Some((x, y, z, self.ctx.cells[self.back]))
+    }
+}
+
+impl ExactSizeIterator for CAContextIter<'_> {}
+
+impl<'a> IntoIterator for &'a CAContext {
+    type Item = (usize, usize, usize, CACell);
+    type IntoIter = CAContextIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        CAContextIter {
+            ctx: self,
+            front: 0,
+            back: self.cells.len(),
+        }
+    }
+}
+
+// Mutable counterpart of CAContextIter
+pub struct CAContextIterMut<'a> {
+    width: usize,
+    height: usize,
+    cells: std::iter::Enumerate<std::slice::IterMut<'a, CACell>>,
+}
+
+impl<'a> CAContextIterMut<'a> {
+    fn with_pos(
+        &self,
+        (i, cell): (usize, &'a mut CACell),
+    ) -> (usize, usize, usize, &'a mut CACell) {
+        let slab = self.width * self.height;
+        (i % self.width, (i % slab) / self.width, i / slab, cell)
+    }
+}
+
+impl<'a> Iterator for CAContextIterMut<'a> {
+    type Item = (usize, usize, usize, &'a mut CACell);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.cells.next()?;
+        Some(self.with_pos(next))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cells.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for CAContextIterMut<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let next = self.cells.next_back()?;
+        Some(self.with_pos(next))
+    }
+}
+
+impl ExactSizeIterator for CAContextIterMut<'_> {}
+
+impl<'a> IntoIterator for &'a mut CAContext {
+    type Item = (usize, usize, usize, &'a mut CACell);
+    type IntoIter = CAContextIterMut<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        CAContextIterMut {
+            width: self.width,
+            height: self.height,
+            cells: self.cells.iter_mut().enumerate(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CAEngine {
+    pub config: CAConfig,
+    context: CAContext,
+    buffer: CAContext,
+    // Ring buffer of previous contexts, costs history_capacity * width * height * depth bytes
+    #[serde(skip)]
+    history: VecDeque<CAContext>,
+    #[serde(skip)]
+    history_capacity: usize,
+    #[serde(default)]
+    mode: EngineMode,
+    // Keys the per-cell RNG of soft rules together with the iteration count
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    iteration: u64,
+    // Only cells inside the region are updated, the rest stays fixed but is still read as
+    // neighbors
+    #[serde(default)]
+    region: Option<Region>,
+    // Cells marked true keep their state like those outside the region
+    #[serde(default)]
+    frozen: Option<Vec<bool>>,
+    // Cells changed by the last sparse iteration, grouped per z-slab. None forces a full update,
+    // so it is cleared whenever the context may have changed behind the engine's back
+    #[serde(skip)]
+    changed: Option<Vec<Vec<usize>>>,
+    // Air neighbor count of every cell of `context`, kept up to date by the incremental mode
+    // and dropped by anything else that changes the grid
+    #[serde(skip)]
+    counts: Option<Vec<u16>>,
+}
+
+impl CAEngine {
+    #[must_use]
+    pub fn new(config: CAConfig, context: CAContext) -> Self {
+        let buffer = context.clone();
+
+        Self {
+            config,
+            context,
+            buffer,
+            history: VecDeque::new(),
+            history_capacity: 0,
+            mode: EngineMode::default(),
+            seed: 0,
+            iteration: 0,
+            region: None,
+            frozen: None,
+            changed: None,
+            counts: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_mode(mut self, mode: EngineMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    #[must_use]
+    pub fn mode(&self) -> EngineMode {
+        self.mode
+    }
+
+    // The mode the next iteration runs in, with auto resolved against the current neighborhood
+    #[must_use]
+    pub fn resolved_mode(&self) -> EngineMode {
+        match self.mode {
+            EngineMode::Auto if self.config.neighborhood.len() > AUTO_INCREMENTAL_NEIGHBORS => {
+                EngineMode::Incremental
+            }
+            EngineMode::Auto => EngineMode::Dense,
+            mode => mode,
+        }
+    }
+
+    // Restricts updates to `region`, None updates the whole grid again
+    pub fn set_region(&mut self, region: Option<Region>) -> Result<(), RegionError> {
+        if let Some(region) = &region {
+            let ctx = &self.context;
+            region.validate(ctx.width(), ctx.height(), ctx.depth())?;
+        }
+
+        self.region = region;
+        self.invalidate_active_set();
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    // Holds every cell set in `mask` at its current state, None frees them again. The mask has
+    // one entry per cell
+    pub fn set_frozen(&mut self, mask: Option<Vec<bool>>) {
+        if let Some(mask) = &mask {
+            assert_eq!(
+                mask.len(),
+                self.context.cells().len(),
+                "Frozen mask size mismatch"
+            );
+        }
+        self.frozen = mask;
+        self.invalidate_active_set();
+    }
+
+    // Post-processing on the current grid that, like an iteration, leaves cells outside the
+    // region and frozen cells alone. See the CAContext methods of the same name
+    pub fn remove_isolated_cells(&mut self) -> usize {
+        let held = held_cells(&self.context, self.region, self.frozen.as_deref());
+        self.context.remove_isolated_cells(held)
+    }
+
+    pub fn remove_dead_ends(&mut self, passes: usize) -> usize {
+        let held = held_cells(&self.context, self.region, self.frozen.as_deref());
+        self.context.remove_dead_ends(passes, held)
+    }
+
+    pub fn ensure_percolates(&mut self, axis: Axis) -> usize {
+        let held = held_cells(&self.context, self.region, self.frozen.as_deref());
+        let boundary = self.config.boundary;
+        self.context.ensure_percolates(axis, &boundary, held)
+    }
+
+    // Runs `iterations` iterations that only update the cells inside min..max, everything
+    // outside acts as fixed material. The engine's own region is restored afterwards
+    pub fn run_region(
+        &mut self,
+        min: (usize, usize, usize),
+        max: (usize, usize, usize),
+        iterations: usize,
+    ) -> Result<EngineReport, RegionError> {
+        let previous = self.region;
+        self.set_region(Some(Region::new(min, max)))?;
+        let report = self.run_report(iterations, &mut Vec::new());
+        self.region = previous;
+        self.invalidate_active_set();
+        Ok(report)
+    }
+
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    #[must_use]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Number of iterations computed so far, step_back counts back down
+    #[must_use]
+    pub fn iteration(&self) -> u64 {
+        self.iteration
+    }
+
+    // RNG iteration key of the next update, 0 is taken by CAContext::random
+    fn rng_iteration(&self) -> u64 {
+        self.iteration + 1
+    }
+
+    // Estimated bytes held by the engine: both grid buffers, the history ring and the sparse
+    // mode's active mask and changed lists
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        let grid = std::mem::size_of_val(self.context.cells());
+        let history = self.history_capacity * grid;
+        let extra = match self.resolved_mode() {
+            EngineMode::Auto | EngineMode::Dense => 0,
+            EngineMode::Incremental => self.context.cells().len() * std::mem::size_of::<u16>(),
+            EngineMode::Sparse => {
+                let changed: usize = (self.changed.iter().flatten())
+                    .map(|slab| slab.capacity() * std::mem::size_of::<usize>())
+                    .sum();
+                self.context.cells().len() * std::mem::size_of::<bool>() + changed
+            }
+        };
+
+        2 * grid + history + extra
+    }
+
+    // Switches to another rule/neighborhood while keeping the context and buffer allocations
+    pub fn set_config(&mut self, config: CAConfig) {
+        self.config = config;
+        self.invalidate_active_set();
+    }
+
+    // Forces the next sparse iteration to recompute every cell and the incremental mode to
+    // recount every neighborhood
+    pub fn invalidate_active_set(&mut self) {
+        self.changed = None;
+        self.counts = None;
+    }
+
+    // After any iteration the context holds the new generation and the buffer the one before
+    // it, the two are swapped rather than copied. A fresh engine starts with equal copies
+    #[must_use]
+    pub fn context(&self) -> &CAContext {
+        &self.context
+    }
+
+    #[must_use]
+    pub fn buffer(&self) -> &CAContext {
+        &self.buffer
+    }
+
+    // The caller is responsible for keeping the grid consistent with the buffer: keep the
+    // dimensions unchanged. The sparse active set is dropped since any cell may change
+    pub fn context_mut(&mut self) -> &mut CAContext {
+        self.invalidate_active_set();
+        &mut self.context
+    }
+
+    #[must_use]
+    pub fn into_context(self) -> CAContext {
+        self.context
+    }
+
+    // Starts over from `ctx` as if the engine was just built with it, but copies into the
+    // existing buffer instead of allocating a new one. The iteration count, history and cached
+    // counts are reset, config, mode, seed, region and frozen cells are kept
+    pub fn replace_context(&mut self, ctx: CAContext) -> Result<(), DimensionMismatch> {
+        let expected = (
+            self.buffer.width(),
+            self.buffer.height(),
+            self.buffer.depth(),
+        );
+        let found = (ctx.width(), ctx.height(), ctx.depth());
+        if found != expected {
+            return Err(DimensionMismatch { expected, found });
+        }
+
+        self.buffer.cells_mut().copy_from_slice(ctx.cells());
+        self.context = ctx;
+        self.iteration = 0;
+        self.history.clear();
+        self.invalidate_active_set();
+        Ok(())
+    }
+
+    // Keeps the last `capacity` contexts so iterations can be undone with step_back
+    #[must_use]
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.history_capacity = capacity;
+        self.history = VecDeque::with_capacity(capacity);
+        self
+    }
+
+    #[must_use]
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    // Restores the context from before the most recent iteration
+    pub fn step_back(&mut self) -> Result<(), HistoryError> {
+        if self.history_capacity == 0 {
+            return Err(HistoryError::Disabled);
+        }
+
+        let previous = self.history.pop_back().ok_or(HistoryError::Empty)?;
+        self.context = previous;
+        self.iteration = self.iteration.saturating_sub(1);
+        self.invalidate_active_set();
+        Ok(())
+    }
+
+    fn push_history(&mut self) {
+        if self.history_capacity == 0 {
+            return;
+        }
+
+        if self.history.len() == self.history_capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.context.clone());
+    }
+
+    // Persists config, context and buffer so a loaded engine continues exactly where it stopped
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(file, self).map_err(std::io::Error::other)
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        serde_json::from_reader(file).map_err(std::io::Error::other)
+    }
+
+    pub fn run(&mut self, iterations: usize, log: &mut Vec<String>) {
+        self.run_report(iterations, log);
+    }
+
+    pub fn run_report(&mut self, iterations: usize, log: &mut Vec<String>) -> EngineReport {
+        self.run_with_hook(iterations, log, None, |_, _| {})
+    }
+
+    // Like run, but stops between iterations once `time_limit` has elapsed. Returns the number
+    // of iterations actually completed
+    pub fn run_with_deadline(
+        &mut self,
+        iterations: usize,
+        log: &mut Vec<String>,
+        time_limit: Option<Duration>,
+    ) -> usize {
+        (self.run_with_hook(iterations, log, time_limit, |_, _| {})).iterations_run
+    }
+
+    // Like run_with_deadline, but calls `hook` with the iteration number and context for the
+    // initial state and after every completed iteration
+    pub fn run_with_hook(
+        &mut self,
+        iterations: usize,
+        log: &mut Vec<String>,
+        time_limit: Option<Duration>,
+        mut hook: impl FnMut(usize, &CAContext),
+    ) -> EngineReport {
+        let start = Instant::now();
+        let mut stop_reason = StopReason::Completed;
+        let mut per_iter = vec![self.iteration_stats(0, 0)];
+        hook(0, &self.context);
+
+        for iter in 0..iterations {
+            if time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                stop_reason = StopReason::TimeLimit;
+                break;
+            }
+
+            // LOGGING
+            log.push(per_iter[iter].to_string());
+
+            self.run_iteration();
+            per_iter.push(self.iteration_stats(iter + 1, self.changed_cells()));
+            hook(iter + 1, &self.context);
+        }
+
+        let last = per_iter
+            .last()
+            .expect("per_iter starts with the initial grid");
+        log.push(last.to_string());
+
+        EngineReport {
+            iterations_run: last.iter,
+            per_iter,
+            wall_time: start.elapsed(),
+            stop_reason,
+        }
+    }
+
+    fn iteration_stats(&self, iter: usize, changed: usize) -> IterationStats {
+        let counted = self.config.count_states.mask();
+        let counted = |cell: CACell| counted[usize::from(cell.0)];
+        let (min_neighbors, max_neighbors, mean_neighbors) = self.context.neighbor_stats_where(
+            &self.config.neighborhood,
+            &self.config.boundary,
+            counted,
+        );
+
+        IterationStats {
+            iter,
+            alive: self.context.count_cells_with(counted),
+            changed,
+            min_neighbors,
+            max_neighbors,
+            mean_neighbors,
+        }
+    }
+
+    // Cells that differ from the grid before the last iteration, which the swap left in the
+    // buffer
+    fn changed_cells(&self) -> usize {
+        (self.context.cells().par_iter())
+            .zip(self.buffer.cells().par_iter())
+            .filter(|(new, old)| new != old)
+            .count()
+    }
+
+    // Grid after k iterations of this engine's rule from `initial`. Any randomness is keyed by
+    // (seed, iteration, cell), so this is a pure function of its inputs
+    #[must_use]
+    pub fn state_at(&self, initial: &CAContext, k: usize) -> CAContext {
+        let mut engine = CAEngine::new(self.config.clone(), initial.clone())
+            .with_mode(self.mode)
+            .with_seed(self.seed);
+        engine.region = self.region;
+        engine.frozen.clone_from(&self.frozen);
+        for _ in 0..k {
+            engine.run_iteration();
+        }
+        engine.context
+    }
+
+    pub fn run_iteration(&mut self) {
+        self.push_history();
+
+        match self.resolved_mode() {
+            // Soft rules can flip any cell, not just the ones near a change
+            EngineMode::Sparse if !self.config.rule.is_soft() => self.update_sparse(),
+            EngineMode::Incremental => self.update_incremental(),
+            EngineMode::Auto | EngineMode::Dense | EngineMode::Sparse => self.update_dense(),
+        }
+
+        // Swap buffers — O(1)
+        std::mem::swap(&mut self.context, &mut self.buffer);
+        self.iteration += 1;
+    }
+
+    fn update_dense(&mut self) {
+        let config = &self.config;
+        let nb = &self.config.neighborhood;
+        let rule = &self.config.rule;
+        let boundary = &self.config.boundary;
+        let (seed, iteration) = (self.seed, self.rng_iteration());
+        let (region, frozen) = (self.region, self.frozen.as_deref());
+        let counted = self.config.count_states.mask();
+        let counted = |cell: CACell| counted[usize::from(cell.0)];
+
+        // SAFELY split mutable borrows
+        let (old, new) = (&self.context, &mut self.buffer);
+
+        new.cells_mut()
+            //.iter_mut()
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, cell)| {
+                let (x, y, z) = old.pos(i);
+                if is_held(region, frozen, (x, y, z), i) {
+                    *cell = old[i];
+                    return;
+                }
+
+                let alive_neighbors = old.count_neighbors_where(x, y, z, nb, boundary, counted);
+                let alive = counted(old[i]);
+                let n = config.rule_count(alive, alive_neighbors);
+
+                let next = rule.next_state_seeded(alive, n, seed, iteration, i);
+
+                cell.set_state(u8::from(next));
+            });
+
+        self.invalidate_active_set();
+    }
+
+    // Reads neighbor counts from the cache instead of the grid, then patches the counts around
+    // the cells that flipped. Falls back to dense when a count doesn't fit the cache
+    fn update_incremental(&mut self) {
+        let counts = match self.counts.take() {
+            Some(counts) => Some(counts),
+            None => self.neighbor_counts(),
+        };
+        let Some(mut counts) = counts else {
+            self.update_dense();
+            return;
+        };
+
+        let config = &self.config;
+        let nb = &self.config.neighborhood;
+        let rule = &self.config.rule;
+        let boundary = &self.config.boundary;
+        let (seed, iteration) = (self.seed, self.rng_iteration());
+        let (region, frozen) = (self.region, self.frozen.as_deref());
+        let counted = self.config.count_states.mask();
+
+        let (old, new) = (&self.context, &mut self.buffer);
+        let slab = (old.width() * old.height()).max(1);
+
+        let changed: Vec<Vec<usize>> = new
+            .cells_mut()
+            .par_chunks_mut(slab)
+            .enumerate()
+            .map(|(z, chunk)| {
+                let mut changed = Vec::new();
+
+                for (j, cell) in chunk.iter_mut().enumerate() {
+                    let i = z * slab + j;
+                    let (x, y, _) = old.pos(i);
+                    if is_held(region, frozen, (x, y, z), i) {
+                        *cell = old[i];
+                        continue;
+                    }
+
+                    let alive = counted[usize::from(old[i].0)];
+                    let n = config.rule_count(alive, usize::from(counts[i]));
+                    let next = u8::from(rule.next_state_seeded(alive, n, seed, iteration, i));
+
+                    if next != old[i].0 {
+                        changed.push(i);
+                    }
+                    cell.set_state(next);
+                }
+
+                changed
+            })
+            .collect();
+
+        apply_count_deltas(&mut counts, old, new, &changed, nb, boundary, &counted);
+        self.counts = Some(counts);
+        self.changed = None;
+    }
+
+    // Full recount of the current grid, None if a count does not fit in a u16
+    fn neighbor_counts(&self) -> Option<Vec<u16>> {
+        let ctx = &self.context;
+        let nb = &self.config.neighborhood;
+        let boundary = &self.config.boundary;
+        let counted = self.config.count_states.mask();
+        let counted = |cell: CACell| counted[usize::from(cell.0)];
+
+        (0..ctx.cells().len())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y, z) = ctx.pos(i);
+                u16::try_from(ctx.count_neighbors_where(x, y, z, nb, boundary, counted)).ok()
+            })
+            .collect()
+    }
+
+    // Only recomputes cells that changed last iteration or have a changed cell in their
+    // neighborhood, everything else is copied straight from the old buffer
+    fn update_sparse(&mut self) {
+        let config = &self.config;
+        let nb = &self.config.neighborhood;
+        let rule = &self.config.rule;
+        let boundary = &self.config.boundary;
+        let counted = self.config.count_states.mask();
+        let counted = |cell: CACell| counted[usize::from(cell.0)];
+
+        let (region, frozen) = (self.region, self.frozen.as_deref());
+
+        let active = (self.changed.take())
+            .map(|changed| active_cells(&self.context, &changed, nb, boundary));
+        let (old, new) = (&self.context, &mut self.buffer);
+        let slab = (old.width() * old.height()).max(1);
+
+        let changed = new
+            .cells_mut()
+            .par_chunks_mut(slab)
+            .enumerate()
+            .map(|(z, chunk)| {
+                let mut changed = Vec::new();
+
+                for (j, cell) in chunk.iter_mut().enumerate() {
+                    let i = z * slab + j;
+                    let (x, y, _) = old.pos(i);
+                    let held = is_held(region, frozen, (x, y, z), i);
+                    if held || active.as_ref().is_some_and(|active| !active[i]) {
+                        *cell = old[i];
+                        continue;
+                    }
+
+                    let alive_neighbors = old.count_neighbors_where(x, y, z, nb, boundary, counted);
+                    let alive = counted(old[i]);
+                    let n = config.rule_count(alive, alive_neighbors);
+                    let next = u8::from(rule.next_state(alive, n));
+
+                    if next != old[i].0 {
+                        changed.push(i);
+                    }
+                    cell.set_state(next);
+                }
+
+                changed
+            })
+            .collect();
+
+        self.changed = Some(changed);
+        self.counts = None;
+    }
+
+    // Flexible (and slower) update driven by a custom transition rule instead of the config's
+    pub fn run_iteration_with(&mut self, rule: &impl TransitionRule) {
+        self.push_history();
+
+        let (region, frozen) = (self.region, self.frozen.as_deref());
+        let (old, new) = (&self.context, &mut self.buffer);
+
+        new.cells_mut()
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, cell)| {
+                let (x, y, z) = old.pos(i);
+                *cell = if is_held(region, frozen, (x, y, z), i) {
+                    old[i]
+                } else {
+                    rule.next(old, x, y, z, old[i])
+                };
+            });
+
+        self.invalidate_active_set();
+        std::mem::swap(&mut self.context, &mut self.buffer);
+        self.iteration += 1;
+    }
+
+    // One iteration of several configs. Sequential applies them in turn, union and intersection
+    // evaluate every config against the same old grid and combine the results per cell before
+    // anything is written, so no config sees another one's output
+    pub fn run_iteration_composed(&mut self, configs: &[&CAConfig], composition: Composition) {
+        if composition == Composition::Sequential {
+            for &config in configs {
+                self.run_iteration_with(config);
+            }
+            return;
+        }
+
+        self.push_history();
+
+        let iteration = self.rng_iteration();
+        let (region, frozen) = (self.region, self.frozen.as_deref());
+        let (old, new) = (&self.context, &mut self.buffer);
+
+        new.cells_mut()
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, cell)| {
+                let (x, y, z) = old.pos(i);
+                if is_held(region, frozen, (x, y, z), i) {
+                    *cell = old[i];
+                    return;
+                }
+
+                // Soft configs get their own seed each so their draws are independent
+                let mut next = configs.iter().enumerate().map(|(k, config)| {
+                    let counted = |cell: CACell| config.count_states.counts(cell);
+                    let n = old.count_neighbors_where(
+                        x,
+                        y,
+                        z,
+                        &config.neighborhood,
+                        &config.boundary,
+                        counted,
+                    );
+                    let seed = self.seed.wrapping_add(k as u64);
+                    let alive = counted(old[i]);
+                    let n = config.rule_count(alive, n);
+                    (config.rule).next_state_seeded(alive, n, seed, iteration, i)
+                });
+
+                let air = match composition {
+                    Composition::Union => next.any(|air| air),
+                    Composition::Intersection => next.all(|air| air),
+                    Composition::Sequential => unreachable!(),
+                };
+                cell.set_state(u8::from(air));
+            });
+
+        self.invalidate_active_set();
+        std::mem::swap(&mut self.context, &mut self.buffer);
+        self.iteration += 1;
+    }
+
+    // Plain sequential version of run_iteration, used to cross-check the parallel update
+    pub fn run_iteration_reference(&mut self) {
+        self.push_history();
+
+        let nb = &self.config.neighborhood;
+        let rule = &self.config.rule;
+        let boundary = &self.config.boundary;
+        let count_states = &self.config.count_states;
+        let (seed, iteration) = (self.seed, self.rng_iteration());
+        let (old, new) = (&self.context, &mut self.buffer);
+
+        for z in 0..old.depth() {
+            for y in 0..old.height() {
+                for x in 0..old.width() {
+                    let i = old.idx(x, y, z);
+                    if is_held(self.region, self.frozen.as_deref(), (x, y, z), i) {
+                        new.set(x, y, z, old.get(x, y, z));
+                        continue;
+                    }
+
+                    let counted = |cell: CACell| count_states.counts(cell);
+                    let alive_neighbors = old.count_neighbors_where(x, y, z, nb, boundary, counted);
+                    let alive = counted(old.get(x, y, z));
+                    let n = self.config.rule_count(alive, alive_neighbors);
+                    let next = rule.next_state_seeded(alive, n, seed, iteration, i);
+                    new.set(x, y, z, CACell::new(u8::from(next)));
+                }
+            }
+        }
+
+        self.invalidate_active_set();
+        std::mem::swap(&mut self.context, &mut self.buffer);
+        self.iteration += 1;
+    }
+
+    // Runs the parallel and the reference update side by side from the current state and
+    // reports the first cell where they disagree
+    pub fn verify_against_reference(&self, iterations: usize) -> Result<(), MismatchReport> {
+        let mut parallel = self.clone();
+        let mut reference = self.clone();
+
+        for iter in 0..iterations {
+            let before = reference.context.clone();
+            parallel.run_iteration();
+            reference.run_iteration_reference();
+
+            let mismatch = (parallel.context.cells().iter())
+                .zip(reference.context.cells())
+                .position(|(a, b)| a.0 != b.0);
+
+            if let Some(i) = mismatch {
+                let (x, y, z) = before.pos(i);
+                return Err(MismatchReport {
+                    iter,
+                    x,
+                    y,
+                    z,
+                    neighbors: before.count_neighbors_where(
+                        x,
+                        y,
+                        z,
+                        &self.config.neighborhood,
+                        &self.config.boundary,
+                        |cell| self.config.count_states.counts(cell),
+                    ),
+                    expected: reference.context[i],
+                    actual: parallel.context[i],
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CAStage {
+    pub config: CAConfig,
+    pub iterations: usize,
+}
+
+// How the stages of a pipeline see each other's output
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Composition {
+    // Each stage runs all its iterations on the previous stage's output
+    #[default]
+    Sequential,
+    // Every iteration all stages read the same grid, a cell is air if any stage makes it air
+    Union,
+    // Like union, but a cell is only air if every stage makes it air
+    Intersection,
+}
+
+impl fmt::Display for Composition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Composition::Sequential => write!(f, "sequential"),
+            Composition::Union => write!(f, "union"),
+            Composition::Intersection => write!(f, "intersection"),
+        }
+    }
+}
+
+// A sequence of rule/neighborhood stages applied to the same grid
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CAPipeline {
+    pub name: String,
+    pub stages: Vec<CAStage>,
+    #[serde(default)]
+    pub composition: Composition,
+}
+
+impl CAPipeline {
+    // Stages run side by side under union and intersection, so those take as long as the
+    // longest stage
+    #[must_use]
+    pub fn total_iterations(&self) -> usize {
+        let iterations = self.stages.iter().map(|stage| stage.iterations);
+        match self.composition {
+            Composition::Sequential => iterations.sum(),
+            Composition::Union | Composition::Intersection => iterations.max().unwrap_or(0),
+        }
+    }
+
+    // Runs every stage on the engine, reusing its buffers. The time limit covers the whole
+    // pipeline, the hook and the report see iteration numbers counted across stages
+    pub fn run(
+        &self,
+        engine: &mut CAEngine,
+        log: &mut Vec<String>,
+        time_limit: Option<Duration>,
+        hook: impl FnMut(usize, &CAContext),
+    ) -> EngineReport {
+        match self.composition {
+            Composition::Sequential => self.run_sequential(engine, log, time_limit, hook),
+            Composition::Union | Composition::Intersection => {
+                self.run_combined(engine, log, time_limit, hook)
+            }
+        }
+    }
+
+    // Stages that ran out of iterations drop out of the combination
+    fn run_combined(
+        &self,
+        engine: &mut CAEngine,
+        log: &mut Vec<String>,
+        time_limit: Option<Duration>,
+        mut hook: impl FnMut(usize, &CAContext),
+    ) -> EngineReport {
+        let start = Instant::now();
+        let total = self.total_iterations();
+        let mut completed = 0;
+        let mut stop_reason = StopReason::Completed;
+        // Neighbor stats are taken under the engine's own config
+        let mut per_iter = vec![engine.iteration_stats(0, 0)];
+
+        log.push(format!(
+            "composition={} stages={} iterations={total}",
+            self.composition,
+            self.stages.len()
+        ));
+        hook(0, &engine.context);
+
+        for iter in 0..total {
+            if time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                stop_reason = StopReason::TimeLimit;
+                break;
+            }
+
+            let configs: Vec<&CAConfig> = (self.stages.iter())
+                .filter(|stage| iter < stage.iterations)
+                .map(|stage| &stage.config)
+                .collect();
+            log.push(format!(
+                "iter={iter} alive={} stages={}",
+                engine.context.total_air_cells(),
+                configs.len()
+            ));
+
+            engine.run_iteration_composed(&configs, self.composition);
+            completed += 1;
+            per_iter.push(engine.iteration_stats(completed, engine.changed_cells()));
+            hook(completed, &engine.context);
+        }
+
+        log.push(format!(
+            "iter={completed} alive={}",
+            engine.context.total_air_cells()
+        ));
+
+        EngineReport {
+            iterations_run: completed,
+            per_iter,
+            wall_time: start.elapsed(),
+            stop_reason,
+        }
+    }
+
+    fn run_sequential(
+        &self,
+        engine: &mut CAEngine,
+        log: &mut Vec<String>,
+        time_limit: Option<Duration>,
+        mut hook: impl FnMut(usize, &CAContext),
+    ) -> EngineReport {
+        let start = Instant::now();
+        let mut completed = 0;
+        let mut stop_reason = StopReason::Completed;
+        let mut per_iter = Vec::new();
+
+        for (i, stage) in self.stages.iter().enumerate() {
+            let remaining = time_limit.map(|limit| limit.saturating_sub(start.elapsed()));
+            log.push(format!(
+                "stage={i} neighborhood={} ruleset={} iterations={}",
+                stage.config.neighborhood.name, stage.config.rule.name, stage.iterations
+            ));
+
+            engine.set_config(stage.config.clone());
+            let offset = completed;
+            let report = engine.run_with_hook(stage.iterations, log, remaining, |iter, ctx| {
+                // Stage starts repeat the previous stage's final state
+                if i == 0 || iter > 0 {
+                    hook(offset + iter, ctx);
+                }
+            });
+
+            let skip = usize::from(i > 0);
+            per_iter.extend(report.per_iter.into_iter().skip(skip).map(|mut stats| {
+                stats.iter += offset;
+                stats
+            }));
+
+            completed += report.iterations_run;
+            if report.stop_reason != StopReason::Completed {
+                stop_reason = report.stop_reason;
+                break;
+            }
+        }
+
+        EngineReport {
+            iterations_run: completed,
+            per_iter,
+            wall_time: start.elapsed(),
+            stop_reason,
+        }
+    }
+}
+
+// Offsets of `nb` together with their opposites, without duplicates
+fn symmetric_offsets(nb: &CANeighborhood) -> Vec<(i32, i32, i32)> {
+    let mut offsets: Vec<_> = (nb.offsets().iter())
+        .flat_map(|&(x, y, z)| [(x, y, z), (-x, -y, -z)])
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+// Union-find over `parent`, which holds the global indices base..base + parent.len(). The
+// larger root is linked below the smaller one so every root is the lowest index of its set
+fn union_roots(parent: &mut [usize], base: usize, a: usize, b: usize) {
+    let find = |parent: &mut [usize], mut i: usize| {
+        while parent[i - base] != i {
+            // Path halving
+            let grandparent = parent[parent[i - base] - base];
+            parent[i - base] = grandparent;
+            i = grandparent;
+        }
+        i
+    };
+
+    let (ra, rb) = (find(parent, a), find(parent, b));
+    if ra != rb {
+        parent[ra.max(rb) - base] = ra.min(rb);
+    }
+}
+
+// Patches the neighbor counts of `old` into those of `new`, given the cells that differ between
+// them per z-slab. Like active_cells every task owns one slab of counts and pulls in the
+// changes it reads, so no two tasks write the same count
+fn apply_count_deltas(
+    counts: &mut [u16],
+    old: &CAContext,
+    new: &CAContext,
+    changed: &[Vec<usize>],
+    nb: &CANeighborhood,
+    boundary: &Boundary,
+    counted: &[bool; 256],
+) {
+    let (width, height, depth) = (old.width(), old.height(), old.depth());
+    let slab = (width * height).max(1);
+
+    counts
+        .par_chunks_mut(slab)
+        .enumerate()
+        .for_each(|(z, chunk)| {
+            // A cell c counts c + o, so its count moves with every change at c + o
+            for &(dx, dy, dz) in &nb.offsets {
+                let Some(pz) = boundary.z.wrap(z as i32 + dz, depth) else {
+                    continue;
+                };
+
+                for &p in &changed[pz] {
+                    let (px, py, _) = old.pos(p);
+                    let Some(cx) = boundary.x.wrap(px as i32 - dx, width) else {
+                        continue;
+                    };
+                    let Some(cy) = boundary.y.wrap(py as i32 - dy, height) else {
+                        continue;
+                    };
+
+                    let delta = i32::from(counted[usize::from(new[p].0)])
+                        - i32::from(counted[usize::from(old[p].0)]);
+                    let count = &mut chunk[cx + width * cy];
+                    *count = (i32::from(*count) + delta) as u16;
+                }
+            }
+        });
+}
+
+// Cells outside the region or set in the frozen mask keep their state
+fn is_held(
+    region: Option<Region>,
+    frozen: Option<&[bool]>,
+    (x, y, z): (usize, usize, usize),
+    i: usize,
+) -> bool {
+    region.is_some_and(|region| !region.contains(x, y, z)) || frozen.is_some_and(|f| f[i])
+}
+
+// is_held by cell index, for code that walks cells out of grid order
+fn held_cells<'a>(
+    ctx: &CAContext,
+    region: Option<Region>,
+    frozen: Option<&'a [bool]>,
+) -> impl Fn(usize) -> bool + 'a {
+    let (width, height) = (ctx.width(), ctx.height());
+    move |i| {
+        let pos = (i % width, i / width % height, i / (width * height));
+        is_held(region, frozen, pos, i)
+    }
+}
+
+// Marks every cell that changed or reads a changed cell through the neighborhood, one z-slab
+// per task so writes never overlap
+fn active_cells(
+    ctx: &CAContext,
+    changed: &[Vec<usize>],
+    nb: &CANeighborhood,
+    boundary: &Boundary,
+) -> Vec<bool> {
+    let (width, height, depth) = (ctx.width(), ctx.height(), ctx.depth());
+    let slab = (width * height).max(1);
+    let mut active = vec![false; ctx.cells().len()];
+
+    active
+        .par_chunks_mut(slab)
+        .enumerate()
+        .for_each(|(z, chunk)| {
+            for &i in &changed[z] {
+                chunk[i - z * slab] = true;
+            }
+
+            // A cell c reads c + o, so it is active when c + o changed
+            for &(dx, dy, dz) in &nb.offsets {
+                let Some(pz) = boundary.z.wrap(z as i32 + dz, depth) else {
+                    continue;
+                };
+
+                for &p in &changed[pz] {
+                    let (px, py, _) = ctx.pos(p);
+                    let Some(cx) = boundary.x.wrap(px as i32 - dx, width) else {
+                        continue;
+                    };
+                    let Some(cy) = boundary.y.wrap(py as i32 - dy, height) else {
+                        continue;
+                    };
+
+                    chunk[cx + width * cy] = true;
+                }
+            }
+        });
+
+    active
+}
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+// A 3D C-order .npy with shape (depth, height, width)
+struct NpyArray {
+    descr: String,
+    dims: (usize, usize, usize),
+    data: Vec<u8>,
+}
+
+fn read_npy(path: &Path) -> Result<NpyArray, GridFileError> {
+    let mut bytes = std::fs::read(path).map_err(GridFileError::Io)?;
+    let header_error = |what: &str| GridFileError::Header(what.to_string());
+
+    if bytes.len() < 10 || &bytes[..6] != NPY_MAGIC {
+        return Err(header_error("not a .npy file"));
+    }
+    let (header_len, start): (usize, usize) = match bytes[6] {
+        1 => (usize::from(u16::from_le_bytes([bytes[8], bytes[9]])), 10),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            12,
+        ),
+        _ => return Err(header_error("unsupported .npy version")),
+    };
+    let header = bytes
+        .get(start..start.saturating_add(header_len))
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or_else(|| header_error("truncated header"))?;
+
+    let value = |key: &str| {
+        let rest = &header[header.find(&format!("'{key}'"))? + key.len() + 2..];
+        Some(rest.trim_start().strip_prefix(':')?.trim_start())
+    };
+    let descr = value("descr")
+        .and_then(|v| v.strip_prefix('\''))
+        .and_then(|v| v.split('\'').next())
+        .ok_or_else(|| header_error("no descr"))?
+        .to_string();
+    if !value("fortran_order").is_some_and(|v| v.starts_with("False")) {
+        return Err(header_error("only C-order arrays are supported"));
+    }
+    let shape: Vec<usize> = value("shape")
+        .and_then(|v| v.strip_prefix('('))
+        .and_then(|v| v.split(')').next())
+        .ok_or_else(|| header_error("no shape"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|_| header_error("shape is not a tuple of integers"))?;
+    let &[depth, height, width] = shape.as_slice() else {
+        return Err(header_error("shape is not (depth, height, width)"));
+    };
+
+    let data = bytes.split_off(start + header_len);
+    Ok(NpyArray {
+        descr,
+        dims: (width, height, depth),
+        data,
+    })
+}
+
+// Per-cell air probabilities for CAContext::random_from_probability_map
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbabilityMap {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub probs: Vec<f32>,
+    // Values that were outside [0, 1] or NaN and got clamped on load
+    pub clamped: usize,
+}
+
+impl ProbabilityMap {
+    // Reads a float32 or float64 .npy with the axis order of CAContext::to_npy
+    pub fn from_npy(path: &Path) -> Result<Self, GridFileError> {
+        let NpyArray {
+            descr,
+            dims: (width, height, depth),
+            data,
+        } = read_npy(path)?;
+        let expected = cell_count(width, height, depth)?;
+
+        let decode: fn(&[u8]) -> f32 = match descr.as_str() {
+            "<f4" => |b| f32::from_le_bytes(b.try_into().unwrap_or_default()),
+            ">f4" => |b| f32::from_be_bytes(b.try_into().unwrap_or_default()),
+            "<f8" => |b| f64::from_le_bytes(b.try_into().unwrap_or_default()) as f32,
+            ">f8" => |b| f64::from_be_bytes(b.try_into().unwrap_or_default()) as f32,
+            _ => {
+                return Err(GridFileError::Header(format!(
+                    "dtype {descr} is not float32 or float64"
+                )));
+            }
+        };
+        let item = if descr.ends_with('4') { 4 } else { 8 };
+        let expected_bytes =
+            (expected.checked_mul(item)).ok_or(GridFileError::TooLarge((width, height, depth)))?;
+        if data.len() != expected_bytes {
+            return Err(GridFileError::Length {
+                expected,
+                found: data.len() / item,
+            });
+        }
+
+        let mut clamped = 0;
+        let probs = data
+            .chunks_exact(item)
+            .map(|b| {
+                let p = decode(b);
+                if (0.0..=1.0).contains(&p) {
+                    p
+                } else {
+                    clamped += 1;
+                    if p.is_nan() { 0.0 } else { p.clamp(0.0, 1.0) }
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+            probs,
+            clamped,
+        })
+    }
+
+    // Builds a grid of the configured size, which the map must have
+    pub fn build(
+        &self,
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+    ) -> Result<CAContext, GridFileError> {
+        self.fits(width, height, depth)?;
+        CAContext::random_from_probability_map(&self.probs, (width, height, depth), seed)
+    }
+
+    pub fn fits(&self, width: usize, height: usize, depth: usize) -> Result<(), GridFileError> {
+        let found = (self.width, self.height, self.depth);
+        if found == (width, height, depth) {
+            Ok(())
+        } else {
+            Err(GridFileError::Size(DimensionMismatch {
+                expected: (width, height, depth),
+                found,
+            }))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawDims {
+    width: usize,
+    height: usize,
+    depth: usize,
+}
+
+// Cells of a width x height x depth grid read from a file, whose dimensions are untrusted
+fn cell_count(width: usize, height: usize, depth: usize) -> Result<usize, GridFileError> {
+    (width.checked_mul(height))
+        .and_then(|n| n.checked_mul(depth))
+        .ok_or(GridFileError::TooLarge((width, height, depth)))
+}
+
+fn raw_sidecar(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+// Symmetries of CAContext::random_symmetric. Mirrors reflect across the midplanes, the
+// rotation turns the grid half way around the z axis through its center
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Symmetry {
+    MirrorX,
+    MirrorXy,
+    RotationalZ2,
+}
+
+impl Symmetry {
+    // Where column (x, y) maps to, padded with itself where the group is smaller
+    fn images(self, x: usize, y: usize, width: usize, height: usize) -> [(usize, usize); 3] {
+        let (mx, my) = (width - 1 - x, height - 1 - y);
+        match self {
+            Symmetry::MirrorX => [(mx, y), (x, y), (x, y)],
+            Symmetry::MirrorXy => [(mx, y), (x, my), (mx, my)],
+            Symmetry::RotationalZ2 => [(mx, my), (x, y), (x, y)],
+        }
+    }
+}
+
+// How the starting grid of a run is filled
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Initializer {
+    // Uniform noise with the configured air percentage
+    #[default]
+    Random,
+    // A fixed number of random air cells
+    Points {
+        count: usize,
+    },
+    // Air probability varying linearly with depth, see CAContext::from_gradient
+    Gradient {
+        prob_top: f64,
+        prob_bottom: f64,
+    },
+    // Boxes with their own air probability, the air percentage fills the rest. See
+    // CAContext::from_regions
+    Regions {
+        regions: Vec<DensityRegion>,
+    },
+    // Air probability over depth from (z fraction, probability) control points, see
+    // CAContext::random_gradient
+    DepthProfile {
+        points: Vec<(f64, f64)>,
+    },
+    // Uniform noise mirrored or rotated onto itself, see CAContext::random_symmetric
+    Symmetric {
+        symmetry: Symmetry,
+    },
+    // Horizontal strata with their own air probability, see CAContext::random_layers
+    Layers {
+        layers: Vec<Layer>,
+    },
+    // Random-walk tunnels through sparse noise, see CAContext::from_walks
+    Walks(WalkParams),
+    // Read from a .vox file, see VoxInit
+    Vox(VoxInit),
+    // Read from CAContext::to_npy or to_raw output, which must match the configured grid
+    Npy {
+        path: PathBuf,
+    },
+    Raw {
+        path: PathBuf,
+    },
+    // Air with a per-cell probability from a float .npy, see ProbabilityMap
+    ProbMap {
+        path: PathBuf,
+    },
+    // Thresholded fractal Perlin noise, see CAContext::from_noise
+    #[serde(alias = "perlin")]
+    Noise(NoiseParams),
+}
+
+impl Initializer {
+    // Like build, with the shell kept solid. Random fills only the interior, every other
+    // initializer fills the whole grid before the shell is cleared. A file-based initializer
+    // builds from `file` when given instead of reading its file again
+    #[must_use]
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_with_shell(
+        &self,
+        file: Option<&InitFile>,
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_percentage: f64,
+        shell: &ShellSpec,
+    ) -> CAContext {
+        if matches!(self, Initializer::Random) {
+            return CAContext::random_with_shell(width, height, depth, seed, air_percentage, shell);
+        }
+
+        let mut ctx = match file {
+            Some(file) => file.build(width, height, depth, seed),
+            None => self.build(width, height, depth, seed, air_percentage),
+        };
+        if !shell.is_empty() {
+            ctx.apply_shell(shell);
+        }
+        ctx
+    }
+
+    // The box frozen layers leave to evolve, None when nothing is frozen
+    #[must_use]
+    pub fn live_region(&self, width: usize, height: usize, depth: usize) -> Option<Region> {
+        let Initializer::Layers { layers } = self else {
+            return None;
+        };
+        Layer::live_slices(layers, depth)
+            .map(|z| Region::new((0, 0, z.start), (width, height, z.end)))
+    }
+
+    // Reads the file of a file-based initializer and checks it against the configured size,
+    // None for the initializers without a file
+    pub fn load_file(
+        &self,
+        width: usize,
+        height: usize,
+        depth: usize,
+    ) -> Result<Option<InitFile>, Box<dyn std::error::Error>> {
+        let grid = match self {
+            Initializer::Vox(init) => init.load(width, height, depth)?,
+            Initializer::Npy { path } => CAContext::from_npy(path)?,
+            Initializer::Raw { path } => CAContext::from_raw(path)?,
+            Initializer::ProbMap { path } => {
+                let map = ProbabilityMap::from_npy(path)?;
+                map.fits(width, height, depth)?;
+                return Ok(Some(InitFile::ProbMap(Arc::new(map))));
+            }
+            _ => return Ok(None),
+        };
+
+        let found = (grid.width, grid.height, grid.depth);
+        if found != (width, height, depth) {
+            return Err(Box::new(GridFileError::Size(DimensionMismatch {
+                expected: (width, height, depth),
+                found,
+            })));
+        }
+        Ok(Some(InitFile::Grid(Arc::new(grid))))
+    }
+
+    #[must_use]
+    pub fn build(
+        &self,
+        width: usize,
+        height: usize,
+        depth: usize,
+        seed: u64,
+        air_percentage: f64,
+    ) -> CAContext {
+        match self {
+            Initializer::Random => CAContext::random(width, height, depth, seed, air_percentage),
+            Initializer::Symmetric { symmetry } => {
+                CAContext::random_symmetric(width, height, depth, seed, air_percentage, *symmetry)
+            }
+            Initializer::Points { count } => {
+                CAContext::random_points(width, height, depth, seed, *count)
+            }
+            Initializer::Gradient {
+                prob_top,
+                prob_bottom,
+            } => CAContext::from_gradient(width, height, depth, seed, *prob_top, *prob_bottom),
+            Initializer::Regions { regions } => {
+                CAContext::from_regions(width, height, depth, seed, air_percentage, regions)
+            }
+            Initializer::DepthProfile { points } => {
+                CAContext::random_gradient(width, height, depth, seed, points)
+            }
+            Initializer::Layers { layers } => {
+                CAContext::random_layers(width, height, depth, seed, layers)
+            }
+            Initializer::Noise(params) => {
+                CAContext::from_noise(width, height, depth, seed, air_percentage, params)
+            }
+            Initializer::Walks(params) => CAContext::from_walks(width, height, depth, seed, params),
+            Initializer::Vox(VoxInit { path, .. })
+            | Initializer::ProbMap { path }
+            | Initializer::Npy { path }
+            | Initializer::Raw { path } => self
+                .load_file(width, height, depth)
+                .unwrap_or_else(|e| panic!("Failed to load {}: {e}", path.display()))
+                .expect("file-based initializers load a file")
+                .build(width, height, depth, seed),
+        }
+    }
+}
+
+// What a file-based initializer read from disk. The runner loads it once and shares it
+// between all runs
+#[derive(Clone, Debug)]
+pub enum InitFile {
+    Grid(Arc<CAContext>),
+    ProbMap(Arc<ProbabilityMap>),
+}
+
+impl InitFile {
+    // The starting grid for `seed`, the size was checked by Initializer::load_file
+    #[must_use]
+    pub fn build(&self, width: usize, height: usize, depth: usize, seed: u64) -> CAContext {
+        match self {
+            InitFile::Grid(grid) => CAContext::clone(grid),
+            InitFile::ProbMap(map) => map
+                .build(width, height, depth, seed)
+                .expect("probability map was checked on load"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineMode {
+    // Incremental for neighborhoods larger than Moore, dense otherwise
+    #[default]
+    Auto,
+    // Recompute every cell each iteration
+    Dense,
+    // Recompute only cells near last iteration's changes. Also accepted as "active_list", the
+    // active set is rebuilt from the cells that flipped in the previous iteration
+    #[serde(alias = "active_list")]
+    Sparse,
+    // Cache every cell's neighbor count and only patch it around flipped cells
+    Incremental,
+}
+
+impl fmt::Display for EngineMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineMode::Auto => write!(f, "auto"),
+            EngineMode::Dense => write!(f, "dense"),
+            EngineMode::Sparse => write!(f, "sparse"),
+            EngineMode::Incremental => write!(f, "incremental"),
+        }
+    }
+}
+
+// Why a run ended
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    // Every requested iteration ran
+    #[default]
+    Completed,
+    // The time limit elapsed before the last iteration
+    TimeLimit,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Completed => write!(f, "completed"),
+            StopReason::TimeLimit => write!(f, "time_limit"),
+        }
+    }
+}
+
+// What a run did. per_iter[k] describes the grid after k iterations, starting with the initial
+// grid, so it holds iterations_run + 1 entries
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EngineReport {
+    pub iterations_run: usize,
+    pub per_iter: Vec<IterationStats>,
+    pub wall_time: Duration,
+    pub stop_reason: StopReason,
+}
+
+impl EngineReport {
+    // Iteration count after which the grid no longer changed for the rest of the run, None if
+    // the last iteration still flipped cells
+    #[must_use]
+    pub fn converged_at(&self) -> Option<usize> {
+        let last_change = (self.per_iter.iter())
+            .rposition(|stats| stats.changed > 0)
+            .unwrap_or(0);
+        (last_change < self.iterations_run).then_some(last_change)
+    }
+
+    // Fraction of the `cells` cells flipped by the last iteration. Near 0 the grid has frozen,
+    // high values mean it is still oscillating or chaotic
+    #[must_use]
+    pub fn final_activity(&self, cells: usize) -> f64 {
+        match self.per_iter.last() {
+            Some(last) if cells > 0 => last.changed as f64 / cells as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MismatchReport {
+    pub iter: usize,
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+    pub neighbors: usize,
+    pub expected: CACell,
+    pub actual: CACell,
+}
+
+impl fmt::Display for MismatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mismatch at iter={} ({}, {}, {}) neighbors={}: expected {}, got {}",
+            self.iter, self.x, self.y, self.z, self.neighbors, self.expected.0, self.actual.0
+        )
+    }
+}
+
+impl std::error::Error for MismatchReport {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryError {
+    Disabled,
+    Empty,
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Disabled => write!(f, "history is not enabled on this engine"),
+            HistoryError::Empty => write!(f, "no history left to step back into"),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionError {
+    Empty { axis: Axis, min: usize, max: usize },
+    OutOfBounds { axis: Axis, max: usize, len: usize },
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Empty { axis, min, max } => {
+                write!(f, "region is empty along {axis:?}: min {min} >= max {max}")
+            }
+            RegionError::OutOfBounds { axis, max, len } => {
+                write!(
+                    f,
+                    "region max {max} along {axis:?} exceeds the grid size {len}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
+
+// Slice ranges are half-open, z = 0 is the top
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayerError {
+    Empty(Range<usize>),
+    Gap(Range<usize>),
+    Overlap(Range<usize>),
+    // The bottom layer ends at `end` instead of the grid depth
+    Coverage { end: usize, depth: usize },
+    AllFrozen,
+    FrozenBetween(Range<usize>),
+}
+
+impl fmt::Display for LayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayerError::Empty(z) => write!(f, "layer at slices {z:?} is empty"),
+            LayerError::Gap(z) => write!(f, "no layer covers slices {z:?}"),
+            LayerError::Overlap(z) => write!(f, "layers overlap at slices {z:?}"),
+            LayerError::Coverage { end, depth } => {
+                write!(f, "layers end at slice {end} of a grid {depth} deep")
+            }
+            LayerError::AllFrozen => write!(f, "every layer is frozen, nothing would evolve"),
+            LayerError::FrozenBetween(z) => write!(
+                f,
+                "frozen layer at slices {z:?} lies between evolving ones, only the top and bottom layers can be frozen"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LayerError {}
+
+// Grid sizes as (width, height, depth)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DimensionMismatch {
+    pub expected: (usize, usize, usize),
+    pub found: (usize, usize, usize),
+}
+
+impl fmt::Display for DimensionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (w, h, d) = self.expected;
+        let (fw, fh, fd) = self.found;
+        write!(f, "expected a {w}x{h}x{d} grid, got {fw}x{fh}x{fd}")
+    }
+}
+
+impl std::error::Error for DimensionMismatch {}
+
+#[derive(Debug)]
+pub enum GridFileError {
+    Io(std::io::Error),
+    // Malformed .npy header or raw sidecar
+    Header(String),
+    // The data holds a different number of cells than its header, sidecar or size promise
+    Length { expected: usize, found: usize },
+    Size(DimensionMismatch),
+    // Header or sidecar dimensions whose cell or byte count overflows usize
+    TooLarge((usize, usize, usize)),
+}
+
+impl fmt::Display for GridFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridFileError::Io(e) => write!(f, "{e}"),
+            GridFileError::Header(what) => write!(f, "bad grid header: {what}"),
+            GridFileError::Length { expected, found } if found < expected => {
+                write!(f, "truncated grid: {found} of {expected} cells")
+            }
+            GridFileError::Length { expected, found } => {
+                write!(f, "grid holds {found} cells, expected {expected}")
+            }
+            GridFileError::Size(e) => write!(f, "grid file does not fit the grid: {e}"),
+            GridFileError::TooLarge((w, h, d)) => write!(f, "grid of {w}x{h}x{d} is too large"),
+        }
+    }
+}
+
+impl std::error::Error for GridFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GridFileError::Io(e) => Some(e),
+            GridFileError::Size(e) => Some(e),
+            GridFileError::Header(_)
+            | GridFileError::Length { .. }
+            | GridFileError::TooLarge(_) => None,
+        }
+    }
+}
+
+// Corners of the box covering every model and voxel of a .vox file, None when it is empty
+fn vox_bounds(models: &[crate::data::VoxModel]) -> Option<([i32; 3], [i32; 3])> {
+    let (mut min, mut max) = ([i32::MAX; 3], [i32::MIN; 3]);
+    let mut cover = |p: [i32; 3]| {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(p[axis]);
+            max[axis] = max[axis].max(p[axis]);
+        }
+    };
+    for model in models {
+        let (ox, oy, oz) = model.origin;
+        let (sx, sy, sz) = model.size;
+        if sx > 0 && sy > 0 && sz > 0 {
+            cover([ox, oy, oz]);
+            cover([ox + sx - 1, oy + sy - 1, oz + sz - 1]);
+        }
+        model.positions().for_each(&mut cover);
+    }
+    (min[0] <= max[0]).then_some((min, max))
+}
+
+#[derive(Debug)]
+pub enum VoxLoadError {
+    Io(std::io::Error),
+    // No model holds a voxel or has a size
+    Empty,
+    Size(DimensionMismatch),
+}
+
+impl fmt::Display for VoxLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxLoadError::Io(e) => write!(f, "{e}"),
+            VoxLoadError::Empty => write!(f, "vox file has no voxels"),
+            VoxLoadError::Size(e) => write!(f, "vox file does not fit the grid: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for VoxLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VoxLoadError::Io(e) => Some(e),
+            VoxLoadError::Size(e) => Some(e),
+            VoxLoadError::Empty => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(birth: &[usize], survival: &[usize]) -> CARule {
+        CARule {
+            name: "test".to_string(),
+            rule_type: CARuleType::Standard {
+                birth: birth.to_vec(),
+                survival: survival.to_vec(),
+            },
+            temperature: None,
+        }
+    }
+
+    fn config(neighborhood: CANeighborhood, rule: CARule, boundary: Boundary) -> CAConfig {
+        CAConfig {
+            neighborhood,
+            rule,
+            boundary,
+            count_states: CountStates::default(),
+            include_self: false,
+        }
+    }
+
+    #[test]
+    fn periodic_axis_wraps_opposite_faces() {
+        let boundary = Boundary::preset("tube_x").unwrap();
+        let initial = CAContext::random(12, 10, 8, 3, 0.5);
+        let config = config(
+            CANeighborhood::moore(),
+            rule(&[6, 7, 8], &[5, 6, 7, 8]),
+            boundary,
+        );
+
+        let mut engine = CAEngine::new(config.clone(), initial.clone());
+        let mut shifted = CAEngine::new(config, initial.rolled(Axis::X, 5));
+        engine.run(4, &mut Vec::new());
+        shifted.run(4, &mut Vec::new());
+
+        // Column 0 sees the last column as its neighbor, so evolving commutes with rolling x
+        assert_eq!(engine.context().rolled(Axis::X, 5), *shifted.context());
+    }
+
+    #[test]
+    fn structure_across_periodic_seam_is_one_component() {
+        let mut ctx = CAContext::new(12, 6, 6);
+        for x in (0..3).chain(8..12) {
+            ctx.set(x, 3, 3, CACell::new(1));
+        }
+
+        let tube = Boundary::preset("tube_x").unwrap();
+        assert_eq!(ctx.connected_components_with(&tube).len(), 1);
+        assert_eq!(ctx.connected_components_with(&Boundary::default()).len(), 2);
+    }
+
+    #[test]
+    fn saved_vox_loads_back_identically() {
+        let dir = std::env::temp_dir().join("gradwork-ca-vox-round-trip");
+        // 130 is split into two 126-cell models along x
+        for (width, height, depth) in [(20, 30, 10), (130, 5, 7)] {
+            let ctx = CAContext::random(width, height, depth, 7, 0.5);
+            let metadata = crate::data::RunMetadata::new(
+                7,
+                "moore".to_string(),
+                width,
+                height,
+                depth,
+                0,
+                "B".to_string(),
+                0.5,
+            );
+            crate::data::RunInfo::new(metadata, ctx.clone())
+                .save(&dir)
+                .unwrap();
+
+            let init = VoxInit {
+                path: dir.join("grid.vox"),
+                voxels: VoxelState::default(),
+            };
+            assert_eq!(init.load(width, height, depth).unwrap(), ctx);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probability_map_sets_air_fraction_per_region() {
+        let (width, height, depth) = (32, 32, 16);
+        // Lower half 0.2, upper half 0.8, with the upper half written as out-of-range values
+        // for the top quarter to check the clamping
+        let map: Vec<f32> = (0..width * height * depth)
+            .map(|i| match i / (width * height) {
+                z if z < depth / 2 => 0.2,
+                z if z < depth * 3 / 4 => 0.8,
+                _ => 1.5,
+            })
+            .collect();
+
+        let ctx = CAContext::random_from_probability_map(&map, (width, height, depth), 9).unwrap();
+        let air_fraction = |zs: Range<usize>| {
+            let layer = width * height;
+            let cells = &ctx.cells()[zs.start * layer..zs.end * layer];
+            cells.iter().filter(|c| c.is_air()).count() as f64 / cells.len() as f64
+        };
+        assert!((air_fraction(0..depth / 2) - 0.2).abs() < 0.02);
+        assert!((air_fraction(depth / 2..depth * 3 / 4) - 0.8).abs() < 0.02);
+        assert!((air_fraction(depth * 3 / 4..depth) - 1.0).abs() < f64::EPSILON);
+
+        let again = CAContext::random_from_probability_map(&map, (width, height, depth), 9);
+        assert_eq!(again.unwrap(), ctx);
+    }
+
+    #[test]
+    fn random_fill_is_independent_of_thread_count() {
+        let pools = [1, 4].map(|threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        });
+        let keys: Vec<u64> = (0..50_000)
+            .map(|i| CellRng::new(7, 0, i).next_u64())
+            .collect();
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+
+        for n in [0, 1, 12_345, 25_000, 49_999] {
+            let thresholds = pools
+                .each_ref()
+                .map(|pool| pool.install(|| nth_smallest_key(&keys, n)));
+            assert_eq!(thresholds, [sorted[n]; 2], "key {n}");
+        }
+        for air in [0.0, 0.37, 1.0] {
+            let [one, four] = pools
+                .each_ref()
+                .map(|pool| pool.install(|| CAContext::random(40, 30, 20, 5, air)));
+            assert_eq!(one, four, "air {air}");
+            assert_eq!(one.total_air_cells(), (24_000.0 * air).round() as usize);
+        }
+    }
+
+    #[test]
+    fn noise_fill_hits_the_air_fraction() {
+        let params = NoiseParams {
+            frequency: 4.0,
+            octaves: 3,
+            threshold: None,
+        };
+        let ctx = CAContext::from_noise(32, 24, 16, 11, 0.4, &params);
+        let air = ctx.total_air_cells() as f64 / ctx.cells().len() as f64;
+        assert!((air - 0.4).abs() < 0.01, "{air}");
+        assert_eq!(ctx.fingerprint(), 16_959_805_308_991_482_016);
+
+        // A fixed threshold ignores the requested fraction
+        let fixed = NoiseParams {
+            threshold: Some(0.0),
+            ..params
+        };
+        let low = CAContext::from_noise(32, 24, 16, 11, 0.1, &fixed);
+        assert_eq!(low, CAContext::from_noise(32, 24, 16, 11, 0.9, &fixed));
+    }
+
+    #[test]
+    fn parallel_labeling_matches_serial_flood_fill() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let sorted = |mut components: Vec<Vec<usize>>| {
+            components.iter_mut().for_each(|c| c.sort_unstable());
+            components
+        };
+
+        for seed in 0..8 {
+            let ctx = CAContext::random(17, 13, 11, seed, 0.3 + 0.05 * seed as f64);
+            for preset in ["solid", "periodic", "tube_x", "tube_z"] {
+                let boundary = Boundary::preset(preset).unwrap();
+                let serial = sorted(ctx.serial_connected_components_with(&boundary));
+                // Four threads split the 11 layers into several runs with borders to merge
+                let parallel = pool.install(|| ctx.par_connected_components_with(&boundary));
+                assert_eq!(parallel, serial, "seed {seed}, {preset}");
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_counts_match_dense() {
+        let neighborhoods = [
+            "von_neumann",
+            "moore",
+            "layered_moore",
+            "cross_2d",
+            "fcc",
+            "bcc",
+            "extended_moore(2)",
+            "extended_von_neumann(2)",
+        ];
+        let initial = CAContext::random(14, 12, 10, 5, 0.45);
+
+        for name in neighborhoods {
+            let nb: CANeighborhood = name.parse().unwrap();
+            // Scaled from B5678/S45678 so every neighborhood keeps flipping cells
+            let n = nb.len();
+            let birth: Vec<usize> = (n * 5 / 9..=n).collect();
+            let survival: Vec<usize> = (n * 4 / 9..=n).collect();
+
+            for preset in Boundary::PRESETS {
+                let boundary = Boundary::preset(preset).unwrap();
+                let config = config(nb.clone(), rule(&birth, &survival), boundary);
+                let run = |mode| {
+                    let mut engine = CAEngine::new(config.clone(), initial.clone()).with_mode(mode);
+                    engine.run(6, &mut Vec::new());
+                    engine.context().clone()
+                };
+                let dense = run(EngineMode::Dense);
+                assert_ne!(dense, initial, "{name}, {preset} never flips a cell");
+                assert_eq!(run(EngineMode::Incremental), dense, "{name}, {preset}");
+            }
+        }
+    }
+
+    #[test]
+    fn cells_outside_region_stay_identical() {
+        let initial = CAContext::random(16, 16, 12, 11, 0.55);
+        let region = Region::new((3, 4, 2), (13, 12, 10));
+        let first = config(
+            CANeighborhood::moore(),
+            rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]),
+            Boundary::default(),
+        );
+        let second = config(
+            CANeighborhood::von_neumann(),
+            rule(&[2, 3], &[1, 2, 3]),
+            Boundary::default(),
+        );
+
+        let mut engine = CAEngine::new(first.clone(), initial.clone());
+        engine.set_region(Some(region)).unwrap();
+        for composition in [
+            Composition::Sequential,
+            Composition::Union,
+            Composition::Intersection,
+        ] {
+            engine.run_iteration_composed(&[&first, &second], composition);
+        }
+        engine.run_iteration_with(&second);
+        engine.remove_isolated_cells();
+        engine.remove_dead_ends(3);
+        engine.ensure_percolates(Axis::X);
+
+        let ctx = engine.context();
+        assert_ne!(*ctx, initial);
+        for (i, (&cell, &before)) in ctx.cells().iter().zip(initial.cells()).enumerate() {
+            let (x, y, z) = ctx.pos(i);
+            if !region.contains(x, y, z) {
+                assert_eq!(
+                    cell, before,
+                    "cell ({x}, {y}, {z}) outside the region changed"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn periodic_percolation_is_carved_as_a_loop() {
+        // A tunnel that joins the x faces at different heights, so it does not wrap
+        let mut ctx = CAContext::new(10, 6, 4);
+        for x in 0..10 {
+            ctx.set(x, if x < 5 { 0 } else { 3 }, 0, CACell::new(1));
+        }
+        for y in 0..3 {
+            ctx.set(5, y, 0, CACell::new(1));
+        }
+        let wraps = |ctx: &CAContext, boundary: &Boundary| {
+            ctx.percolates_with(&ctx.connected_components_with(boundary), Axis::X, boundary)
+        };
+
+        let solid = Boundary::default();
+        let tube = Boundary::preset("tube_x").unwrap();
+        assert!(wraps(&ctx, &solid));
+        assert!(!wraps(&ctx, &tube));
+        assert_eq!(ctx.clone().ensure_percolates(Axis::X, &solid, |_| false), 0);
+
+        // Closing the loop across the seam takes (0, 1..4, 0)
+        assert_eq!(ctx.ensure_percolates(Axis::X, &tube, |_| false), 3);
+        assert!(wraps(&ctx, &tube));
+        assert!((1..4).all(|y| ctx.get(0, y, 0).is_air()));
+
+        let mut rock = CAContext::new(10, 6, 4);
+        assert_eq!(rock.ensure_percolates(Axis::X, &tube, |_| false), 10);
+        assert!(wraps(&rock, &tube));
+    }
+
+    #[test]
+    fn frozen_shafts_stay_open() {
+        let params = EntranceParams {
+            count: 3,
+            radius: 0,
+            depth: 6,
+            frozen: true,
+        };
+        let mut initial = CAContext::random(12, 12, 10, 2, 0.2);
+        let positions = params.positions(12, 12, 2);
+        let shafts = initial.carve_entrances(&params, &positions);
+
+        // Nothing survives or is born, so only the frozen shafts are left as air
+        let config = config(CANeighborhood::moore(), rule(&[], &[]), Boundary::default());
+        let mut engine = CAEngine::new(config.clone(), initial);
+        engine.set_frozen(Some(shafts.clone()));
+        engine.run(2, &mut Vec::new());
+        engine.run_iteration_with(&config);
+        engine.run_iteration_composed(&[&config, &config], Composition::Sequential);
+        // A lone radius-0 shaft is a dead end at both tips
+        engine.remove_isolated_cells();
+        engine.remove_dead_ends(10);
+        engine.ensure_percolates(Axis::Z);
+
+        let ctx = engine.context();
+        assert!((shafts.iter().zip(ctx.cells())).all(|(&shaft, cell)| !shaft || cell.is_air()));
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn canonical_name_includes_temperature_of_soft_rules() {
+        let mut soft = rule(&[5, 6, 7], &[4, 5]);
+        assert_eq!(soft.canonical_name(), "B5.6.7_S4.5");
+        soft.temperature = Some(0.0);
+        assert_eq!(soft.canonical_name(), "B5.6.7_S4.5");
+        soft.temperature = Some(0.5);
+        assert_eq!(soft.canonical_name(), "B5.6.7_S4.5_t0.5");
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn mutated_rules_stay_in_range_and_round_trip() {
+        use rand::{SeedableRng, rngs::SmallRng};
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        let neighborhoods = [
+            CANeighborhood::von_neumann(),
+            CANeighborhood::moore(),
+            CANeighborhood::extended_moore(2),
+        ];
+        for nb in neighborhoods {
+            let max = nb.len();
+            let mut soft = rule(&[5, 6, 7], &[4, 5]);
+            soft.temperature = Some(0.5);
+            let threshold = CARule {
+                rule_type: CARuleType::Threshold(max / 2),
+                ..rule(&[], &[])
+            };
+            let parents = [
+                rule(&[5, 6, 7], &[4, 5, 6, 7, 8]),
+                soft,
+                threshold,
+                CARule::erosion(3),
+            ];
+
+            for parent in parents {
+                let mut current = parent;
+                for i in 0..200 {
+                    let mutated = current.mutate(&mut rng, f64::from(i % 5) / 4.0, max);
+                    assert!(mutated.max_count() <= max);
+                    // Two in-range parents can only have an in-range child
+                    let child = mutated.crossover(&current.mutate(&mut rng, 0.0, max), &mut rng);
+                    assert!(child.max_count() <= max);
+
+                    let name = mutated.canonical_name();
+                    let crisp = match name.rsplit_once("_t") {
+                        Some((crisp, _)) if mutated.is_soft() => crisp,
+                        _ => &name,
+                    };
+                    assert_eq!(crisp.parse::<CARuleType>(), Ok(mutated.rule_type.clone()));
+                    current = mutated;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn erosion_only_runs_on_faces() {
+        let erosion = CARule::erosion(3);
+        assert!(
+            erosion
+                .validate(&CANeighborhood::von_neumann(), false)
+                .is_ok()
+        );
+        for nb in [CANeighborhood::moore(), CANeighborhood::cross_2d()] {
+            let problems = erosion.validate(&nb, false).unwrap_err().problems;
+            assert!(problems.contains(&RuleProblem::ErosionNeighborhood));
+        }
+
+        // A lone rock voxel has all six faces exposed, a wall interior only the two sides
+        let mut ctx = CAContext::new(9, 9, 9);
+        ctx.cells_mut().fill(CACell::new(1));
+        ctx.set(4, 4, 4, CACell::new(0));
+        for (y, z) in (2..7).flat_map(|y| (2..7).map(move |z| (y, z))) {
+            ctx.set(1, y, z, CACell::new(0));
+        }
+        let config = config(CANeighborhood::von_neumann(), erosion, Boundary::default());
+        let mut engine = CAEngine::new(config, ctx);
+        engine.run(1, &mut Vec::new());
+        assert!(engine.context().get(4, 4, 4).is_air());
+        assert!(!engine.context().get(1, 4, 4).is_air());
+    }
+
+    #[test]
+    fn symmetric_start_stays_symmetric() {
+        let (width, height, depth) = (15, 12, 8);
+        for symmetry in [
+            Symmetry::MirrorX,
+            Symmetry::MirrorXy,
+            Symmetry::RotationalZ2,
+        ] {
+            let initial = CAContext::random_symmetric(width, height, depth, 4, 0.5, symmetry);
+            let config = config(
+                CANeighborhood::moore(),
+                rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]),
+                Boundary::default(),
+            );
+            let mut engine = CAEngine::new(config, initial.clone());
+            engine.run(8, &mut Vec::new());
+
+            let ctx = engine.context();
+            assert_ne!(*ctx, initial);
+            for (x, y, z) in (0..ctx.cells().len()).map(|i| ctx.pos(i)) {
+                for (ix, iy) in symmetry.images(x, y, width, height) {
+                    assert_eq!(ctx.get(x, y, z), ctx.get(ix, iy, z), "{symmetry:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stamps_fill_their_shape_inside_the_region() {
+        let before = CAContext::random(20, 20, 12, 3, 0.2);
+        let stamp = Stamp {
+            shape: StampShape::Sphere {
+                center: [0.5, 0.5, 0.5],
+                radius: 0.3,
+            },
+            units: StampUnits::Fraction,
+        };
+        let region = Region::new((0, 0, 4), (20, 20, 12));
+
+        let mut ctx = before.clone();
+        let stamped = ctx.stamp_within(&stamp, CACell::new(1), Some(region));
+        let shape = stamp.in_cells(20, 20, 12);
+        let mut inside = 0;
+        for (i, cell) in ctx.cells().iter().enumerate() {
+            let (x, y, z) = ctx.pos(i);
+            if shape.contains([x as f64, y as f64, z as f64]) && region.contains(x, y, z) {
+                assert!(cell.is_air(), "({x}, {y}, {z})");
+                inside += 1;
+            } else {
+                assert_eq!(*cell, before.cells()[i], "({x}, {y}, {z})");
+            }
+        }
+        assert_eq!(stamped, inside);
+        assert!(inside > 0);
+
+        let mut unbounded = before.clone();
+        assert!(unbounded.stamp(&stamp, CACell::new(1)) > stamped);
+    }
+
+    #[test]
+    fn dead_ends_of_a_t_shape() {
+        // A corridor along x with a stem of three cells leaving it at x = 4
+        let mut ctx = CAContext::new(9, 6, 3);
+        for x in 0..9 {
+            ctx.set(x, 1, 1, CACell::new(1));
+        }
+        for y in 2..5 {
+            ctx.set(4, y, 1, CACell::new(1));
+        }
+
+        let tips = [(0, 1, 1), (8, 1, 1), (4, 4, 1)];
+        let mut dead_ends: Vec<_> = (ctx.dead_end_cells(Connectivity::Six).into_iter())
+            .map(|i| ctx.pos(i))
+            .collect();
+        dead_ends.sort_unstable();
+        let mut expected = tips.to_vec();
+        expected.sort_unstable();
+        assert_eq!(dead_ends, expected);
+        assert_eq!(crate::metrics::count_dead_ends(&ctx, Connectivity::Six), 3);
+
+        // Every cell but the tips is a cut vertex, the stem hangs off the corridor at (4, 1, 1)
+        let cuts = crate::metrics::articulation_points(&ctx, Connectivity::Six);
+        assert_eq!(cuts.len(), 9);
+        assert!(cuts.contains(&(4, 1, 1)));
+
+        let mut once = ctx.clone();
+        assert_eq!(once.remove_dead_ends(1, |_| false), 3);
+        assert!(tips.iter().all(|&(x, y, z)| !once.get(x, y, z).is_air()));
+
+        // Three passes take the stem back to the corridor, the fourth leaves the junction alone
+        assert_eq!(ctx.remove_dead_ends(3, |_| false), 9);
+        assert_eq!(ctx.total_air_cells(), 3);
+        assert_eq!(ctx.remove_dead_ends(5, |_| false), 2);
+        assert_eq!(ctx.total_air_cells(), 1);
+        assert!(ctx.get(4, 1, 1).is_air());
+    }
+
+    #[test]
+    fn layer_solid_fractions_match_their_probabilities() {
+        let layer = |z_from, z_to, air_prob| Layer {
+            z_from,
+            z_to,
+            air_prob,
+            frozen: false,
+            units: StampUnits::Fraction,
+        };
+        let layers = [
+            layer(0.0, 0.25, 0.1),
+            layer(0.25, 0.75, 0.5),
+            layer(0.75, 1.0, 0.8),
+        ];
+        let (width, height, depth) = (40, 40, 16);
+        assert_eq!(Layer::check_stack(&layers, depth), Ok(()));
+
+        let ctx = CAContext::random_layers(width, height, depth, 6, &layers);
+        for l in &layers {
+            let zs = l.z_range(depth);
+            let slab = width * height;
+            let cells = &ctx.cells()[zs.start * slab..zs.end * slab];
+            let solid = cells.iter().filter(|c| !c.is_air()).count() as f64 / cells.len() as f64;
+            assert!((solid - (1.0 - l.air_prob)).abs() < 0.03, "{zs:?}: {solid}");
+        }
+    }
+
+    #[test]
+    fn npy_and_raw_round_trip() {
+        let dir = std::env::temp_dir().join("gradwork-ca-grid-files");
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = CAContext::random(7, 5, 3, 8, 0.5);
+
+        ctx.to_npy(&dir.join("grid.npy")).unwrap();
+        assert_eq!(CAContext::from_npy(&dir.join("grid.npy")).unwrap(), ctx);
+        ctx.to_raw(&dir.join("grid.raw")).unwrap();
+        assert_eq!(CAContext::from_raw(&dir.join("grid.raw")).unwrap(), ctx);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Written by hand to the NumPy format 1.0 spec, as np.save writes them
+    #[test]
+    fn npy_golden_files() {
+        let dir = std::env::temp_dir().join("gradwork-ca-npy-golden");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // (2, 3, 4) uint8 of alternating 0 and 1
+        let golden = include_bytes!("../testdata/grid_u1.npy");
+        let mut ctx = CAContext::new(4, 3, 2);
+        for i in (1..24).step_by(2) {
+            ctx[i] = CACell::new(1);
+        }
+        ctx.to_npy(&dir.join("grid.npy")).unwrap();
+        assert_eq!(std::fs::read(dir.join("grid.npy")).unwrap(), golden);
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/grid_u1.npy");
+        assert_eq!(CAContext::from_npy(&path).unwrap(), ctx);
+
+        // (1, 2, 2) little-endian float32 of 0.25, 1.5, -1 and 0.5
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/probs_f4.npy");
+        let map = ProbabilityMap::from_npy(&path).unwrap();
+        assert_eq!((map.width, map.height, map.depth), (2, 2, 1));
+        assert_eq!((map.probs, map.clamped), (vec![0.25, 1.0, 0.0, 0.5], 2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_grid_files_are_rejected() {
+        let dir = std::env::temp_dir().join("gradwork-ca-oversized");
+        std::fs::create_dir_all(&dir).unwrap();
+        let huge = 1usize << (usize::BITS / 2);
+
+        let write_npy = |name: &str, descr: &str| {
+            let header = format!(
+                "{{'descr': '{descr}', 'fortran_order': False, 'shape': ({huge}, {huge}, 2), }}\n"
+            );
+            let mut npy = NPY_MAGIC.to_vec();
+            npy.extend([1, 0]);
+            npy.extend((header.len() as u16).to_le_bytes());
+            npy.extend(header.as_bytes());
+            std::fs::write(dir.join(name), &npy).unwrap();
+            dir.join(name)
+        };
+        let too_large = |e| matches!(e, GridFileError::TooLarge(_));
+        let path = write_npy("huge_u1.npy", "|u1");
+        assert!(CAContext::from_npy(&path).is_err_and(too_large));
+        let path = write_npy("huge_f4.npy", "<f4");
+        assert!(ProbabilityMap::from_npy(&path).is_err_and(too_large));
+
+        std::fs::write(dir.join("huge.raw"), []).unwrap();
+        let sidecar = format!(r#"{{"width": {huge}, "height": {huge}, "depth": 1}}"#);
+        std::fs::write(raw_sidecar(&dir.join("huge.raw")), sidecar).unwrap();
+        assert!(CAContext::from_raw(&dir.join("huge.raw")).is_err_and(too_large));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn layered_and_planar_offset_sets() {
+        let sorted = |nb: CANeighborhood| {
+            let mut offsets = nb.offsets;
+            offsets.sort_unstable();
+            offsets
+        };
+
+        let mut layered = vec![
+            (-1, -1, 0),
+            (-1, 0, 0),
+            (-1, 1, 0),
+            (0, -1, 0),
+            (0, 1, 0),
+            (1, -1, 0),
+            (1, 0, 0),
+            (1, 1, 0),
+            (0, 0, -1),
+            (0, 0, 1),
+        ];
+        layered.sort_unstable();
+        assert_eq!(sorted(CANeighborhood::layered_moore()), layered);
+
+        let mut cross = vec![(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0)];
+        cross.sort_unstable();
+        assert_eq!(sorted(CANeighborhood::cross_2d()), cross);
+
+        for name in ["layered_moore", "cross_2d"] {
+            let nb: CANeighborhood = name.parse().unwrap();
+            assert_eq!((nb.name.as_str(), nb.to_string()), (name, name.to_string()));
+        }
+    }
+
+    #[test]
+    fn extended_von_neumann_neighbor_counts() {
+        for (radius, count) in [(1, 6), (2, 24), (3, 62)] {
+            let nb = CANeighborhood::extended_von_neumann(radius);
+            assert_eq!(nb.offsets.len(), count, "radius {radius}");
+            assert_eq!(nb.name, format!("extended_von_neumann_{radius}"));
+            assert!((nb.offsets.iter()).all(|&(x, y, z)| x.abs() + y.abs() + z.abs() <= radius));
+        }
+        assert!("extended_von_neumann(0)".parse::<CANeighborhood>().is_err());
+    }
+
+    #[test]
+    fn save_and_load_continue_the_run_exactly() {
+        let path = std::env::temp_dir().join("gradwork-ca-engine.json");
+        let initial = CAContext::random(16, 16, 8, 4, 0.45);
+        let mut soft = rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]);
+        soft.temperature = Some(0.5);
+
+        for rule in [rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]), soft] {
+            let config = config(CANeighborhood::moore(), rule, Boundary::default());
+            let mut straight = CAEngine::new(config.clone(), initial.clone()).with_seed(3);
+            straight.run(12, &mut Vec::new());
+
+            let mut first = CAEngine::new(config, initial.clone()).with_seed(3);
+            first.run(6, &mut Vec::new());
+            first.save(&path).unwrap();
+            let mut resumed = CAEngine::load(&path).unwrap();
+            resumed.run(6, &mut Vec::new());
+
+            assert_eq!(resumed.iteration(), 12);
+            assert_eq!(resumed.context(), straight.context());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stepping_back_and_rerunning_repeats_the_trajectory() {
+        let initial = CAContext::random(16, 16, 8, 9, 0.45);
+        let mut soft = rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]);
+        soft.temperature = Some(0.5);
+        let config = config(CANeighborhood::moore(), soft, Boundary::default());
+
+        let mut engine = CAEngine::new(config, initial).with_seed(2).with_history(3);
+        let mut trajectory = Vec::new();
+        for _ in 0..5 {
+            engine.run_iteration();
+            trajectory.push(engine.context().clone());
+        }
+        assert_eq!(engine.history_len(), 3);
+
+        engine.step_back().unwrap();
+        engine.step_back().unwrap();
+        assert_eq!(engine.iteration(), 3);
+        assert_eq!(engine.context(), &trajectory[2]);
+
+        for expected in &trajectory[3..] {
+            engine.run_iteration();
+            assert_eq!(engine.context(), expected);
+        }
+
+        let mut plain = CAEngine::new(engine.config.clone(), trajectory[0].clone());
+        assert_eq!(plain.step_back(), Err(HistoryError::Disabled));
+    }
+
+    #[test]
+    fn sparse_updates_match_dense_on_random_rules() {
+        for seed in 0..12 {
+            let mut rng = CellRng::new(seed, 0, 0);
+            let nb = [
+                CANeighborhood::von_neumann(),
+                CANeighborhood::moore(),
+                CANeighborhood::extended_moore(2),
+            ][(seed % 3) as usize]
+                .clone();
+            let n = nb.offsets.len();
+            let mut counts = |p| {
+                (0..=n)
+                    .filter(|_| rng.bool_with_prob(p))
+                    .collect::<Vec<_>>()
+            };
+            let rule = rule(&counts(0.4), &counts(0.6));
+            let preset = Boundary::PRESETS[(seed % 8) as usize];
+            let config = config(nb, rule, Boundary::preset(preset).unwrap());
+
+            let initial = CAContext::random(14, 12, 10, seed, 0.3 + 0.03 * seed as f64);
+            let mut dense =
+                CAEngine::new(config.clone(), initial.clone()).with_mode(EngineMode::Dense);
+            let mut sparse = CAEngine::new(config, initial).with_mode(EngineMode::Sparse);
+            for iteration in 1..=8 {
+                dense.run_iteration();
+                sparse.run_iteration();
+                assert_eq!(
+                    sparse.context(),
+                    dense.context(),
+                    "seed {seed}, iteration {iteration}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cell_rng_draws_ignore_thread_count() {
+        let pools = [1, 3].map(|threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+        });
+        let serial: Vec<(bool, u8)> = (0..10_000u64)
+            .map(|i| {
+                let mut rng = CellRng::new(21, 4, i);
+                (rng.bool_with_prob(0.3), rng.uniform_u8())
+            })
+            .collect();
+
+        let mut soft = rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]);
+        soft.temperature = Some(0.7);
+        let config = config(CANeighborhood::moore(), soft, Boundary::default());
+        let points = [(0.0, 0.2), (1.0, 0.6)];
+
+        let runs = pools.each_ref().map(|pool| {
+            pool.install(|| {
+                let draws: Vec<(bool, u8)> = (0..10_000u64)
+                    .into_par_iter()
+                    .map(|i| {
+                        let mut rng = CellRng::new(21, 4, i);
+                        (rng.bool_with_prob(0.3), rng.uniform_u8())
+                    })
+                    .collect();
+                assert_eq!(draws, serial);
+
+                let initial = CAContext::random_gradient(24, 20, 16, 5, &points);
+                let mut engine = CAEngine::new(config.clone(), initial).with_seed(5);
+                engine.run(4, &mut Vec::new());
+                engine.context().clone()
+            })
+        });
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn close_packed_neighborhoods_are_symmetric() {
+        for (nb, count) in [(CANeighborhood::fcc12(), 12), (CANeighborhood::bcc8(), 8)] {
+            assert_eq!(nb.offsets.len(), count, "{nb}");
+            for &(x, y, z) in &nb.offsets {
+                assert!(nb.offsets.contains(&(-x, -y, -z)), "{nb}: ({x}, {y}, {z})");
+            }
+        }
+        assert!(
+            (CANeighborhood::fcc12().offsets.iter())
+                .all(|&(x, y, z)| x.abs() + y.abs() + z.abs() == 2
+                    && x.abs().max(y.abs()).max(z.abs()) == 1)
+        );
+        assert!(
+            (CANeighborhood::bcc8().offsets.iter())
+                .all(|&(x, y, z)| x.abs() == 1 && y.abs() == 1 && z.abs() == 1)
+        );
+    }
+
+    #[test]
+    fn tunnel_through_the_x_seam_is_one_periodic_component() {
+        // Leaves at x = width - 1 and comes back in at x = 0
+        let mut ctx = CAContext::new(10, 5, 5);
+        for x in (0..3).chain(7..10) {
+            ctx.set(x, 2, 2, CACell::new(1));
+        }
+        let clamped = Boundary::default();
+        let periodic = Boundary::preset("tube_x").unwrap();
+        assert_eq!(ctx.connected_components_with(&clamped).len(), 2);
+        assert_eq!(ctx.connected_components_with(&periodic).len(), 1);
+        assert_eq!(
+            ctx.connected_components_via(&CANeighborhood::moore(), &periodic)
+                .len(),
+            1
+        );
+
+        // Only once it is complete does the tunnel wrap around
+        let components = ctx.connected_components_with(&periodic);
+        assert!(!ctx.percolates_with(&components, Axis::X, &periodic));
+        for x in 3..7 {
+            ctx.set(x, 2, 2, CACell::new(1));
+        }
+        let components = ctx.connected_components_with(&periodic);
+        assert!(ctx.percolates_with(&components, Axis::X, &periodic));
+
+        // The open ends only count as surface where the x faces are capped
+        assert_eq!(crate::metrics::surface_area(&ctx, &clamped), 42);
+        assert_eq!(crate::metrics::surface_area(&ctx, &periodic), 40);
+    }
+
+    #[test]
+    fn composition_modes_differ_on_a_single_voxel() {
+        let grow = rule(&(1..=26).collect::<Vec<_>>(), &(0..=26).collect::<Vec<_>>());
+        let erode = rule(&[], &[26]);
+        let stage = |rule| CAStage {
+            config: config(CANeighborhood::moore(), rule, Boundary::default()),
+            iterations: 1,
+        };
+        let mut initial = CAContext::new(7, 7, 7);
+        initial.set(3, 3, 3, CACell::new(1));
+
+        // Growing then eroding gives back the voxel, side by side the stages cover the 3x3x3
+        // cube or nothing at all
+        for (composition, air) in [
+            (Composition::Sequential, 1),
+            (Composition::Union, 27),
+            (Composition::Intersection, 0),
+        ] {
+            let pipeline = CAPipeline {
+                name: "test".to_string(),
+                stages: vec![stage(grow.clone()), stage(erode.clone())],
+                composition,
+            };
+            let mut engine = CAEngine::new(pipeline.stages[0].config.clone(), initial.clone());
+            let report = pipeline.run(&mut engine, &mut Vec::new(), None, |_, _| {});
+            assert_eq!(engine.context().total_air_cells(), air, "{composition}");
+            assert_eq!(report.iterations_run, pipeline.total_iterations());
+        }
+    }
+
+    #[test]
+    fn unreachable_counts_are_rule_problems() {
+        let problems = (rule(&[5, 30], &[4, 27, 40]).validate(&CANeighborhood::moore(), false))
+            .unwrap_err()
+            .problems;
+        assert_eq!(
+            problems,
+            [
+                RuleProblem::UnreachableCounts {
+                    kind: "birth",
+                    counts: vec![30],
+                },
+                RuleProblem::UnreachableCounts {
+                    kind: "survival",
+                    counts: vec![27, 40],
+                },
+            ]
+        );
+
+        // The cell itself makes one more count reachable
+        let self_count = rule(&[27], &[4]);
+        assert!(
+            self_count
+                .validate(&CANeighborhood::moore(), false)
+                .is_err()
+        );
+        assert!(self_count.validate(&CANeighborhood::moore(), true).is_ok());
+    }
+
+    #[test]
+    fn empty_sets_are_rule_problems() {
+        let err = (rule(&[], &[]).validate(&CANeighborhood::von_neumann(), false)).unwrap_err();
+        assert_eq!(
+            err.problems,
+            [
+                RuleProblem::EmptySet { kind: "birth" },
+                RuleProblem::EmptySet { kind: "survival" },
+            ]
+        );
+        assert_eq!(
+            (err.neighborhood.as_str(), err.neighbors),
+            ("von_neumann", 6)
+        );
+    }
+
+    #[test]
+    fn thresholds_out_of_range_are_rule_problems() {
+        let threshold = |threshold| CARule {
+            name: "T".to_string(),
+            rule_type: CARuleType::Threshold(threshold),
+            temperature: None,
+        };
+        let nb = CANeighborhood::moore();
+        for bad in [0, 27] {
+            let problems = threshold(bad).validate(&nb, false).unwrap_err().problems;
+            assert_eq!(
+                problems,
+                [RuleProblem::ThresholdOutOfRange { threshold: bad }]
+            );
+        }
+        assert!(threshold(1).validate(&nb, false).is_ok());
+        assert!(threshold(26).validate(&nb, false).is_ok());
+    }
+
+    #[test]
+    fn exposures_out_of_range_are_rule_problems() {
+        let nb = CANeighborhood::von_neumann();
+        for bad in [0, 7] {
+            let problems = CARule::erosion(bad)
+                .validate(&nb, false)
+                .unwrap_err()
+                .problems;
+            assert_eq!(problems, [RuleProblem::ExposureOutOfRange { exposed: bad }]);
+        }
+        assert!(CARule::erosion(6).validate(&nb, false).is_ok());
+    }
+
+    #[test]
+    fn checkerboard_prints_as_ascii_layers() {
+        let mut ctx = CAContext::new(4, 4, 2);
+        for (i, cell) in ctx.cells_mut().iter_mut().enumerate() {
+            let (x, y, z) = (i % 4, i / 4 % 4, i / 16);
+            cell.set_state(u8::from((x + y + z) % 2 == 0));
+        }
+
+        let expected = "--- Z=0 ---\n#.#.\n.#.#\n#.#.\n.#.#\n--- Z=1 ---\n.#.#\n#.#.\n.#.#\n#.#.\n";
+        assert_eq!(ctx.to_string(), expected);
+        assert_eq!(ctx.display_layer(1), ".#.#\n#.#.\n.#.#\n#.#.\n");
+
+        // Large grids are cut off with ellipses
+        let big = CAContext::new(90, 20, 20).to_string();
+        assert_eq!(
+            big.lines().filter(|l| l.starts_with("--- Z=")).count(),
+            DISPLAY_MAX_LAYERS
+        );
+        assert!(big.lines().nth(1).unwrap().ends_with("..."));
+        assert_eq!(big.lines().nth(1).unwrap().len(), DISPLAY_MAX_COLUMNS + 3);
+        assert!(big.ends_with("...\n...\n"));
+    }
+
+    #[test]
+    fn flip_fraction_approaches_half_at_high_temperature() {
+        let threshold = |temperature| CARule {
+            name: "T13".to_string(),
+            rule_type: CARuleType::Threshold(13),
+            temperature,
+        };
+        let initial = CAContext::random(64, 64, 32, 12, 0.5);
+        let run = |temperature| {
+            let config = config(
+                CANeighborhood::moore(),
+                threshold(temperature),
+                Boundary::default(),
+            );
+            let mut engine = CAEngine::new(config, initial.clone()).with_seed(8);
+            engine.run_iteration();
+            engine
+        };
+
+        let hot = run(Some(1e3));
+        let flipped = hot.changed_cells() as f64 / initial.cells().len() as f64;
+        assert!((flipped - 0.5).abs() < 0.01, "{flipped}");
+        assert!((threshold(None).air_probability(false, 13, 1e3) - 0.5).abs() < 1e-3);
+
+        // Near zero temperature the soft rule is the crisp one
+        assert_eq!(run(Some(1e-3)).context(), run(None).context());
+    }
+
+    #[test]
+    fn equal_grids_compare_and_hash_equal() {
+        use std::collections::HashSet;
+
+        assert_eq!(CAContext::new(5, 4, 3), CAContext::new(5, 4, 3));
+        assert_ne!(CAContext::new(5, 4, 3), CAContext::new(4, 5, 3));
+
+        let mut changed = CAContext::new(5, 4, 3);
+        changed.set(1, 2, 0, CACell::new(1));
+        assert_ne!(changed, CAContext::new(5, 4, 3));
+
+        let states: HashSet<CAContext> = [
+            CAContext::new(5, 4, 3),
+            changed.clone(),
+            CAContext::new(5, 4, 3),
+            changed,
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(states.len(), 2);
+    }
+
+    #[test]
+    fn reports_count_the_iterations_that_ran() {
+        let config = config(
+            CANeighborhood::moore(),
+            rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]),
+            Boundary::default(),
+        );
+        let initial = CAContext::random(12, 12, 12, 6, 0.5);
+
+        let mut engine = CAEngine::new(config.clone(), initial.clone());
+        let report = engine.run_report(7, &mut Vec::new());
+        assert_eq!(report.iterations_run, 7);
+        assert_eq!(report.per_iter.len(), 8);
+        assert_eq!(report.stop_reason, StopReason::Completed);
+        assert_eq!(engine.iteration(), 7);
+
+        // An elapsed deadline stops before the first iteration
+        let mut engine = CAEngine::new(config, initial.clone());
+        let report = engine.run_with_hook(7, &mut Vec::new(), Some(Duration::ZERO), |_, _| {});
+        assert_eq!(report.iterations_run, 0);
+        assert_eq!(report.per_iter.len(), 1);
+        assert_eq!(report.stop_reason, StopReason::TimeLimit);
+        assert_eq!(engine.context(), &initial);
+    }
+
+    #[test]
+    fn buffer_holds_the_previous_generation() {
+        let grow = rule(&(1..=26).collect::<Vec<_>>(), &(0..=26).collect::<Vec<_>>());
+        let mut initial = CAContext::new(7, 7, 7);
+        initial.set(3, 3, 3, CACell::new(1));
+        let mut engine = CAEngine::new(
+            config(CANeighborhood::moore(), grow, Boundary::default()),
+            initial.clone(),
+        );
+
+        engine.run_iteration();
+        let mut cube = CAContext::new(7, 7, 7);
+        for (x, y, z) in
+            (2..5).flat_map(|x| (2..5).flat_map(move |y| (2..5).map(move |z| (x, y, z))))
+        {
+            cube.set(x, y, z, CACell::new(1));
+        }
+        assert_eq!(engine.context(), &cube);
+        assert_eq!(engine.buffer(), &initial);
+
+        engine.run_iteration();
+        assert_eq!(engine.context().total_air_cells(), 125);
+        assert_eq!(engine.buffer(), &cube);
+    }
+
+    #[test]
+    fn three_state_grid_counts_only_the_configured_state() {
+        let mut ctx = CAContext::new(6, 5, 4);
+        for (i, cell) in ctx.cells_mut().iter_mut().enumerate() {
+            cell.set_state((i * 7 % 3) as u8);
+        }
+        let state_one = CountStates::States(vec![1]);
+        let mask = state_one.mask();
+        let ones = ctx.cells().iter().filter(|c| c.0 == 1).count();
+        assert_eq!(ctx.count_cells_with(|c| mask[usize::from(c.0)]), ones);
+        assert!(ctx.total_air_cells() > ones);
+
+        let nb = CANeighborhood::moore();
+        let boundary = Boundary::default();
+        for (x, y, z) in [(0, 0, 0), (2, 2, 1), (5, 4, 3), (3, 1, 2)] {
+            let expected = (nb.offsets.iter())
+                .filter_map(|&d| ctx.offset_index(x, y, z, d, &boundary))
+                .filter(|&j| ctx.cells()[j].0 == 1)
+                .count();
+            let counted =
+                ctx.count_neighbors_where(x, y, z, &nb, &boundary, |c| state_one.counts(c));
+            assert_eq!(counted, expected, "({x}, {y}, {z})");
+        }
+
+        let mut config = config(nb.clone(), rule(&[4], &[4]), boundary);
+        config.count_states = state_one.clone();
+        let mut engine = CAEngine::new(config, ctx.clone());
+        assert_eq!(
+            engine.run_report(0, &mut Vec::new()).per_iter[0].alive,
+            ones
+        );
+
+        // Neighbors in state 2 do not count towards the rule
+        engine.run_iteration();
+        for (i, cell) in engine.context().cells().iter().enumerate() {
+            let (x, y, z) = ctx.pos(i);
+            let n = ctx.count_neighbors_where(x, y, z, &nb, &boundary, |c| c.0 == 1);
+            assert_eq!(cell.is_air(), n == 4, "({x}, {y}, {z})");
+        }
+    }
+
+    #[test]
+    fn active_list_matches_full_recompute() {
+        let mode: EngineMode = serde_json::from_str("\"active_list\"").unwrap();
+        assert_eq!(mode, EngineMode::Sparse);
+
+        let smooth = rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]);
+        let majority = rule(
+            &(14..=26).collect::<Vec<_>>(),
+            &(13..=26).collect::<Vec<_>>(),
+        );
+        for preset in ["solid", "periodic", "slab_z"] {
+            let boundary = Boundary::preset(preset).unwrap();
+            let initial = CAContext::random(20, 18, 12, 3, 0.48);
+            let engines = [EngineMode::Dense, mode].map(|mode| {
+                let config = config(CANeighborhood::moore(), smooth.clone(), boundary);
+                let mut engine = CAEngine::new(config, initial.clone()).with_mode(mode);
+                // Long enough to settle, then a region pass and a rule change reset the
+                // active set
+                engine.run(15, &mut Vec::new());
+                engine.run_region((4, 4, 2), (12, 10, 8), 3).unwrap();
+                let mut config = engine.config.clone();
+                config.rule = majority.clone();
+                engine.set_config(config);
+                engine.run(6, &mut Vec::new());
+                engine.context().clone()
+            });
+            assert_eq!(engines[1], engines[0], "{preset}");
+        }
+    }
+
+    #[test]
+    fn replaced_context_runs_like_a_fresh_engine() {
+        let mut soft = rule(&[5, 6, 7, 8], &[4, 5, 6, 7, 8]);
+        soft.temperature = Some(0.4);
+        let config = config(CANeighborhood::moore(), soft, Boundary::default());
+        let next = CAContext::random(12, 10, 8, 2, 0.5);
+
+        let mut engine = CAEngine::new(config.clone(), CAContext::random(12, 10, 8, 1, 0.5))
+            .with_seed(4)
+            .with_history(2);
+        engine.run(3, &mut Vec::new());
+        engine.replace_context(next.clone()).unwrap();
+        assert_eq!((engine.iteration(), engine.history_len()), (0, 0));
+        assert_eq!((engine.context(), engine.buffer()), (&next, &next));
+
+        let mut fresh = CAEngine::new(config, next.clone()).with_seed(4);
+        engine.run(3, &mut Vec::new());
+        fresh.run(3, &mut Vec::new());
+        let last = fresh.context().clone();
+        assert_eq!(engine.context(), &last);
+
+        let err = engine
+            .replace_context(CAContext::new(10, 12, 8))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            DimensionMismatch {
+                expected: (12, 10, 8),
+                found: (10, 12, 8),
+            }
+        );
+        assert_eq!(engine.context(), &last);
+        assert_eq!(engine.into_context(), last);
+    }
+
+    #[test]
+    fn gradient_slices_land_near_their_probabilities() {
+        let (width, height, depth) = (64, 64, 11);
+        let points = [(1.0, 0.1), (0.0, 0.8), (0.5, 0.3)];
+        let ctx = CAContext::random_gradient(width, height, depth, 13, &points);
+        let slab = width * height;
+        for z in 0..depth {
+            let t = z as f64 / 10.0;
+            let expected = if t <= 0.5 {
+                0.8 - t
+            } else {
+                0.3 - 0.4 * (t - 0.5)
+            };
+            let cells = &ctx.cells()[z * slab..(z + 1) * slab];
+            let air = cells.iter().filter(|c| c.is_air()).count() as f64 / slab as f64;
+            assert!(
+                (air - expected).abs() < 0.03,
+                "z = {z}: {air} vs {expected}"
+            );
+        }
+        assert_eq!(
+            CAContext::random_gradient(width, height, depth, 13, &points),
+            ctx
+        );
+
+        let linear = CAContext::from_gradient(width, height, depth, 13, 0.9, 0.2);
+        for z in 0..depth {
+            let expected = 0.9 - 0.07 * z as f64;
+            let cells = &linear.cells()[z * slab..(z + 1) * slab];
+            let air = cells.iter().filter(|c| c.is_air()).count() as f64 / slab as f64;
+            assert!(
+                (air - expected).abs() < 0.03,
+                "z = {z}: {air} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn walks_carve_a_bounded_count_inside_the_grid() {
+        let params = WalkParams {
+            n_walks: 4,
+            walk_len: 30,
+            step_radius: 1.5,
+            horizontal_bias: 0.5,
+            starts: Vec::new(),
+            background_air_prob: 0.0,
+        };
+        // Lattice cells within the brush radius of a step
+        let brush = (-1..=1i32)
+            .flat_map(|x| {
+                (-1..=1i32).flat_map(move |y| (-1..=1i32).map(move |z| x * x + y * y + z * z))
+            })
+            .filter(|&d| f64::from(d) <= 1.5 * 1.5)
+            .count();
+        for seed in 0..6 {
+            let mut ctx = CAContext::new(24, 24, 12);
+            let carved = ctx.carve_walks(seed, &params);
+            assert_eq!(carved, ctx.total_air_cells());
+            assert!(carved > 0, "seed {seed}");
+            assert!(
+                carved <= params.n_walks * (params.walk_len + 1) * brush,
+                "seed {seed}: {carved}"
+            );
+            assert_eq!(CAContext::from_walks(24, 24, 12, seed, &params), ctx);
+        }
+
+        // A long walk from a corner keeps bumping into the edges without leaving the grid, so
+        // its trail stays one connected tunnel through the corner
+        let corner = WalkParams {
+            n_walks: 1,
+            walk_len: 400,
+            step_radius: 0.0,
+            starts: vec![(0, 0, 0)],
+            ..params
+        };
+        let mut ctx = CAContext::new(6, 5, 4);
+        let carved = ctx.carve_walks(3, &corner);
+        assert!((2..=401).contains(&carved));
+        assert!(ctx.get(0, 0, 0).is_air());
+        assert_eq!(ctx.connected_components().len(), 1);
+    }
+
+    #[test]
+    fn shell_stays_solid_and_air_fills_the_interior() {
+        let shell = ShellSpec {
+            min_x: 1,
+            max_x: 2,
+            min_y: 3,
+            max_y: 1,
+            min_z: 0,
+            max_z: 2,
+            frozen: false,
+        };
+        let (width, height, depth) = (10, 9, 8);
+        let interior = shell.interior(width, height, depth);
+        let interior_cells = (8 - 1) * (8 - 3) * 6;
+
+        for air in [0.0, 0.3, 0.55, 1.0] {
+            let mut ctx = CAContext::random_with_shell(width, height, depth, 4, air, &shell);
+            let mut interior_air = 0;
+            for i in 0..ctx.cells().len() {
+                let (x, y, z) = ctx.pos(i);
+                if interior.contains(x, y, z) {
+                    interior_air += usize::from(ctx.cells()[i].is_air());
+                } else {
+                    assert_eq!(ctx.cells()[i], CACell::new(0));
+                }
+            }
+            assert_eq!(interior_air, (interior_cells as f64 * air).round() as usize);
+
+            // Already solid outside, so the shell changes nothing
+            let before = ctx.clone();
+            ctx.apply_shell(&shell);
+            assert_eq!(ctx, before);
+        }
+
+        // A shell thicker than the grid leaves no interior, only rock
+        let filled = ShellSpec {
+            min_x: width + 2,
+            ..shell
+        };
+        let ctx = CAContext::random_with_shell(width, height, depth, 4, 0.5, &filled);
+        assert!(ctx.cells().iter().all(|&cell| cell == CACell::new(0)));
+    }
+
+    #[test]
+    fn every_mode_matches_the_reference() {
+        let initial = CAContext::random(14, 12, 10, 6, 0.45);
+        let frozen: Vec<bool> = (0..initial.cells().len()).map(|i| i % 7 == 0).collect();
+        let mut soft = rule(&[5, 6, 7], &[4, 5, 6, 7, 8]);
+        soft.temperature = Some(0.6);
+
+        for rule in [rule(&[5, 6, 7], &[4, 5, 6, 7, 8]), soft] {
+            for mode in [
+                EngineMode::Dense,
+                EngineMode::Sparse,
+                EngineMode::Incremental,
+            ] {
+                let config = config(CANeighborhood::moore(), rule.clone(), Boundary::default());
+                let mut engine = CAEngine::new(config, initial.clone())
+                    .with_mode(mode)
+                    .with_seed(9);
+                engine
+                    .set_region(Some(Region::new((2, 1, 0), (12, 11, 8))))
+                    .unwrap();
+                engine.set_frozen(Some(frozen.clone()));
+                if let Err(report) = engine.verify_against_reference(6) {
+                    panic!("{mode}: {report}");
+                }
+            }
+        }
     }
 }
@@ -1,13 +1,38 @@
 use crate::ca::Axis;
+use crate::ca::Boundary;
+use crate::ca::CACell;
 use crate::ca::CAContext;
+use crate::ca::CAEngine;
+use crate::ca::CANeighborhood;
+use crate::ca::CAPipeline;
+use crate::ca::CAStage;
+use crate::ca::Composition;
+use crate::ca::EngineMode;
+use crate::ca::EngineReport;
+use crate::ca::EntranceParams;
+use crate::ca::Initializer;
+use crate::ca::IterationStats;
+use crate::ca::Region;
+use crate::ca::ShellSpec;
+use crate::ca::Stamp;
+use crate::ca::StopReason;
+use crate::metrics;
+use crate::metrics::Connectivity;
+use crate::topology::{self, CaveGraph};
 use csv::WriterBuilder;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+// Stone gray used for grid.vox unless a color is configured
+pub const DEFAULT_VOX_COLOR: [u8; 4] = [128, 124, 118, 255];
 
 #[derive(Hash, Eq, PartialEq)]
 pub struct ConfigKey {
@@ -26,6 +51,81 @@ pub struct RunMetadata {
     pub width: usize,
     pub height: usize,
     pub depth: usize,
+    pub engine_mode: EngineMode,
+    // Boundary preset name, or the per-axis modes when no preset matches
+    pub boundary: String,
+    // Everything replay needs to rebuild the run on top of the fields above
+    #[serde(default)]
+    pub initializer: Initializer,
+    #[serde(default)]
+    pub stages: Vec<CAStage>,
+    #[serde(default)]
+    pub composition: Composition,
+    #[serde(default)]
+    pub iterations_completed: usize,
+    #[serde(default)]
+    pub clean_isolated: bool,
+    #[serde(default)]
+    pub remove_dead_ends: usize,
+    #[serde(default)]
+    pub region: Option<Region>,
+    // Box the metrics were taken over, the whole grid when absent
+    #[serde(default)]
+    pub roi: Option<Region>,
+    #[serde(default)]
+    pub shell: ShellSpec,
+    #[serde(default)]
+    pub stamps: Vec<Stamp>,
+    #[serde(default)]
+    pub entrances: Option<EntranceParams>,
+    // Shaft axes on the top face, also the sources to search the grid from
+    #[serde(default)]
+    pub entrance_positions: Vec<(usize, usize)>,
+    #[serde(default)]
+    pub carve_path: Option<Axis>,
+    #[serde(default)]
+    pub vox_bands: Vec<(usize, u8)>,
+    #[serde(default = "default_vox_color")]
+    pub vox_color: [u8; 4],
+    // Degenerate attempts redone before this grid, `seed` is the one finally used while the
+    // run_id keeps the configured seed
+    #[serde(default)]
+    pub retries: usize,
+    // Hash of every setting that decides the grid and the results row, a finished run is only
+    // reused while it matches
+    #[serde(default)]
+    pub config_hash: String,
+}
+
+// Names an output path template can hold between braces
+pub const TEMPLATE_VARIABLES: [&str; 7] = [
+    "run_id",
+    "seed",
+    "neighborhood",
+    "ruleset",
+    "width",
+    "height",
+    "depth",
+];
+
+// Replaces every {name} in `template` with its value
+#[must_use]
+pub fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    (values.iter()).fold(template.to_string(), |path, (name, value)| {
+        path.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+// Names between braces in `template`, in order of appearance
+#[must_use]
+pub fn template_variables(template: &str) -> Vec<&str> {
+    (template.split('{').skip(1))
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+fn default_vox_color() -> [u8; 4] {
+    DEFAULT_VOX_COLOR
 }
 
 impl RunMetadata {
@@ -43,13 +143,13 @@ impl RunMetadata {
     ) -> Self {
         Self {
             run_id: format!(
-                "{}__{}__{}x{}x{}__p{:.2}__i{}__s{}",
+                "{}__{}__{}x{}x{}__p{}__i{}__s{}",
                 Self::slugify(&neighborhood),
                 Self::slugify(&ruleset),
                 width,
                 height,
                 depth,
-                air_prob,
+                Self::air_prob_tag(air_prob),
                 iterations,
                 seed
             ),
@@ -62,6 +162,35 @@ impl RunMetadata {
             iterations,
             ruleset,
             air_prob,
+            engine_mode: EngineMode::default(),
+            boundary: Boundary::default().name(),
+            initializer: Initializer::default(),
+            stages: Vec::new(),
+            composition: Composition::default(),
+            iterations_completed: iterations,
+            clean_isolated: false,
+            remove_dead_ends: 0,
+            region: None,
+            roi: None,
+            shell: ShellSpec::default(),
+            stamps: Vec::new(),
+            entrances: None,
+            entrance_positions: Vec::new(),
+            carve_path: None,
+            vox_bands: Vec::new(),
+            vox_color: DEFAULT_VOX_COLOR,
+            retries: 0,
+            config_hash: String::new(),
+        }
+    }
+
+    // Two decimals unless that would merge distinct probabilities of a finer sweep
+    fn air_prob_tag(air_prob: f64) -> String {
+        let short = format!("{air_prob:.2}");
+        if short.parse() == Ok(air_prob) {
+            short
+        } else {
+            air_prob.to_string()
         }
     }
 
@@ -72,6 +201,88 @@ impl RunMetadata {
         serde_json::to_writer_pretty(file, self).map_err(std::io::Error::other)
     }
 
+    pub fn load(file_dir: &Path) -> std::io::Result<Self> {
+        let file = BufReader::new(File::open(file_dir.join("metadata.json"))?);
+        serde_json::from_reader(file).map_err(std::io::Error::other)
+    }
+
+    // Values of TEMPLATE_VARIABLES for this run. The seed is the configured one, like in the
+    // run_id, and names are slugified the same way
+    #[must_use]
+    pub fn template_values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("run_id", self.run_id.clone()),
+            ("seed", self.seed.to_string()),
+            ("neighborhood", Self::slugify(&self.neighborhood)),
+            ("ruleset", Self::slugify(&self.ruleset)),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+            ("depth", self.depth.to_string()),
+        ]
+    }
+
+    // Rebuilds the engine of a finished run from scratch. Every step is a pure function of the
+    // seed and the recorded stages, so the final grid matches the original run bit for bit
+    #[must_use]
+    pub fn replay(&self) -> Option<CAEngine> {
+        let first = self.stages.first()?;
+        let mut context = self.initializer.build_with_shell(
+            None,
+            self.width,
+            self.height,
+            self.depth,
+            self.seed,
+            self.air_prob,
+            &self.shell,
+        );
+        for stamp in &self.stamps {
+            context.stamp_within(stamp, CACell::new(1), self.region);
+        }
+        let mut frozen = None;
+        if let Some(entrances) = &self.entrances {
+            let carved = context.carve_entrances(entrances, &self.entrance_positions);
+            frozen = entrances.frozen.then_some(carved);
+        }
+
+        // Stop where the original run stopped, which is earlier than planned after a time out.
+        // Sequential stages use up the budget one after the other, combined ones share it
+        let mut remaining = self.iterations_completed;
+        let stages = (self.stages.iter())
+            .map(|stage| {
+                let iterations = stage.iterations.min(remaining);
+                if self.composition == Composition::Sequential {
+                    remaining -= iterations;
+                }
+                CAStage {
+                    config: stage.config.clone(),
+                    iterations,
+                }
+            })
+            .collect();
+        let pipeline = CAPipeline {
+            name: self.ruleset.clone(),
+            stages,
+            composition: self.composition,
+        };
+
+        let mut engine = CAEngine::new(first.config.clone(), context)
+            .with_mode(self.engine_mode)
+            .with_seed(self.seed);
+        engine.set_region(self.region).ok()?;
+        engine.set_frozen(frozen);
+        pipeline.run(&mut engine, &mut Vec::new(), None, |_, _| {});
+
+        if self.clean_isolated {
+            engine.remove_isolated_cells();
+        }
+        engine.remove_dead_ends(self.remove_dead_ends);
+        if let Some(axis) = self.carve_path {
+            engine.ensure_percolates(axis);
+        }
+
+        Some(engine)
+    }
+
     fn slugify(s: &str) -> String {
         s.to_lowercase().replace([' ', ',', '[', ']'], "")
     }
@@ -82,6 +293,48 @@ pub struct RunInfo {
     pub metadata: RunMetadata,
     pub context: CAContext,
     pub logs: Vec<String>,
+    // Per-iteration stats of the run, only written by LogFormat::Json
+    pub iterations: Vec<IterationStats>,
+    pub log_format: LogFormat,
+    // (minimum component size, palette index) bands for coloring air cells, empty draws solids
+    pub vox_bands: Vec<(usize, u8)>,
+    // RGBA written into the grid.vox palette, every palette index the export uses gets a shade
+    pub vox_color: [u8; 4],
+    // Also write distance.vox with air cells colored by their distance to the rock
+    pub distance_vox: bool,
+    pub gif: Option<GifExport>,
+    // Snapshot files already written during the run, moved to snapshots/ on save
+    pub snapshots: Vec<PathBuf>,
+    // Edge handling used to label the air components drawn in grid.vox
+    pub boundary: Boundary,
+    // Axes to write projection_<axis>.png density maps for
+    pub projections: Vec<Axis>,
+    // Also write grid.ply with a vertex at the center of every air cell
+    pub ply: bool,
+    // Color the grid.ply vertices by the air component they belong to
+    pub ply_colors: bool,
+    // Without grids only metadata.json and the log are written
+    pub save_grids: bool,
+    // Written to cave_graph.json
+    pub cave_graph: Option<CaveGraph>,
+}
+
+// How RunInfo writes its log
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // log.txt, one line per log entry
+    #[default]
+    Text,
+    // log.json, { "iterations": [IterationStats], "logs": [..] } without the iter= lines
+    Json,
+}
+
+// Per-iteration copies of the animated z-slice for iterations.gif, each one cell deep
+#[derive(Serialize, Debug)]
+pub struct GifExport {
+    pub delay_cs: u16,
+    pub frames: Vec<(usize, CAContext)>,
 }
 
 impl RunInfo {
@@ -91,9 +344,51 @@ impl RunInfo {
             metadata,
             context,
             logs: Vec::new(),
+            iterations: Vec::new(),
+            log_format: LogFormat::default(),
+            vox_bands: Vec::new(),
+            vox_color: DEFAULT_VOX_COLOR,
+            distance_vox: false,
+            gif: None,
+            snapshots: Vec::new(),
+            boundary: Boundary::default(),
+            projections: Vec::new(),
+            ply: false,
+            ply_colors: false,
+            save_grids: true,
+            cave_graph: None,
         }
     }
 
+    pub fn set_vox_bands(&mut self, mut bands: Vec<(usize, u8)>) {
+        bands.sort_by_key(|&(threshold, _)| threshold);
+        self.metadata.vox_bands.clone_from(&bands);
+        self.vox_bands = bands;
+    }
+
+    pub fn set_vox_color(&mut self, color: [u8; 4]) {
+        self.metadata.vox_color = color;
+        self.vox_color = color;
+    }
+
+    // Reconstructs a saved run from its metadata.json alone. Saving the result writes the same
+    // grid.vox as the original run
+    pub fn replay(run_dir: &Path) -> std::io::Result<Self> {
+        let metadata = RunMetadata::load(run_dir)?;
+        let engine = metadata.replay().ok_or_else(|| {
+            std::io::Error::other(format!("{} has no recorded stages", run_dir.display()))
+        })?;
+        let bands = metadata.vox_bands.clone();
+        let color = metadata.vox_color;
+
+        let boundary = engine.config.boundary;
+        let mut info = Self::new(metadata, engine.into_context());
+        info.boundary = boundary;
+        info.set_vox_bands(bands);
+        info.set_vox_color(color);
+        Ok(info)
+    }
+
     pub fn set_logs(&mut self, logs: Vec<String>) {
         self.logs = logs;
     }
@@ -106,15 +401,63 @@ impl RunInfo {
         self.logs.push(log);
     }
 
-    pub fn save(&self, dir: &std::path::Path) -> std::io::Result<()> {
-        let run_dir = dir.join(&self.metadata.run_id);
-        fs::create_dir_all(&run_dir)?;
-        self.metadata.save(&run_dir)?;
-        self.save_log(&run_dir)?;
-        self.save_vox(&run_dir)
+    pub fn save(&self, run_dir: &Path) -> std::io::Result<()> {
+        // Write into a temporary sibling first so a visible run directory is always complete
+        let mut tmp_name = run_dir.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_dir = run_dir.with_file_name(tmp_name);
+        if tmp_dir.exists() {
+            fs::remove_dir_all(&tmp_dir)?;
+        }
+        fs::create_dir_all(&tmp_dir)?;
+
+        self.metadata.save(&tmp_dir)?;
+        self.save_log(&tmp_dir)?;
+        if self.save_grids {
+            self.save_grids(&tmp_dir)?;
+        }
+        if let Some(graph) = &self.cave_graph {
+            graph.save(&tmp_dir.join("cave_graph.json"))?;
+        }
+
+        if run_dir.exists() {
+            fs::remove_dir_all(run_dir)?;
+        }
+        fs::rename(&tmp_dir, run_dir)
+    }
+
+    // grid.vox and every other export of the grid
+    fn save_grids(&self, run_dir: &Path) -> std::io::Result<()> {
+        self.save_vox(run_dir)?;
+        if !self.snapshots.is_empty() {
+            self.save_snapshots(run_dir)?;
+        }
+        if self.distance_vox {
+            self.save_distance_vox(run_dir)?;
+        }
+        for &axis in &self.projections {
+            self.save_projection(run_dir, axis)?;
+        }
+        if self.ply {
+            self.save_ply(run_dir)?;
+        }
+        if let Some(gif) = &self.gif {
+            save_iteration_gif(
+                &gif.frames,
+                0,
+                &run_dir.join("iterations.gif"),
+                gif.delay_cs,
+            )?;
+        }
+
+        Ok(())
     }
 
     fn save_log(&self, run_dir: &std::path::Path) -> std::io::Result<()> {
+        if self.log_format == LogFormat::Json {
+            return self.save_log_json(run_dir);
+        }
+
         let path = run_dir.join("log.txt");
         let mut file = std::fs::File::create(path)?;
 
@@ -125,12 +468,187 @@ impl RunInfo {
         Ok(())
     }
 
+    fn save_log_json(&self, run_dir: &Path) -> std::io::Result<()> {
+        #[derive(Serialize)]
+        struct JsonLog<'a> {
+            iterations: &'a [IterationStats],
+            logs: Vec<&'a str>,
+        }
+
+        // The engine's per-iteration lines are covered by `iterations`
+        let file = File::create(run_dir.join("log.json"))?;
+        let log = JsonLog {
+            iterations: &self.iterations,
+            logs: (self.logs.iter())
+                .map(String::as_str)
+                .filter(|line| !line.starts_with("iter="))
+                .collect(),
+        };
+        serde_json::to_writer(BufWriter::new(file), &log).map_err(std::io::Error::other)
+    }
+
+    #[must_use]
+    pub fn vox_style(&self) -> VoxStyle<'_> {
+        VoxStyle {
+            bands: &self.vox_bands,
+            color: self.vox_color,
+            boundary: self.boundary,
+        }
+    }
+
     fn save_vox(&self, run_dir: &std::path::Path) -> std::io::Result<()> {
-        let path = run_dir.join("grid.vox");
+        (self.vox_style()).write_vox(&self.context, &run_dir.join("grid.vox"))
+    }
+
+    fn save_snapshots(&self, run_dir: &std::path::Path) -> std::io::Result<()> {
+        let dir = run_dir.join("snapshots");
+        fs::create_dir_all(&dir)?;
+
+        for path in &self.snapshots {
+            let name = path.file_name().unwrap_or_default();
+            fs::rename(path, dir.join(name))?;
+        }
+
+        Ok(())
+    }
+
+    // Air cells colored by their Chebyshev distance to the nearest solid cell, clamped to the
+    // palette range
+    fn save_distance_vox(&self, run_dir: &std::path::Path) -> std::io::Result<()> {
+        let path = run_dir.join("distance.vox");
+        let mut vox = vox_writer::VoxWriter::create_empty();
+        let dist = metrics::distance_transform_l_inf(&self.context);
+
+        for (idx, &d) in dist.iter().enumerate() {
+            if d == 0 {
+                continue;
+            }
+            let (x, y, z) = self.context.pos(idx);
+            vox.add_voxel(x as i32, y as i32, z as i32, d.min(255) as i32);
+        }
+
+        vox.save_to_file(path.to_string_lossy().to_string())
+            .map_err(std::io::Error::other)
+    }
+
+    // Grayscale view along `axis`, each pixel is brighter the more air cells its column holds.
+    // The image spans the other two axes in (x, y, z) order
+    pub fn save_projection(&self, run_dir: &Path, axis: Axis) -> std::io::Result<()> {
+        let ctx = &self.context;
+        let (w, h, d) = (ctx.width(), ctx.height(), ctx.depth());
+        let (img_w, img_h, len) = match axis {
+            Axis::X => (h, d, w),
+            Axis::Y => (w, d, h),
+            Axis::Z => (w, h, d),
+        };
+
+        let mut counts = vec![0usize; img_w * img_h];
+        for (x, y, z, cell) in ctx {
+            let (u, v) = match axis {
+                Axis::X => (y, z),
+                Axis::Y => (x, z),
+                Axis::Z => (x, y),
+            };
+            counts[u + img_w * v] += usize::from(cell.is_air());
+        }
+
+        let pixels = (counts.iter())
+            .map(|&count| (count * 255 / len.max(1)) as u8)
+            .collect();
+        let image = image::GrayImage::from_raw(img_w as u32, img_h as u32, pixels)
+            .expect("Projection buffer matches the image size");
+
+        let name = match axis {
+            Axis::X => "projection_x.png",
+            Axis::Y => "projection_y.png",
+            Axis::Z => "projection_z.png",
+        };
+        image
+            .save(run_dir.join(name))
+            .map_err(std::io::Error::other)
+    }
+
+    // Binary little-endian point cloud of the air cell centers. Vertices are streamed straight
+    // to the file, only the component labels for ply_colors are held in memory
+    pub fn save_ply(&self, run_dir: &Path) -> std::io::Result<()> {
+        let ctx = &self.context;
+        let mut file = BufWriter::new(File::create(run_dir.join("grid.ply"))?);
+
+        let labels = self.ply_colors.then(|| {
+            let mut labels = vec![0usize; ctx.cells().len()];
+            for (label, comp) in ctx
+                .connected_components_with(&self.boundary)
+                .iter()
+                .enumerate()
+            {
+                for &idx in comp {
+                    labels[idx] = label;
+                }
+            }
+            labels
+        });
+
+        writeln!(file, "ply")?;
+        writeln!(file, "format binary_little_endian 1.0")?;
+        writeln!(file, "element vertex {}", ctx.total_air_cells())?;
+        for axis in ["x", "y", "z"] {
+            writeln!(file, "property float {axis}")?;
+        }
+        if labels.is_some() {
+            for channel in ["red", "green", "blue"] {
+                writeln!(file, "property uchar {channel}")?;
+            }
+        }
+        writeln!(file, "end_header")?;
+
+        for (idx, cell) in ctx.cells().iter().enumerate() {
+            if !cell.is_air() {
+                continue;
+            }
+
+            let (x, y, z) = ctx.pos(idx);
+            for v in [x, y, z] {
+                file.write_all(&(v as f32 + 0.5).to_le_bytes())?;
+            }
+            if let Some(labels) = &labels {
+                file.write_all(&component_color(labels[idx]))?;
+            }
+        }
+
+        file.flush()
+    }
+}
+
+// How grid.vox and the snapshots draw a grid, see RunInfo::vox_bands and vox_color
+pub struct VoxStyle<'a> {
+    pub bands: &'a [(usize, u8)],
+    pub color: [u8; 4],
+    // Edge handling used to label the drawn air components
+    pub boundary: Boundary,
+}
+
+impl VoxStyle<'_> {
+    pub fn write_vox(&self, ctx: &CAContext, path: &Path) -> std::io::Result<()> {
         let mut vox = vox_writer::VoxWriter::create_empty();
 
-        let components = self.context.connected_components();
-        let mut air_component_of = vec![None; self.context.cells().len()];
+        let components = ctx.connected_components_with(&self.boundary);
+
+        if !self.bands.is_empty() {
+            self.add_banded_air_voxels(&mut vox, ctx, &components);
+
+            // Larger bands get brighter shades
+            let n = self.bands.len();
+            for (rank, &(_, index)) in self.bands.iter().enumerate() {
+                let brightness = 0.5 + 0.5 * (rank + 1) as f64 / n as f64;
+                self.add_vox_shade(&mut vox, index, brightness);
+            }
+
+            return vox
+                .save_to_file(path.to_string_lossy().to_string())
+                .map_err(std::io::Error::other);
+        }
+
+        let mut air_component_of = vec![None; ctx.cells().len()];
 
         for (i, comp) in components.iter().enumerate() {
             for &idx in comp {
@@ -138,13 +656,18 @@ impl RunInfo {
             }
         }
 
+        // Rock walls of the main cave are drawn in full color, other walls and the interior darker
+        for (index, brightness) in [(200, 1.0), (120, 0.75), (20, 0.5)] {
+            self.add_vox_shade(&mut vox, index, brightness);
+        }
+
         let Some((main_comp_idx, _)) = components.iter().enumerate().max_by_key(|(_, c)| c.len())
         else {
-            for z in 0..self.context.depth() {
-                for y in 0..self.context.height() {
-                    for x in 0..self.context.width() {
-                        let idx = self.context.idx(x, y, z);
-                        if !self.context[idx].is_air() {
+            for z in 0..ctx.depth() {
+                for y in 0..ctx.height() {
+                    for x in 0..ctx.width() {
+                        let idx = ctx.idx(x, y, z);
+                        if !ctx[idx].is_air() {
                             vox.add_voxel(x as i32, y as i32, z as i32, 20);
                         }
                     }
@@ -165,13 +688,13 @@ impl RunInfo {
             (0, 0, -1),
         ];
 
-        for z in 0..self.context.depth() {
-            for y in 0..self.context.height() {
-                for x in 0..self.context.width() {
-                    let idx = self.context.idx(x, y, z);
+        for z in 0..ctx.depth() {
+            for y in 0..ctx.height() {
+                for x in 0..ctx.width() {
+                    let idx = ctx.idx(x, y, z);
 
                     // Only draw SOLID voxels
-                    if self.context[idx].is_air() {
+                    if ctx[idx].is_air() {
                         continue;
                     }
 
@@ -189,14 +712,11 @@ impl RunInfo {
 
                         let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
 
-                        if nx >= self.context.width()
-                            || ny >= self.context.height()
-                            || nz >= self.context.depth()
-                        {
+                        if nx >= ctx.width() || ny >= ctx.height() || nz >= ctx.depth() {
                             continue;
                         }
 
-                        let nidx = self.context.idx(nx, ny, nz);
+                        let nidx = ctx.idx(nx, ny, nz);
 
                         if let Some(comp_idx) = air_component_of[nidx] {
                             if comp_idx == main_comp_idx {
@@ -223,35 +743,169 @@ impl RunInfo {
         vox.save_to_file(path.to_string_lossy().to_string())
             .map_err(std::io::Error::other)
     }
+
+    // Voxel color index i reads palette entry i - 1, index 0 means empty
+    fn add_vox_shade(&self, vox: &mut vox_writer::VoxWriter, index: u8, brightness: f64) {
+        let Some(entry) = index.checked_sub(1) else {
+            return;
+        };
+
+        let [r, g, b, a] = self.color;
+        let shade = |c: u8| (f64::from(c) * brightness).round() as u8;
+        vox.add_color(shade(r), shade(g), shade(b), a, entry);
+    }
+
+    // Draws every air cell with the palette index of the largest band threshold its component
+    // reaches, components below the smallest threshold are left out
+    fn add_banded_air_voxels(
+        &self,
+        vox: &mut vox_writer::VoxWriter,
+        ctx: &CAContext,
+        components: &[Vec<usize>],
+    ) {
+        for comp in components {
+            let Some(&(_, color)) = (self.bands.iter())
+                .rev()
+                .find(|&&(threshold, _)| comp.len() >= threshold)
+            else {
+                continue;
+            };
+
+            for &idx in comp {
+                let (x, y, z) = ctx.pos(idx);
+                vox.add_voxel(x as i32, y as i32, z as i32, i32::from(color));
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Debug)]
+// Stable, well spread RGB color for a component label
+fn component_color(label: usize) -> [u8; 3] {
+    let hash = (label as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let [r, g, b, ..] = hash.to_be_bytes();
+    // Keep every channel away from black so small components stay visible
+    [r | 0x40, g | 0x40, b | 0x40]
+}
+
+// Animates the z-slice of every frame, air in white and rock in black, looping forever
+pub fn save_iteration_gif(
+    history: &[(usize, CAContext)],
+    z: usize,
+    path: &Path,
+    delay_cs: u16,
+) -> std::io::Result<()> {
+    let Some((_, first)) = history.first() else {
+        return Ok(());
+    };
+
+    if z >= first.depth() {
+        return Err(std::io::Error::other(format!(
+            "gif slice z={z} is outside a grid of depth {}",
+            first.depth()
+        )));
+    }
+
+    let width = u16::try_from(first.width()).map_err(std::io::Error::other)?;
+    let height = u16::try_from(first.height()).map_err(std::io::Error::other)?;
+
+    let file = File::create(path)?;
+    let palette = [0, 0, 0, 255, 255, 255];
+    let mut encoder =
+        gif::Encoder::new(file, width, height, &palette).map_err(std::io::Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(std::io::Error::other)?;
+
+    for (_, ctx) in history {
+        let mut pixels = Vec::with_capacity(ctx.width() * ctx.height());
+        for y in 0..ctx.height() {
+            for x in 0..ctx.width() {
+                pixels.push(u8::from(ctx.get(x, y, z).is_air()));
+            }
+        }
+
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+        frame.delay = delay_cs;
+        encoder.write_frame(&frame).map_err(std::io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct RunResults {
     // Identification
     pub run_id: String,
+    // Seed of the kept attempt, differs from the one in run_id after retries
     pub seed: u64,
+    #[serde(default)]
+    pub retries: usize,
     pub neighborhood: String,
     pub ruleset: String,
+    #[serde(default)]
+    pub grid_hash: u64,
 
     // Grid parameters
     pub width: usize,
     pub height: usize,
     pub depth: usize,
+    // Cells the metrics cover, only the roi's when one is set
+    #[serde(default)]
+    pub cells: usize,
     pub iterations: usize,
     pub air_prob: f64,
 
     // Performance
+    #[serde(default)]
+    pub engine_mode: String,
     pub duration_ms: u128,
+    #[serde(default)]
+    pub mem_bytes: usize,
+    #[serde(default)]
+    pub timed_out: bool,
+    #[serde(default)]
+    pub iterations_completed: usize,
+
+    // Convergence
+    #[serde(default)]
+    pub stop_reason: String,
+    #[serde(default)]
+    pub converged_at: Option<usize>,
+    #[serde(default)]
+    pub final_activity: f64,
 
     // Global density
     pub v_total: usize,
     pub porosity: f64,
 
-    // Connectivity
+    // Connectivity. Components connect through shared faces, or through the offsets of the
+    // neighborhood named in component_connectivity
+    #[serde(default)]
+    pub component_connectivity: String,
     pub n_comp: usize,
     pub v_max: usize,
     pub lcr: f64,
     pub n_islands: usize,
+    #[serde(default)]
+    pub isolated_cells: usize,
+    // Air cells whose removal splits their 6-connected component
+    #[serde(default)]
+    pub articulation_point_count: usize,
+    // Air cells with a single air face-neighbor, the tips of dead-end passages
+    #[serde(default)]
+    pub dead_end_cells: usize,
+    // Air components of at least the room threshold, a cave without any is too narrow to
+    // walk through
+    #[serde(default)]
+    pub room_count: usize,
+    #[serde(default)]
+    pub largest_room_volume: usize,
+
+    // Boundary contact
+    #[serde(default)]
+    pub boundary_air: usize,
+    #[serde(default)]
+    pub boundary_v_max: usize,
 
     // Percolation
     pub percolates_x: bool,
@@ -263,16 +917,71 @@ pub struct RunResults {
     pub roughness_mean: f64,
     pub roughness_std: f64,
 
+    // Coordination numbers (6-connectivity)
+    #[serde(default)]
+    pub coordination_mean: f64,
+    #[serde(default)]
+    pub coordination_std: f64,
+
+    // Shape factor
+    #[serde(default)]
+    pub specific_surface_area: f64,
+    #[serde(default)]
+    pub compactness: f64,
+
     // Tunnel geometry (largest component only)
     pub tunnel_radius_mean: f64,
     pub tunnel_radius_std: f64,
+
+    // Minkowski functionals of the air voxels
+    #[serde(default)]
+    pub mink_surface: usize,
+    #[serde(default)]
+    pub mink_mean_breadth: f64,
+    #[serde(default)]
+    pub mink_euler: i64,
+
+    // Path geometry
+    #[serde(default)]
+    pub tortuosity: f64,
+
+    // Lacunarity of the 2-cell gliding box, metrics::LACUNARITY_BOX
+    #[serde(default, alias = "lacunarity_r1")]
+    pub lacunarity_r2: f64,
+
+    // Normalized by grid size, porosity and specific_surface_area already are air fraction and
+    // surface-to-volume
+    #[serde(default)]
+    pub comp_per_kcell: f64,
 }
 
 impl RunResults {
     #[must_use]
-    pub fn from_context(meta: &RunMetadata, ctx: &CAContext, duration_ms: u128) -> Self {
-        // 1. Connected components (6-connectivity)
-        let components = ctx.connected_components();
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_context(
+        meta: &RunMetadata,
+        ctx: &CAContext,
+        boundary: &Boundary,
+        report: &EngineReport,
+        mem_bytes: usize,
+        tortuosity_samples: usize,
+        connectivity: Option<&CANeighborhood>,
+        room_threshold: usize,
+    ) -> Self {
+        // Inside a region of interest its faces act as solid walls
+        let grid = ctx;
+        let cropped = meta.roi.map(|roi| grid.crop(&roi));
+        let (ctx, boundary) = match &cropped {
+            Some(cropped) => (cropped, &Boundary::default()),
+            None => (grid, boundary),
+        };
+
+        // 1. Connected components (6-connectivity unless a neighborhood is given, wrapping on
+        // periodic axes)
+        let components = match connectivity {
+            Some(nb) => ctx.connected_components_via(nb, boundary),
+            None => ctx.connected_components_with(boundary),
+        };
         let v_total = ctx.total_air_cells();
         let n_comp = components.len();
         let v_max = components.iter().map(std::vec::Vec::len).max().unwrap_or(0);
@@ -281,11 +990,16 @@ impl RunResults {
         } else {
             0.0
         };
+        let rooms = topology::rooms_in(ctx, &components, room_threshold);
 
         // 2. Percolation
-        let percolates_x = ctx.percolates(&components, Axis::X);
-        let percolates_y = ctx.percolates(&components, Axis::Y);
-        let percolates_z = ctx.percolates(&components, Axis::Z);
+        let percolates = |axis| match connectivity {
+            Some(nb) => ctx.percolates_in(&components, axis, boundary, nb),
+            None => ctx.percolates_with(&components, axis, boundary),
+        };
+        let percolates_x = percolates(Axis::X);
+        let percolates_y = percolates(Axis::Y);
+        let percolates_z = percolates(Axis::Z);
 
         // 3. Roughness
         let rough = RoughnessStats::from_context(ctx);
@@ -293,31 +1007,73 @@ impl RunResults {
         // 4. Distance transform (largest component only)
         let tunnel = TunnelStats::from_context(ctx, &components);
 
+        // 5. Coordination numbers
+        let (coordination_mean, coordination_std) =
+            metrics::coordination_stats(&metrics::coordination_number_distribution(ctx));
+
+        // 6. Tortuosity over sampled air pairs
+        let tortuosity = metrics::tortuosity(ctx, tortuosity_samples, meta.seed);
+
+        // 7. Minkowski functionals
+        let mink = metrics::minkowski(ctx);
+
+        // 8. Lacunarity
+        let lacunarity = metrics::lacunarity(ctx, &[metrics::LACUNARITY_BOX])[0];
+
         Self {
             run_id: meta.run_id.clone(),
             seed: meta.seed,
+            retries: meta.retries,
             neighborhood: meta.neighborhood.clone(),
             ruleset: meta.ruleset.clone(),
+            grid_hash: grid.fingerprint(),
             width: meta.width,
             height: meta.height,
             depth: meta.depth,
+            cells: ctx.cells().len(),
             iterations: meta.iterations,
             air_prob: meta.air_prob,
-            duration_ms,
+            engine_mode: meta.engine_mode.to_string(),
+            duration_ms: report.wall_time.as_millis(),
+            mem_bytes,
+            timed_out: report.stop_reason == StopReason::TimeLimit,
+            iterations_completed: report.iterations_run,
+            stop_reason: report.stop_reason.to_string(),
+            converged_at: report.converged_at(),
+            final_activity: report.final_activity(grid.cells().len()),
             v_total,
-            porosity: v_total as f64 / (meta.width * meta.height * meta.depth) as f64,
+            porosity: v_total as f64 / ctx.cells().len() as f64,
+            component_connectivity: connectivity
+                .map_or_else(|| "faces".to_string(), |nb| nb.name.clone()),
             n_comp,
             v_max,
             lcr,
             n_islands: n_comp.saturating_sub(1),
+            isolated_cells: ctx.isolated_cell_count(),
+            articulation_point_count: metrics::articulation_points(ctx, Connectivity::Six).len(),
+            dead_end_cells: metrics::count_dead_ends(ctx, Connectivity::Six),
+            room_count: rooms.len(),
+            largest_room_volume: rooms.iter().map(|r| r.volume).max().unwrap_or(0),
+            boundary_air: metrics::boundary_air_volume(ctx),
+            boundary_v_max: metrics::largest_boundary_component(ctx, &components),
             percolates_x,
             percolates_y,
             percolates_z,
             surface_voxels: rough.count,
             roughness_mean: rough.mean,
             roughness_std: rough.std,
+            coordination_mean,
+            coordination_std,
+            specific_surface_area: metrics::specific_surface_area(ctx, boundary),
+            compactness: metrics::compactness(ctx, boundary),
             tunnel_radius_mean: tunnel.mean,
             tunnel_radius_std: tunnel.std,
+            mink_surface: mink.surface,
+            mink_mean_breadth: mink.mean_breadth,
+            mink_euler: mink.euler,
+            tortuosity,
+            lacunarity_r2: lacunarity,
+            comp_per_kcell: n_comp as f64 * 1000.0 / ctx.cells().len().max(1) as f64,
         }
     }
 
@@ -344,6 +1100,519 @@ impl RunResults {
     }
 }
 
+// Reads a metrics.csv written by the runner back in for re-analysis
+pub fn load_results_csv(path: &Path) -> Result<Vec<RunResults>, csv::Error> {
+    csv::Reader::from_path(path)?.deserialize().collect()
+}
+
+pub fn write_results_csv(path: &Path, results: &[RunResults]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for r in results {
+        writer.serialize(r)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Voxels of a .vox file as (x, y, z, palette index), in scene coordinates
+pub fn read_vox(path: &Path) -> std::io::Result<Vec<(i32, i32, i32, u8)>> {
+    Ok(read_vox_models(path)?
+        .into_iter()
+        .flat_map(|model| {
+            let (ox, oy, oz) = model.origin;
+            model.voxels.into_iter().map(move |[x, y, z, c]| {
+                (ox + i32::from(x), oy + i32::from(y), oz + i32::from(z), c)
+            })
+        })
+        .collect())
+}
+
+// One model of a .vox file placed in the scene
+pub struct VoxModel {
+    // Scene position of the model's voxel (0, 0, 0)
+    pub origin: (i32, i32, i32),
+    pub size: (i32, i32, i32),
+    // (x, y, z, palette index) inside the model
+    pub voxels: Vec<[u8; 4]>,
+}
+
+impl VoxModel {
+    // Scene position of every voxel
+    pub fn positions(&self) -> impl Iterator<Item = [i32; 3]> + '_ {
+        let (ox, oy, oz) = self.origin;
+        (self.voxels.iter())
+            .map(move |&[x, y, z, _]| [ox + i32::from(x), oy + i32::from(y), oz + i32::from(z)])
+    }
+}
+
+// Models of a .vox file. Each model is moved by the translation of the nTRN node directly above
+// its shape, which MagicaVoxel applies to the model's center
+pub fn read_vox_models(path: &Path) -> std::io::Result<Vec<VoxModel>> {
+    let bytes = fs::read(path)?;
+    let invalid = |what: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {what}", path.display()),
+        )
+    };
+    if bytes.len() < 8 || &bytes[..4] != b"VOX " {
+        return Err(invalid("not a vox file"));
+    }
+
+    let mut sizes = Vec::new();
+    let mut models = Vec::new();
+    // nTRN child node -> translation, nSHP node -> model ids
+    let mut translations = BTreeMap::new();
+    let mut shapes = Vec::new();
+
+    let mut reader = VoxReader {
+        bytes: &bytes,
+        pos: 8,
+    };
+    // MAIN only wraps the other chunks, its children are read like top-level chunks
+    while reader.pos < bytes.len() {
+        let (id, content) = reader.chunk().ok_or_else(|| invalid("truncated chunk"))?;
+        let mut chunk = VoxReader {
+            bytes: content,
+            pos: 0,
+        };
+        let parsed = match &id {
+            b"MAIN" => Some(()),
+            b"SIZE" => chunk.size().map(|size| sizes.push(size)),
+            b"XYZI" => chunk.xyzi().map(|voxels| models.push(voxels)),
+            b"nTRN" => chunk.transform().map(|(child, t)| {
+                translations.insert(child, t);
+            }),
+            b"nSHP" => chunk.shape().map(|shape| shapes.push(shape)),
+            _ => Some(()),
+        };
+        parsed
+            .ok_or_else(|| invalid(&format!("malformed {} chunk", String::from_utf8_lossy(&id))))?;
+    }
+    if sizes.len() != models.len() {
+        return Err(invalid("SIZE and XYZI chunks do not pair up"));
+    }
+
+    // Without a scene graph every model sits at the origin
+    let mut offsets = vec![(0, 0, 0); models.len()];
+    for (node, model_ids) in shapes {
+        let Some(&(tx, ty, tz)) = translations.get(&node) else {
+            continue;
+        };
+        for model in model_ids {
+            let (sx, sy, sz) = *sizes
+                .get(model)
+                .ok_or_else(|| invalid("unknown model id"))?;
+            offsets[model] = (tx - sx / 2, ty - sy / 2, tz - sz / 2);
+        }
+    }
+
+    Ok((models.into_iter().zip(sizes).zip(offsets))
+        .map(|((voxels, size), origin)| VoxModel {
+            origin,
+            size,
+            voxels,
+        })
+        .collect())
+}
+
+// Grid of a run from its grid.vox, which holds every solid cell when the run wrote no
+// vox_bands. The file does not record where the solids sit inside the grid, so they are
+// anchored by their bounding box, None when that box does not span the grid on every axis
+pub fn load_vox_grid(
+    path: &Path,
+    width: usize,
+    height: usize,
+    depth: usize,
+) -> std::io::Result<Option<CAContext>> {
+    let voxels = read_vox(path)?;
+
+    let mut ctx = CAContext::new(width, height, depth);
+    ctx.cells_mut().fill(CACell::new(1));
+    let Some(&(x0, y0, z0, _)) = voxels.first() else {
+        return Ok(Some(ctx));
+    };
+
+    let (mut min, mut max) = ([x0, y0, z0], [x0, y0, z0]);
+    for &(x, y, z, _) in &voxels {
+        for (axis, v) in [x, y, z].into_iter().enumerate() {
+            min[axis] = min[axis].min(v);
+            max[axis] = max[axis].max(v);
+        }
+    }
+    let spans = [width, height, depth];
+    if (0..3).any(|axis| (max[axis] - min[axis] + 1) as usize != spans[axis]) {
+        return Ok(None);
+    }
+
+    for (x, y, z, _) in voxels {
+        let pos = [x - min[0], y - min[1], z - min[2]].map(|v| v as usize);
+        ctx.set(pos[0], pos[1], pos[2], CACell::new(0));
+    }
+
+    Ok(Some(ctx))
+}
+
+// Cursor over the little-endian contents of a .vox chunk
+struct VoxReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> VoxReader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn count(&mut self) -> Option<usize> {
+        usize::try_from(self.i32()?).ok()
+    }
+
+    // Chunk id and contents. Children are not skipped, they follow as the next chunks
+    fn chunk(&mut self) -> Option<([u8; 4], &'a [u8])> {
+        let id = self.take(4)?.try_into().ok()?;
+        let content = self.count()?;
+        self.count()?;
+        Some((id, self.take(content)?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.count()?;
+        Some(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Option<BTreeMap<String, String>> {
+        (0..self.count()?)
+            .map(|_| Some((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    fn size(&mut self) -> Option<(i32, i32, i32)> {
+        Some((self.i32()?, self.i32()?, self.i32()?))
+    }
+
+    fn xyzi(&mut self) -> Option<Vec<[u8; 4]>> {
+        (0..self.count()?)
+            .map(|_| self.take(4)?.try_into().ok())
+            .collect()
+    }
+
+    // (child node, translation of the first frame)
+    fn transform(&mut self) -> Option<(i32, (i32, i32, i32))> {
+        self.i32()?;
+        self.dict()?;
+        let child = self.i32()?;
+        self.i32()?;
+        self.i32()?;
+        let mut frames = (0..self.count()?)
+            .map(|_| self.dict())
+            .collect::<Option<Vec<_>>>()?
+            .into_iter();
+        let translation = match frames.next().and_then(|mut frame| frame.remove("_t")) {
+            Some(t) => {
+                let mut parts = t.split_whitespace().map(str::parse);
+                (
+                    parts.next()?.ok()?,
+                    parts.next()?.ok()?,
+                    parts.next()?.ok()?,
+                )
+            }
+            None => (0, 0, 0),
+        };
+        Some((child, translation))
+    }
+
+    // (node, model ids)
+    fn shape(&mut self) -> Option<(i32, Vec<usize>)> {
+        let node = self.i32()?;
+        self.dict()?;
+        let models = (0..self.count()?)
+            .map(|_| {
+                let model = self.count()?;
+                self.dict()?;
+                Some(model)
+            })
+            .collect::<Option<_>>()?;
+        Some((node, models))
+    }
+}
+
+// SQLite store for run results, one row per run in the `runs` table
+pub struct ResultsDb {
+    conn: rusqlite::Connection,
+}
+
+// Columns of the runs table in RunResults field order. SQLite integers are signed 64-bit, so
+// the u64 hashes and seeds are stored bit-for-bit as i64
+const RUNS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id TEXT PRIMARY KEY,
+        seed INTEGER NOT NULL,
+        retries INTEGER NOT NULL,
+        neighborhood TEXT NOT NULL,
+        ruleset TEXT NOT NULL,
+        grid_hash INTEGER NOT NULL,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        depth INTEGER NOT NULL,
+        cells INTEGER NOT NULL,
+        iterations INTEGER NOT NULL,
+        air_prob REAL NOT NULL,
+        engine_mode TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        mem_bytes INTEGER NOT NULL,
+        timed_out INTEGER NOT NULL,
+        iterations_completed INTEGER NOT NULL,
+        stop_reason TEXT NOT NULL,
+        converged_at INTEGER,
+        final_activity REAL NOT NULL,
+        v_total INTEGER NOT NULL,
+        porosity REAL NOT NULL,
+        component_connectivity TEXT NOT NULL,
+        n_comp INTEGER NOT NULL,
+        v_max INTEGER NOT NULL,
+        lcr REAL NOT NULL,
+        n_islands INTEGER NOT NULL,
+        isolated_cells INTEGER NOT NULL,
+        articulation_point_count INTEGER NOT NULL,
+        dead_end_cells INTEGER NOT NULL,
+        room_count INTEGER NOT NULL,
+        largest_room_volume INTEGER NOT NULL,
+        boundary_air INTEGER NOT NULL,
+        boundary_v_max INTEGER NOT NULL,
+        percolates_x INTEGER NOT NULL,
+        percolates_y INTEGER NOT NULL,
+        percolates_z INTEGER NOT NULL,
+        surface_voxels INTEGER NOT NULL,
+        roughness_mean REAL NOT NULL,
+        roughness_std REAL NOT NULL,
+        coordination_mean REAL NOT NULL,
+        coordination_std REAL NOT NULL,
+        specific_surface_area REAL NOT NULL,
+        compactness REAL NOT NULL,
+        tunnel_radius_mean REAL NOT NULL,
+        tunnel_radius_std REAL NOT NULL,
+        mink_surface INTEGER NOT NULL,
+        mink_mean_breadth REAL NOT NULL,
+        mink_euler INTEGER NOT NULL,
+        tortuosity REAL NOT NULL,
+        lacunarity_r2 REAL NOT NULL,
+        comp_per_kcell REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS runs_ruleset_neighborhood ON runs (ruleset, neighborhood);
+";
+
+const RUNS_COLUMNS: &str = "run_id, seed, retries, neighborhood, ruleset, grid_hash, \
+    width, height, depth, cells, iterations, air_prob, engine_mode, duration_ms, mem_bytes, \
+    timed_out, iterations_completed, stop_reason, converged_at, final_activity, v_total, porosity, \
+    component_connectivity, n_comp, v_max, lcr, n_islands, isolated_cells, \
+    articulation_point_count, dead_end_cells, room_count, largest_room_volume, boundary_air, \
+    boundary_v_max, percolates_x, percolates_y, percolates_z, surface_voxels, roughness_mean, \
+    roughness_std, coordination_mean, coordination_std, specific_surface_area, compactness, \
+    tunnel_radius_mean, tunnel_radius_std, mink_surface, mink_mean_breadth, mink_euler, \
+    tortuosity, lacunarity_r2, comp_per_kcell";
+
+// Columns renamed after the first version of the runs table, as (old, new). lacunarity_r1
+// always held the lacunarity of the 2-cell box
+const RUNS_RENAMED_COLUMNS: [(&str, &str); 1] = [("lacunarity_r1", "lacunarity_r2")];
+
+// Columns added after the first version of the runs table, with the value older rows get.
+// Old runs always labeled components through faces
+const RUNS_ADDED_COLUMNS: [&str; 10] = [
+    "retries INTEGER NOT NULL DEFAULT 0",
+    "stop_reason TEXT NOT NULL DEFAULT ''",
+    "converged_at INTEGER",
+    "final_activity REAL NOT NULL DEFAULT 0",
+    "component_connectivity TEXT NOT NULL DEFAULT 'faces'",
+    "articulation_point_count INTEGER NOT NULL DEFAULT 0",
+    "dead_end_cells INTEGER NOT NULL DEFAULT 0",
+    "room_count INTEGER NOT NULL DEFAULT 0",
+    "largest_room_volume INTEGER NOT NULL DEFAULT 0",
+    "comp_per_kcell REAL NOT NULL DEFAULT 0",
+];
+
+impl ResultsDb {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Ok(Self {
+            conn: rusqlite::Connection::open(path)?,
+        })
+    }
+
+    // Creates the runs table, or adds the columns it is missing when an older version of the
+    // tool created it
+    pub fn create_schema(&self) -> rusqlite::Result<()> {
+        self.conn.execute_batch(RUNS_SCHEMA)?;
+
+        let existing = self
+            .conn
+            .prepare("PRAGMA table_info(runs)")?
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (old, new) in RUNS_RENAMED_COLUMNS {
+            if existing.iter().any(|c| c == old) && !existing.iter().any(|c| c == new) {
+                (self.conn)
+                    .execute_batch(&format!("ALTER TABLE runs RENAME COLUMN {old} TO {new}"))?;
+            }
+        }
+        for column in RUNS_ADDED_COLUMNS {
+            let name = column.split_whitespace().next().unwrap_or_default();
+            if !existing.iter().any(|c| c == name) {
+                (self.conn).execute_batch(&format!("ALTER TABLE runs ADD COLUMN {column}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Re-running a config replaces the previous row for the same run id
+    pub fn insert_result(&self, r: &RunResults) -> rusqlite::Result<()> {
+        Self::insert_into(&self.conn, r)
+    }
+
+    // All rows in one transaction, so a batch costs a single commit and lands either whole or
+    // not at all
+    pub fn insert_results(&mut self, results: &[RunResults]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for r in results {
+            Self::insert_into(&tx, r)?;
+        }
+        tx.commit()
+    }
+
+    fn insert_into(conn: &rusqlite::Connection, r: &RunResults) -> rusqlite::Result<()> {
+        let placeholders = vec!["?"; 52].join(", ");
+        let sql = format!("INSERT OR REPLACE INTO runs ({RUNS_COLUMNS}) VALUES ({placeholders})");
+
+        conn.prepare_cached(&sql)?.execute(rusqlite::params![
+            r.run_id,
+            r.seed as i64,
+            r.retries as i64,
+            r.neighborhood,
+            r.ruleset,
+            r.grid_hash as i64,
+            r.width as i64,
+            r.height as i64,
+            r.depth as i64,
+            r.cells as i64,
+            r.iterations as i64,
+            r.air_prob,
+            r.engine_mode,
+            r.duration_ms as i64,
+            r.mem_bytes as i64,
+            r.timed_out,
+            r.iterations_completed as i64,
+            r.stop_reason,
+            r.converged_at.map(|k| k as i64),
+            r.final_activity,
+            r.v_total as i64,
+            r.porosity,
+            r.component_connectivity,
+            r.n_comp as i64,
+            r.v_max as i64,
+            r.lcr,
+            r.n_islands as i64,
+            r.isolated_cells as i64,
+            r.articulation_point_count as i64,
+            r.dead_end_cells as i64,
+            r.room_count as i64,
+            r.largest_room_volume as i64,
+            r.boundary_air as i64,
+            r.boundary_v_max as i64,
+            r.percolates_x,
+            r.percolates_y,
+            r.percolates_z,
+            r.surface_voxels as i64,
+            r.roughness_mean,
+            r.roughness_std,
+            r.coordination_mean,
+            r.coordination_std,
+            r.specific_surface_area,
+            r.compactness,
+            r.tunnel_radius_mean,
+            r.tunnel_radius_std,
+            r.mink_surface as i64,
+            r.mink_mean_breadth,
+            r.mink_euler,
+            r.tortuosity,
+            r.lacunarity_r2,
+            r.comp_per_kcell,
+        ])?;
+
+        Ok(())
+    }
+
+    pub fn query_by_ruleset(&self, name: &str) -> rusqlite::Result<Vec<RunResults>> {
+        let sql = format!("SELECT {RUNS_COLUMNS} FROM runs WHERE ruleset = ?1 ORDER BY run_id");
+        let mut stmt = self.conn.prepare(&sql)?;
+
+        stmt.query_map([name], Self::row_to_results)?.collect()
+    }
+
+    fn row_to_results(row: &rusqlite::Row) -> rusqlite::Result<RunResults> {
+        let unsigned = |i: usize| row.get::<_, i64>(i).map(|v| v as usize);
+
+        Ok(RunResults {
+            run_id: row.get(0)?,
+            seed: row.get::<_, i64>(1)? as u64,
+            retries: unsigned(2)?,
+            neighborhood: row.get(3)?,
+            ruleset: row.get(4)?,
+            grid_hash: row.get::<_, i64>(5)? as u64,
+            width: unsigned(6)?,
+            height: unsigned(7)?,
+            depth: unsigned(8)?,
+            cells: unsigned(9)?,
+            iterations: unsigned(10)?,
+            air_prob: row.get(11)?,
+            engine_mode: row.get(12)?,
+            duration_ms: row.get::<_, i64>(13)? as u128,
+            mem_bytes: unsigned(14)?,
+            timed_out: row.get(15)?,
+            iterations_completed: unsigned(16)?,
+            stop_reason: row.get(17)?,
+            converged_at: row.get::<_, Option<i64>>(18)?.map(|k| k as usize),
+            final_activity: row.get(19)?,
+            v_total: unsigned(20)?,
+            porosity: row.get(21)?,
+            component_connectivity: row.get(22)?,
+            n_comp: unsigned(23)?,
+            v_max: unsigned(24)?,
+            lcr: row.get(25)?,
+            n_islands: unsigned(26)?,
+            isolated_cells: unsigned(27)?,
+            articulation_point_count: unsigned(28)?,
+            dead_end_cells: unsigned(29)?,
+            room_count: unsigned(30)?,
+            largest_room_volume: unsigned(31)?,
+            boundary_air: unsigned(32)?,
+            boundary_v_max: unsigned(33)?,
+            percolates_x: row.get(34)?,
+            percolates_y: row.get(35)?,
+            percolates_z: row.get(36)?,
+            surface_voxels: unsigned(37)?,
+            roughness_mean: row.get(38)?,
+            roughness_std: row.get(39)?,
+            coordination_mean: row.get(40)?,
+            coordination_std: row.get(41)?,
+            specific_surface_area: row.get(42)?,
+            compactness: row.get(43)?,
+            tunnel_radius_mean: row.get(44)?,
+            tunnel_radius_std: row.get(45)?,
+            mink_surface: unsigned(46)?,
+            mink_mean_breadth: row.get(47)?,
+            mink_euler: row.get(48)?,
+            tortuosity: row.get(49)?,
+            lacunarity_r2: row.get(50)?,
+            comp_per_kcell: row.get(51)?,
+        })
+    }
+}
+
 // Helper struct for surface roughness statistics
 pub struct RoughnessStats {
     pub count: usize,
@@ -613,3 +1882,189 @@ impl DiversityStats {
         Ok(())
     }
 }
+
+// Mean and standard deviation of the headline metrics for one (neighborhood, ruleset) pair
+#[derive(Serialize, Debug)]
+pub struct SummaryRow {
+    pub neighborhood: String,
+    pub ruleset: String,
+    pub count: usize,
+
+    pub lcr_mean: f64,
+    pub lcr_std: f64,
+    pub n_comp_mean: f64,
+    pub n_comp_std: f64,
+    pub v_max_mean: f64,
+    pub v_max_std: f64,
+    pub tortuosity_mean: f64,
+    pub tortuosity_std: f64,
+    pub duration_ms_mean: f64,
+    pub duration_ms_std: f64,
+}
+
+// One row per (neighborhood, ruleset), sorted by neighborhood then ruleset
+#[must_use]
+pub fn summarize(results: &[RunResults]) -> Vec<SummaryRow> {
+    let mut groups: BTreeMap<(&str, &str), Vec<&RunResults>> = BTreeMap::new();
+    for r in results {
+        (groups.entry((&r.neighborhood, &r.ruleset)).or_default()).push(r);
+    }
+
+    groups
+        .into_iter()
+        .map(|((neighborhood, ruleset), runs)| {
+            let stat = |f: fn(&RunResults) -> f64| {
+                DiversityStats::mean_std(&runs.iter().map(|&r| f(r)).collect::<Vec<_>>())
+            };
+
+            let (lcr_mean, lcr_std) = stat(|r| r.lcr);
+            let (n_comp_mean, n_comp_std) = stat(|r| r.n_comp as f64);
+            let (v_max_mean, v_max_std) = stat(|r| r.v_max as f64);
+            let (tortuosity_mean, tortuosity_std) = stat(|r| r.tortuosity);
+            let (duration_ms_mean, duration_ms_std) = stat(|r| r.duration_ms as f64);
+
+            SummaryRow {
+                neighborhood: neighborhood.to_string(),
+                ruleset: ruleset.to_string(),
+                count: runs.len(),
+                lcr_mean,
+                lcr_std,
+                n_comp_mean,
+                n_comp_std,
+                v_max_mean,
+                v_max_std,
+                tortuosity_mean,
+                tortuosity_std,
+                duration_ms_mean,
+                duration_ms_std,
+            }
+        })
+        .collect()
+}
+
+// Fixed-width mean ± std table of the summary rows for the terminal
+#[must_use]
+pub fn summary_table(rows: &[SummaryRow]) -> String {
+    let nb_width = (rows.iter().map(|r| r.neighborhood.len()))
+        .chain([12])
+        .max()
+        .unwrap_or(12);
+    let rs_width = (rows.iter().map(|r| r.ruleset.len()))
+        .chain([7])
+        .max()
+        .unwrap_or(7);
+    let pm =
+        |mean: f64, std: f64, precision: usize| format!("{mean:.precision$} ± {std:.precision$}");
+
+    let mut table = format!(
+        "{:<nb_width$}  {:<rs_width$}  {:>5}  {:>17}  {:>21}  {:>21}  {:>17}  {:>21}\n",
+        "neighborhood", "ruleset", "runs", "lcr", "n_comp", "v_max", "tortuosity", "duration_ms"
+    );
+
+    for r in rows {
+        table.push_str(&format!(
+            "{:<nb_width$}  {:<rs_width$}  {:>5}  {:>17}  {:>21}  {:>21}  {:>17}  {:>21}\n",
+            r.neighborhood,
+            r.ruleset,
+            r.count,
+            pm(r.lcr_mean, r.lcr_std, 4),
+            pm(r.n_comp_mean, r.n_comp_std, 1),
+            pm(r.v_max_mean, r.v_max_std, 1),
+            pm(r.tortuosity_mean, r.tortuosity_std, 4),
+            pm(r.duration_ms_mean, r.duration_ms_std, 1),
+        ));
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_csv_without_newer_columns_loads() {
+        let path = std::env::temp_dir().join("gradwork-ca-old-metrics.csv");
+        let header = "run_id,seed,neighborhood,ruleset,width,height,depth,iterations,air_prob,\
+                      duration_ms,v_total,porosity,n_comp,v_max,lcr,n_islands,percolates_x,\
+                      percolates_y,percolates_z,surface_voxels,roughness_mean,roughness_std,\
+                      tunnel_radius_mean,tunnel_radius_std";
+        let row = "old,3,moore,B,8,8,8,5,0.5,12,200,0.39,4,150,0.75,2,true,false,false,90,\
+                   0.5,0.1,1.5,0.4";
+        fs::write(&path, format!("{header}\n{row}\n")).unwrap();
+
+        let rows = load_results_csv(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            (rows[0].run_id.as_str(), rows[0].seed, rows[0].v_max),
+            ("old", 3, 150)
+        );
+        assert_eq!((rows[0].retries, rows[0].room_count), (0, 0));
+    }
+
+    #[test]
+    fn batch_inserts_round_trip() {
+        let csv = std::env::temp_dir().join("gradwork-ca-batch.csv");
+        let header = "run_id,seed,neighborhood,ruleset,width,height,depth,iterations,air_prob,\
+                      duration_ms,v_total,porosity,n_comp,v_max,lcr,n_islands,percolates_x,\
+                      percolates_y,percolates_z,surface_voxels,roughness_mean,roughness_std,\
+                      tunnel_radius_mean,tunnel_radius_std";
+        let rows: Vec<String> = (0..3)
+            .map(|seed| {
+                format!(
+                    "run{seed},{seed},moore,B,8,8,8,5,0.5,12,200,0.39,4,150,0.75,2,true,false,\
+                     false,90,0.5,0.1,1.5,0.4"
+                )
+            })
+            .collect();
+        fs::write(&csv, format!("{header}\n{}\n", rows.join("\n"))).unwrap();
+        let results = load_results_csv(&csv).unwrap();
+        fs::remove_file(&csv).unwrap();
+
+        let path = std::env::temp_dir().join("gradwork-ca-batch.db");
+        let _ = fs::remove_file(&path);
+        let mut db = ResultsDb::open(&path).unwrap();
+        db.create_schema().unwrap();
+        db.insert_results(&results).unwrap();
+        // A second batch replaces the rows instead of adding to them
+        db.insert_results(&results).unwrap();
+        let stored = db.query_by_ruleset("B").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(stored, results);
+    }
+
+    #[test]
+    fn old_results_db_gains_the_new_columns() {
+        let path = std::env::temp_dir().join("gradwork-ca-old-results.db");
+        let _ = fs::remove_file(&path);
+        let db = ResultsDb::open(&path).unwrap();
+        // The runs table as its first version created it
+        let columns = "run_id, seed, neighborhood, ruleset, grid_hash, width, height, depth, \
+                       cells, iterations, air_prob, engine_mode, duration_ms, mem_bytes, \
+                       timed_out, iterations_completed, v_total, porosity, n_comp, v_max, lcr, \
+                       n_islands, isolated_cells, boundary_air, boundary_v_max, percolates_x, \
+                       percolates_y, percolates_z, surface_voxels, roughness_mean, \
+                       roughness_std, coordination_mean, coordination_std, \
+                       specific_surface_area, compactness, tunnel_radius_mean, \
+                       tunnel_radius_std, mink_surface, mink_mean_breadth, mink_euler, \
+                       tortuosity, lacunarity_r1";
+        let values = "'old', 3, 'moore', 'B', 7, 8, 8, 8, 512, 5, 0.5, 'dense', 12, 0, 0, 5, \
+                      200, 0.39, 4, 150, 0.75, 2, 1, 20, 10, 1, 0, 0, 90, 0.5, 0.1, 3.0, 1.0, \
+                      0.4, 0.2, 1.5, 0.4, 90, 1.0, 2, 1.2, 1.1";
+        db.conn
+            .execute_batch(&format!(
+                "CREATE TABLE runs ({columns}); INSERT INTO runs ({columns}) VALUES ({values});"
+            ))
+            .unwrap();
+
+        db.create_schema().unwrap();
+        let rows = db.query_by_ruleset("B").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].seed, rows[0].v_max, rows[0].retries), (3, 150, 0));
+        assert_eq!(rows[0].component_connectivity, "faces");
+        assert_eq!(rows[0].converged_at, None);
+        assert!((rows[0].lacunarity_r2 - 1.1).abs() < f64::EPSILON);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod ca;
 pub mod data;
+pub mod metrics;
 pub mod runner;
+pub mod topology;
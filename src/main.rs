@@ -1,59 +1,481 @@
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use gradwork_ca::ca::{CANeighborhood, CARule};
-use gradwork_ca::runner::{Runner, RunnerConfig};
+use gradwork_ca::ca::{
+    Axis, Boundary, CAConfig, CANeighborhood, CAPipeline, CARule, CAStage, CellRng, Composition,
+    CountStates, EngineMode, EntranceParams, Initializer, Layer, Region, RuleSweep, ShellSpec,
+    Stamp, Symmetry, hash_seed,
+};
+use gradwork_ca::data::{DEFAULT_VOX_COLOR, LogFormat};
+use gradwork_ca::runner::{
+    ConfigProblem, DEFAULT_METRICS_PATH_TEMPLATE, DEFAULT_RUN_DIR_TEMPLATE, RetryPolicy, Runner,
+    RunnerConfig, reanalyze,
+};
+use gradwork_ca::topology::CaveGraphParams;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use schemars::JsonSchema;
 use serde::Deserialize;
 
+const VERIFY_GRID_SIZE: usize = 16;
+// Smaller air components are too narrow to count as a room
+const DEFAULT_ROOM_THRESHOLD: usize = 1000;
+// Names end up in run directories and CSV rows
+const MAX_NEIGHBORHOOD_NAME_LEN: usize = 64;
+
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to experiment config file
-    #[arg(value_name = "FILE")]
-    file: String,
+    #[arg(value_name = "FILE", required = true)]
+    file: Option<String>,
+
+    /// Cross-check the parallel update against the sequential reference before running
+    #[arg(long)]
+    verify: bool,
+
+    /// Save an animated GIF of a z-slice over all iterations for every run
+    #[arg(long)]
+    save_gif: bool,
+
+    /// Z-slice to animate, defaults to the middle of the grid
+    #[arg(long, value_name = "Z")]
+    gif_z: Option<usize>,
+
+    /// Delay between GIF frames in centiseconds
+    #[arg(long, value_name = "CS", default_value_t = 20)]
+    gif_delay: u16,
+
+    /// Write a vox snapshot every N iterations plus the final one, 0 disables snapshots
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    snapshot_every: usize,
+
+    /// Run this neighborhood instead of the config's, e.g. moore or extended_moore(2). Repeatable
+    #[arg(long = "neighborhood", value_name = "NAME")]
+    neighborhoods: Vec<CANeighborhood>,
+
+    /// Air components of at least N cells count as rooms
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_ROOM_THRESHOLD)]
+    room_threshold: usize,
+
+    /// Fill dead-end passages with rock for N passes after the run, each pass also takes the
+    /// tips the previous one exposed
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    remove_dead_ends: usize,
+
+    /// Redo runs already in the output folder instead of reusing their results. Needed after
+    /// changing settings the run name does not include, such as the boundary
+    #[arg(long)]
+    overwrite: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Recompute metrics.csv from the runs saved in an output folder without running the CA
+    Reanalyze {
+        /// Output folder holding runs/ and metrics.csv
+        #[arg(value_name = "DIR", default_value = "data")]
+        dir: PathBuf,
+
+        /// Air cell pairs sampled for tortuosity
+        #[arg(long, value_name = "N", default_value_t = 64)]
+        tortuosity_samples: usize,
+
+        /// Air components of at least N cells count as rooms
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_ROOM_THRESHOLD)]
+        room_threshold: usize,
+
+        /// The output.metrics_path_template the runs were made with
+        #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_METRICS_PATH_TEMPLATE)]
+        metrics_path_template: String,
+    },
+    /// Print the JSON Schema of the experiment config, for editor completion. The repository
+    /// keeps a copy in config.schema.json
+    PrintSchema,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct ExperimentConfig {
     folder: Option<PathBuf>,
     grid: GridConfig,
     generator: GeneratorConfig,
     seeds: SeedConfig,
-    neighborhoods: Vec<NeighborhoodConfig>,
+    neighborhoods: Vec<NeighborhoodEntry>,
+    #[serde(default)]
     rulesets: Vec<CARule>,
+    rule_sweep: Option<RuleSweep>,
+    #[serde(default)]
+    pipelines: Vec<PipelineConfig>,
+    #[serde(default)]
+    metrics: MetricsConfig,
+    #[serde(default)]
+    output: OutputConfig,
+    #[serde(default)]
+    engine_mode: EngineMode,
+    #[serde(default)]
+    boundary: BoundaryConfig,
+    // {"threshold": n} counts states >= n, {"states": [..]} only the listed ones
+    #[serde(default)]
+    count_states: CountStates,
+    // Add the cell's own state to its neighbor count before the rule reads it
+    #[serde(default)]
+    include_self: bool,
+    #[serde(default)]
+    postprocess: PostProcessConfig,
+    // Evolve only this box, {"min": [x, y, z], "max": [x, y, z]} with max exclusive
+    region: Option<Region>,
+    // Take metrics over this box only, same format as region. Its faces count as solid walls
+    roi: Option<Region>,
+    time_limit_secs: Option<f64>,
+    #[serde(default)]
+    strict: bool,
+    // Air pockets stamped onto every initial grid, e.g.
+    // {"kind": "sphere", "center": [64, 64, 32], "radius": 10}, "units": "fraction" for sizes
+    // relative to the grid. Frozen layers and a frozen shell are left as they are
+    #[serde(default)]
+    seeds_geometry: Vec<Stamp>,
+    // Redo runs ending with too little or too much air, {"min_air", "max_air", "max_retries"}
+    retry: Option<RetryPolicy>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct GridConfig {
     width: usize,
     height: usize,
     depth: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct GeneratorConfig {
     air_percentage: f64,
+    // Sweeps the initial air fraction instead, overriding air_percentage
+    air_probs: Option<AirProbs>,
+    iterations: usize,
+    #[serde(default)]
+    initializer: Initializer,
+    // "mirror_x", "mirror_xy" or "rotational_z2", turns the random initializer into a symmetric one
+    symmetry: Option<Symmetry>,
+    // Strata from the top down replacing the random initializer, e.g. {"z_from": 0.0,
+    // "z_to": 0.3, "air_prob": 0.55, "frozen": false, "units": "fraction"}
+    #[serde(default)]
+    layers: Vec<Layer>,
+    // Air shafts from the top face, e.g. {"count": 2, "radius": 2, "depth": 20, "frozen": true}
+    entrances: Option<EntranceParams>,
+    // Solid border on every face, the per-face keys override it. Top is z = 0
+    #[serde(default)]
+    shell_thickness: usize,
+    shell_top: Option<usize>,
+    shell_bottom: Option<usize>,
+    shell_min_x: Option<usize>,
+    shell_max_x: Option<usize>,
+    shell_min_y: Option<usize>,
+    shell_max_y: Option<usize>,
+    #[serde(default)]
+    shell_frozen: bool,
+}
+
+impl GeneratorConfig {
+    // The initializer with the symmetry and layers shorthands applied, plus the problems with
+    // them. On a conflict the layers win so their stack is still validated
+    fn initializer(&self) -> (Initializer, Vec<ConfigProblem>) {
+        let mut problems = Vec::new();
+        let (key, replacement) = match (self.symmetry, self.layers.is_empty()) {
+            (None, true) => return (self.initializer.clone(), problems),
+            (Some(symmetry), true) => ("symmetry", Initializer::Symmetric { symmetry }),
+            (symmetry, false) => {
+                if symmetry.is_some() {
+                    problems.push(ConfigProblem::SymmetryWithLayers);
+                }
+                let layers = self.layers.clone();
+                ("layers", Initializer::Layers { layers })
+            }
+        };
+        if self.initializer != Initializer::Random {
+            problems.push(ConfigProblem::ShorthandInitializer(key));
+        }
+        (replacement, problems)
+    }
+
+    fn shell(&self) -> ShellSpec {
+        let t = self.shell_thickness;
+        ShellSpec {
+            min_x: self.shell_min_x.unwrap_or(t),
+            max_x: self.shell_max_x.unwrap_or(t),
+            min_y: self.shell_min_y.unwrap_or(t),
+            max_y: self.shell_max_y.unwrap_or(t),
+            min_z: self.shell_top.unwrap_or(t),
+            max_z: self.shell_bottom.unwrap_or(t),
+            frozen: self.shell_frozen,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+struct MetricsConfig {
+    tortuosity_samples: usize,
+    // Connect air components through the rule's neighborhood, e.g. 26-connected under Moore
+    neighborhood_components: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            tortuosity_samples: 64,
+            neighborhood_components: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(default)]
+struct OutputConfig {
+    // [min component size, palette index] pairs
+    vox_bands: Vec<(usize, u8)>,
+    // RGBA palette color of grid.vox, stone gray when absent
+    vox_color: Option<[u8; 4]>,
+    distance_vox: bool,
+    // Axes to write density map PNGs along, "x", "y" and/or "z"
+    projections: Vec<Axis>,
+    // Write grid.ply point clouds, optionally colored by air component
+    ply: bool,
+    ply_colors: bool,
+    // SQLite database to insert results into next to metrics.csv
+    db_path: Option<PathBuf>,
+    // false keeps only metadata.json and the log per run, enough to replay it
+    save_grids: bool,
+    // "text" writes log.txt, "json" writes log.json with the per-iteration stats
+    log_format: LogFormat,
+    // Paths below the output folder, {run_id}, {seed}, {neighborhood}, {ruleset}, {width},
+    // {height} and {depth} are filled in. The metrics path only takes the grid size
+    run_dir_template: String,
+    metrics_path_template: String,
+    // { "room_min_volume": .., "corridor_max_width": .. } writes cave_graph.json per run
+    cave_graph: Option<CaveGraphParams>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            vox_bands: Vec::new(),
+            vox_color: None,
+            distance_vox: false,
+            projections: Vec::new(),
+            ply: false,
+            ply_colors: false,
+            db_path: None,
+            save_grids: true,
+            log_format: LogFormat::Text,
+            run_dir_template: DEFAULT_RUN_DIR_TEMPLATE.to_string(),
+            metrics_path_template: DEFAULT_METRICS_PATH_TEMPLATE.to_string(),
+            cave_graph: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+struct PostProcessConfig {
+    clean_isolated: bool,
+    // "x", "y" or "z", carves a tunnel between that axis' faces if the cave doesn't percolate
+    carve_path: Option<Axis>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct PipelineConfig {
+    name: String,
+    stages: Vec<StageConfig>,
+    #[serde(default)]
+    composition: Composition,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct StageConfig {
+    neighborhood: NeighborhoodEntry,
+    rule: CARule,
     iterations: usize,
 }
 
-#[derive(Debug, Deserialize)]
+// A preset name like "tube_x" or explicit per-axis modes
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum BoundaryConfig {
+    Preset(String),
+    Axes(Boundary),
+}
+
+impl Default for BoundaryConfig {
+    fn default() -> Self {
+        BoundaryConfig::Axes(Boundary::default())
+    }
+}
+
+impl BoundaryConfig {
+    fn resolve(&self) -> Boundary {
+        match self {
+            BoundaryConfig::Preset(name) => Boundary::preset(name).unwrap_or_else(|| {
+                panic!(
+                    "Unknown boundary preset: {name}, expected one of {:?}",
+                    Boundary::PRESETS
+                )
+            }),
+            BoundaryConfig::Axes(boundary) => *boundary,
+        }
+    }
+}
+
+// A list of air fractions, or every step from start up to and including stop
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum AirProbs {
+    List(Vec<f64>),
+    Range { start: f64, stop: f64, step: f64 },
+}
+
+impl AirProbs {
+    // An empty range is left for validation to report
+    fn resolve(&self) -> Vec<f64> {
+        match self {
+            AirProbs::List(probs) => probs.clone(),
+            AirProbs::Range { start, stop, step } => {
+                if !(*step > 0.0 && start <= stop) {
+                    return Vec::new();
+                }
+                let n = ((stop - start) / step + 1e-9).floor() as usize + 1;
+                // Rounded so 0.1 + 2 * 0.1 is 0.3 again
+                (0..n)
+                    .map(|k| ((start + k as f64 * step) * 1e9).round() / 1e9)
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct SeedConfig {
-    base: u64,
+    base: SeedBase,
     count: usize,
+    #[serde(default)]
+    method: SeedMethod,
+    // Hash every seed with the run's neighborhood, ruleset and seed index, so no two
+    // configurations share an RNG stream
+    #[serde(default)]
+    per_config: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum SeedMethod {
+    // Counting up from a number base, hashing with the index for a string base
+    #[default]
+    Sequential,
+    // One seed from each of `count` equal strata of the u64 range
+    Lhs,
+}
+
+// A number counts up from itself, a string is hashed together with every index
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SeedBase {
+    Number(u64),
+    Text(String),
+}
+
+impl SeedConfig {
+    fn resolve(&self) -> Vec<u64> {
+        match (&self.method, &self.base) {
+            (SeedMethod::Sequential, SeedBase::Number(base)) => generate_seeds(self.count, *base),
+            (SeedMethod::Sequential, SeedBase::Text(base)) => {
+                generate_seeds_hashed(self.count, base)
+            }
+            (SeedMethod::Lhs, SeedBase::Number(base)) => generate_seeds_lhs(self.count, *base),
+            (SeedMethod::Lhs, SeedBase::Text(base)) => {
+                generate_seeds_lhs(self.count, generate_seeds_hashed(1, base)[0])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct NeighborhoodConfig {
     #[serde(rename = "type")]
     kind: String,
     radius: Option<i32>,
+    // Only used by "compose"
+    name: Option<String>,
+    base: Option<NeighborhoodRef>,
+    add: Option<NeighborhoodRef>,
+    remove: Option<NeighborhoodRef>,
+}
+
+// Either the string form of a built-in neighborhood like "extended_moore(3)" or a full table
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum NeighborhoodEntry {
+    Parsed(String),
+    Config(NeighborhoodConfig),
+}
+
+impl NeighborhoodEntry {
+    fn resolve(&self) -> CANeighborhood {
+        match self {
+            NeighborhoodEntry::Parsed(s) => s.parse().unwrap_or_else(|e| panic!("{e}")),
+            NeighborhoodEntry::Config(cfg) => build_neighborhood(cfg),
+        }
+    }
+}
+
+// A built-in neighborhood by name or a literal offset list
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum NeighborhoodRef {
+    Named(String),
+    Offsets(Vec<(i32, i32, i32)>),
+}
+
+impl NeighborhoodRef {
+    fn resolve(&self) -> CANeighborhood {
+        match self {
+            NeighborhoodRef::Named(kind) => build_neighborhood(&NeighborhoodConfig {
+                kind: kind.clone(),
+                radius: None,
+                name: None,
+                base: None,
+                add: None,
+                remove: None,
+            }),
+            NeighborhoodRef::Offsets(offsets) => CANeighborhood::custom("custom", offsets),
+        }
+    }
 }
 
 fn generate_seeds(n: usize, base: u64) -> Vec<u64> {
     (0..n).map(|i| base + i as u64).collect()
 }
 
+// Hash of the base string followed by the index bytes
+fn generate_seeds_hashed(n: usize, base: &str) -> Vec<u64> {
+    (0..n as u64)
+        .map(|i| hash_seed(&[base.as_bytes(), &i.to_le_bytes()]))
+        .collect()
+}
+
+// Latin hypercube over the seed space: the u64 range is cut into n equal strata and each one
+// contributes a single seed drawn from the base seed's stream
+fn generate_seeds_lhs(n: usize, base_seed: u64) -> Vec<u64> {
+    let stratum = (u128::from(u64::MAX) + 1) / n.max(1) as u128;
+
+    (0..n as u64)
+        .map(|k| {
+            let offset = u128::from(CellRng::new(base_seed, 0, k).next_u64()) % stratum;
+            (u128::from(k) * stratum + offset) as u64
+        })
+        .collect()
+}
+
 fn load_config(path: &std::path::Path) -> ExperimentConfig {
     let text = std::fs::read_to_string(path).expect("Failed to read config file");
     serde_json::from_str(&text).expect("Invalid config format")
@@ -63,48 +485,267 @@ fn build_neighborhood(cfg: &NeighborhoodConfig) -> CANeighborhood {
     match cfg.kind.as_str() {
         "von_neumann" => CANeighborhood::von_neumann(),
         "moore" => CANeighborhood::moore(),
+        "layered_moore" => CANeighborhood::layered_moore(),
+        "cross_2d" => CANeighborhood::cross_2d(),
+        "fcc" => CANeighborhood::fcc12(),
+        "bcc" => CANeighborhood::bcc8(),
         "extended_moore" => {
             let r = cfg.radius.unwrap_or(2);
             CANeighborhood::extended_moore(r)
         }
+        "extended_von_neumann" => {
+            let r = cfg.radius.unwrap_or(2);
+            assert!(r >= 1, "extended_von_neumann radius must be >= 1, got {r}");
+            CANeighborhood::extended_von_neumann(r)
+        }
+        "compose" => build_composed_neighborhood(cfg),
         other => panic!("Unknown neighborhood type: {other}"),
     }
 }
 
-fn resolve_config(args: &Args) -> RunnerConfig {
-    let cfg = load_config(Path::new(&args.file));
+fn build_composed_neighborhood(cfg: &NeighborhoodConfig) -> CANeighborhood {
+    let base = cfg
+        .base
+        .as_ref()
+        .expect("compose neighborhood requires a base");
+    let mut nb = base.resolve();
+
+    if let Some(add) = &cfg.add {
+        nb = nb.union(&add.resolve());
+    }
+    if let Some(remove) = &cfg.remove {
+        nb = nb.difference(&remove.resolve());
+    }
+    if let Some(name) = &cfg.name {
+        nb.name.clone_from(name);
+    }
+
+    assert!(
+        !nb.is_empty(),
+        "Composed neighborhood {} has no offsets",
+        nb.name
+    );
+    assert!(
+        nb.name.len() <= MAX_NEIGHBORHOOD_NAME_LEN,
+        "Composed neighborhood name {} is longer than {MAX_NEIGHBORHOOD_NAME_LEN} characters, set an explicit name",
+        nb.name
+    );
+
+    nb
+}
+
+fn build_pipeline(
+    cfg: &PipelineConfig,
+    boundary: Boundary,
+    count_states: &CountStates,
+    include_self: bool,
+) -> CAPipeline {
+    assert!(
+        !cfg.stages.is_empty(),
+        "Pipeline {} has no stages",
+        cfg.name
+    );
+
+    let stages = (cfg.stages.iter())
+        .map(|stage| CAStage {
+            config: CAConfig {
+                neighborhood: stage.neighborhood.resolve(),
+                rule: stage.rule.clone(),
+                boundary,
+                count_states: count_states.clone(),
+                include_self,
+            },
+            iterations: stage.iterations,
+        })
+        .collect();
+
+    CAPipeline {
+        name: cfg.name.clone(),
+        stages,
+        composition: cfg.composition,
+    }
+}
+
+fn resolve_vox_color(color: Option<[u8; 4]>) -> [u8; 4] {
+    let Some(color) = color else {
+        return DEFAULT_VOX_COLOR;
+    };
+
+    // Components above 255 are already rejected while parsing
+    assert!(
+        color[3] > 0,
+        "vox_color {color:?} is fully transparent, the exported cave would be invisible"
+    );
+    color
+}
+
+fn resolve_config(args: &Args, file: &str) -> RunnerConfig {
+    let cfg = load_config(Path::new(file));
     let width = cfg.grid.width;
     let height = cfg.grid.height;
     let depth = cfg.grid.depth;
 
-    let air_percentage = cfg.generator.air_percentage;
+    let air_probs = (cfg.generator.air_probs.as_ref())
+        .map_or_else(|| vec![cfg.generator.air_percentage], AirProbs::resolve);
     let iterations = cfg.generator.iterations;
-    let seeds = generate_seeds(cfg.seeds.count, cfg.seeds.base);
+    let seeds = cfg.seeds.resolve();
 
-    let neighborhoods = cfg.neighborhoods.iter().map(build_neighborhood).collect();
+    let neighborhoods: Vec<_> = if args.neighborhoods.is_empty() {
+        cfg.neighborhoods
+            .iter()
+            .map(NeighborhoodEntry::resolve)
+            .collect()
+    } else {
+        args.neighborhoods.clone()
+    };
+
+    let mut rulesets = cfg.rulesets;
+    if let Some(sweep) = &cfg.rule_sweep {
+        rulesets.extend(sweep.expand().expect("Invalid rule sweep"));
+    }
+
+    let boundary = cfg.boundary.resolve();
+    let pipelines = (cfg.pipelines.iter())
+        .map(|p| build_pipeline(p, boundary, &cfg.count_states, cfg.include_self))
+        .collect();
 
-    let rulesets = cfg.rulesets;
     let mut output_dir = PathBuf::from("data");
     if let Some(folder) = cfg.folder {
         output_dir = folder;
     }
 
+    let (initializer, file_problems) = cfg.generator.initializer();
     RunnerConfig {
         width,
         height,
         depth,
-        air_percentage,
+        air_probs,
+        shell: cfg.generator.shell(),
+        stamps: cfg.seeds_geometry,
+        entrances: cfg.generator.entrances,
+        initializer,
         iterations,
         seeds,
+        hash_seeds: cfg.seeds.per_config,
         neighborhoods,
         rulesets,
+        pipelines,
         output_dir,
+        run_dir_template: cfg.output.run_dir_template,
+        metrics_path_template: cfg.output.metrics_path_template,
+        db_path: cfg.output.db_path,
+        tortuosity_samples: cfg.metrics.tortuosity_samples,
+        neighborhood_components: cfg.metrics.neighborhood_components,
+        room_threshold: args.room_threshold,
+        vox_bands: cfg.output.vox_bands,
+        vox_color: resolve_vox_color(cfg.output.vox_color),
+        distance_vox: cfg.output.distance_vox,
+        projections: cfg.output.projections,
+        ply: cfg.output.ply,
+        ply_colors: cfg.output.ply_colors,
+        save_grids: cfg.output.save_grids,
+        log_format: cfg.output.log_format,
+        cave_graph: cfg.output.cave_graph,
+        engine_mode: cfg.engine_mode,
+        boundary,
+        count_states: cfg.count_states,
+        include_self: cfg.include_self,
+        clean_isolated: cfg.postprocess.clean_isolated,
+        remove_dead_ends: args.remove_dead_ends,
+        carve_path: cfg.postprocess.carve_path,
+        region: cfg.region,
+        roi: cfg.roi,
+        time_limit: cfg.time_limit_secs.map(Duration::from_secs_f64),
+        strict: cfg.strict,
+        save_gif: args.save_gif,
+        gif_z: args.gif_z.unwrap_or(depth / 2),
+        gif_delay_cs: args.gif_delay,
+        snapshot_every: args.snapshot_every,
+        overwrite: args.overwrite,
+        retry: cfg.retry,
+        file_problems,
     }
 }
 
 fn main() {
     let args = Args::parse();
-    let cfg = resolve_config(&args);
+    if let Some(Command::PrintSchema) = &args.command {
+        let schema = schemars::schema_for!(ExperimentConfig);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema).expect("Failed to serialize the config schema")
+        );
+        return;
+    }
+    if let Some(Command::Reanalyze {
+        dir,
+        tortuosity_samples,
+        room_threshold,
+        metrics_path_template,
+    }) = &args.command
+    {
+        match reanalyze(
+            dir,
+            metrics_path_template,
+            *tortuosity_samples,
+            *room_threshold,
+        ) {
+            Ok(done) => println!(
+                "Reanalyzed {} runs, {} of them replayed, {} skipped",
+                done.runs, done.replayed, done.skipped
+            ),
+            Err(err) => {
+                eprintln!("Reanalyze failed: {err}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let file = args.file.as_deref().expect("clap requires FILE");
+    let cfg = resolve_config(&args, file);
+    let runner = Runner::new(cfg).unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    });
+
+    if args.verify {
+        if let Err(report) = runner.verify(VERIFY_GRID_SIZE) {
+            eprintln!("Engine verification failed: {report}");
+            std::process::exit(1);
+        }
+        println!("Engine verification passed");
+    }
+
+    runner.run();
+}
 
-    Runner::new(cfg).run();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(json: &str) -> GeneratorConfig {
+        let base = r#"{"air_percentage": 0.5, "iterations": 5"#;
+        serde_json::from_str(&format!("{base}, {json}}}")).unwrap()
+    }
+
+    #[test]
+    fn conflicting_initializer_shorthands_are_config_problems() {
+        let layers = r#""layers": [{"z_from": 0.0, "z_to": 1.0, "air_prob": 0.5}]"#;
+        let symmetry = r#""symmetry": "mirror_x""#;
+        let points = r#""initializer": {"type": "points", "count": 3}"#;
+
+        let (init, problems) = generator(symmetry).initializer();
+        assert!(matches!(init, Initializer::Symmetric { .. }) && problems.is_empty());
+
+        let (init, problems) = generator(&format!("{symmetry}, {layers}")).initializer();
+        assert!(matches!(init, Initializer::Layers { .. }));
+        assert!(matches!(problems[..], [ConfigProblem::SymmetryWithLayers]));
+
+        let (_, problems) = generator(&format!("{symmetry}, {points}")).initializer();
+        assert!(matches!(
+            problems[..],
+            [ConfigProblem::ShorthandInitializer("symmetry")]
+        ));
+    }
 }
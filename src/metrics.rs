@@ -0,0 +1,891 @@
+use crate::ca::{Axis, Boundary, CAContext};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+const FACE_DIRS: [(i32, i32, i32); 6] = [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+];
+
+// Gliding box side of the lacunarity in RunResults
+pub const LACUNARITY_BOX: usize = 2;
+
+// Summed volume table over air cells, padded with a zero layer on the low side of every axis
+struct SummedVolume {
+    width: usize,
+    height: usize,
+    sums: Vec<i64>,
+}
+
+impl SummedVolume {
+    fn from_context(ctx: &CAContext) -> Self {
+        let (w, h, d) = (ctx.width() + 1, ctx.height() + 1, ctx.depth() + 1);
+        let mut sums = vec![0i64; w * h * d];
+
+        for z in 1..d {
+            for y in 1..h {
+                for x in 1..w {
+                    let cell = i64::from(ctx.get(x - 1, y - 1, z - 1).is_air());
+                    let i = x + w * (y + h * z);
+                    sums[i] = cell + sums[i - 1] + sums[i - w] + sums[i - w * h]
+                        - sums[i - 1 - w]
+                        - sums[i - 1 - w * h]
+                        - sums[i - w - w * h]
+                        + sums[i - 1 - w - w * h];
+                }
+            }
+        }
+
+        Self {
+            width: w,
+            height: h,
+            sums,
+        }
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> i64 {
+        self.sums[x + self.width * (y + self.height * z)]
+    }
+
+    // Air cells inside the box [x0, x1) x [y0, y1) x [z0, z1)
+    fn count(
+        &self,
+        (x0, y0, z0): (usize, usize, usize),
+        (x1, y1, z1): (usize, usize, usize),
+    ) -> u64 {
+        let sum =
+            self.at(x1, y1, z1) - self.at(x0, y1, z1) - self.at(x1, y0, z1) - self.at(x1, y1, z0)
+                + self.at(x0, y0, z1)
+                + self.at(x0, y1, z0)
+                + self.at(x1, y0, z0)
+                - self.at(x0, y0, z0);
+        sum as u64
+    }
+}
+
+// Gliding-box lacunarity, Λ(r) = E[M²] / E[M]² = var / mean² + 1, where M is the air count of
+// a cubic box of side r slid over the grid in 1-voxel steps. Box sizes that do not fit the grid
+// (or windows without any air) yield 0.0
+#[must_use]
+pub fn lacunarity(ctx: &CAContext, box_sizes: &[usize]) -> Vec<f64> {
+    let table = SummedVolume::from_context(ctx);
+
+    box_sizes
+        .iter()
+        .map(|&r| {
+            if r == 0 || r > ctx.width() || r > ctx.height() || r > ctx.depth() {
+                return 0.0;
+            }
+
+            let (nx, ny, nz) = (
+                ctx.width() - r + 1,
+                ctx.height() - r + 1,
+                ctx.depth() - r + 1,
+            );
+
+            let (sum, sum_sq) = (0..nx * ny * nz)
+                .into_par_iter()
+                .map(|i| {
+                    let (x, y, z) = (i % nx, (i / nx) % ny, i / (nx * ny));
+                    let m = table.count((x, y, z), (x + r, y + r, z + r)) as f64;
+                    (m, m * m)
+                })
+                .reduce(|| (0.0, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+            let n = (nx * ny * nz) as f64;
+            let mean = sum / n;
+            if mean <= 0.0 {
+                return 0.0;
+            }
+
+            let var = sum_sq / n - mean * mean;
+            var / (mean * mean) + 1.0
+        })
+        .collect()
+}
+
+fn on_boundary(ctx: &CAContext, x: usize, y: usize, z: usize) -> bool {
+    x == 0
+        || y == 0
+        || z == 0
+        || x == ctx.width() - 1
+        || y == ctx.height() - 1
+        || z == ctx.depth() - 1
+}
+
+// Air cells lying on any of the six faces of the grid
+#[must_use]
+pub fn boundary_air_volume(ctx: &CAContext) -> usize {
+    (0..ctx.cells().len())
+        .filter(|&i| {
+            let (x, y, z) = ctx.pos(i);
+            ctx[i].is_air() && on_boundary(ctx, x, y, z)
+        })
+        .count()
+}
+
+// Size of the largest air component that touches the grid boundary
+#[must_use]
+pub fn largest_boundary_component(ctx: &CAContext, components: &[Vec<usize>]) -> usize {
+    components
+        .iter()
+        .filter(|comp| {
+            comp.iter().any(|&i| {
+                let (x, y, z) = ctx.pos(i);
+                on_boundary(ctx, x, y, z)
+            })
+        })
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0)
+}
+
+// Size of the largest air component that percolates along `axis`, 0 when none does
+#[must_use]
+pub fn largest_percolating_component(
+    ctx: &CAContext,
+    components: &[Vec<usize>],
+    axis: Axis,
+    boundary: &Boundary,
+) -> usize {
+    components
+        .iter()
+        .filter(|comp| ctx.percolates_with(std::slice::from_ref(comp), axis, boundary))
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0)
+}
+
+// Offsets with (r - 1)² < |d|² <= r², so eroding shell by shell covers the full sphere of radius r
+fn sphere_shell(r: usize) -> Vec<(i32, i32, i32)> {
+    let ri = r as i32;
+    let (inner, outer) = ((ri - 1) * (ri - 1), ri * ri);
+    let mut offsets = Vec::new();
+
+    for dx in -ri..=ri {
+        for dy in -ri..=ri {
+            for dz in -ri..=ri {
+                let d2 = dx * dx + dy * dy + dz * dz;
+                if d2 > inner && d2 <= outer {
+                    offsets.push((dx, dy, dz));
+                }
+            }
+        }
+    }
+
+    offsets
+}
+
+fn is_air_at(ctx: &CAContext, x: usize, y: usize, z: usize, (dx, dy, dz): (i32, i32, i32)) -> bool {
+    let nx = x as i32 + dx;
+    let ny = y as i32 + dy;
+    let nz = z as i32 + dz;
+
+    if nx < 0 || ny < 0 || nz < 0 {
+        return false;
+    }
+
+    let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+    if nx >= ctx.width() || ny >= ctx.height() || nz >= ctx.depth() {
+        return false;
+    }
+
+    ctx.get(nx, ny, nz).is_air()
+}
+
+// Histogram of pore radii 1..=max_radius, where a cell has pore radius >= r iff it survives
+// erosion of the air by a sphere of radius r (everything outside the grid counts as solid).
+// Bin r - 1 holds the cells whose pore radius is exactly r, the last bin also holds larger ones
+#[must_use]
+pub fn pore_size_distribution(ctx: &CAContext, max_radius: usize) -> Vec<usize> {
+    let shells: Vec<_> = (1..=max_radius).map(sphere_shell).collect();
+
+    let radii: Vec<usize> = (0..ctx.cells().len())
+        .into_par_iter()
+        .map(|i| {
+            if !ctx[i].is_air() {
+                return 0;
+            }
+
+            let (x, y, z) = ctx.pos(i);
+            shells
+                .iter()
+                .take_while(|shell| shell.iter().all(|&d| is_air_at(ctx, x, y, z, d)))
+                .count()
+        })
+        .collect();
+
+    let mut histogram = vec![0; max_radius];
+    for r in radii.into_iter().filter(|&r| r > 0) {
+        histogram[r - 1] += 1;
+    }
+
+    histogram
+}
+
+// Length of the shortest 6-connected air path from `start` to `goal`, reusing `dist` as scratch
+// space (it must be filled with u32::MAX on entry and is restored before returning)
+fn geodesic_distance(ctx: &CAContext, start: usize, goal: usize, dist: &mut [u32]) -> Option<u32> {
+    let mut queue = VecDeque::new();
+    let mut touched = vec![start];
+    dist[start] = 0;
+    queue.push_back(start);
+
+    let mut found = None;
+    while let Some(idx) = queue.pop_front() {
+        if idx == goal {
+            found = Some(dist[idx]);
+            break;
+        }
+
+        let (x, y, z) = ctx.pos(idx);
+        for d in FACE_DIRS {
+            if !is_air_at(ctx, x, y, z, d) {
+                continue;
+            }
+
+            let nidx = ctx.idx(
+                (x as i32 + d.0) as usize,
+                (y as i32 + d.1) as usize,
+                (z as i32 + d.2) as usize,
+            );
+            if dist[nidx] == u32::MAX {
+                dist[nidx] = dist[idx] + 1;
+                touched.push(nidx);
+                queue.push_back(nidx);
+            }
+        }
+    }
+
+    for i in touched {
+        dist[i] = u32::MAX;
+    }
+
+    found
+}
+
+// Which neighboring cells a path may step to: sharing a face, also an edge, or also a corner
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connectivity {
+    #[default]
+    Six,
+    Eighteen,
+    TwentySix,
+}
+
+impl Connectivity {
+    #[must_use]
+    pub fn offsets(self) -> Vec<(i32, i32, i32)> {
+        let max_axes = match self {
+            Connectivity::Six => 1,
+            Connectivity::Eighteen => 2,
+            Connectivity::TwentySix => 3,
+        };
+
+        (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+            .filter(|&(x, y, z)| {
+                let axes = [x, y, z].iter().filter(|&&d| d != 0).count();
+                (1..=max_axes).contains(&axes)
+            })
+            .collect()
+    }
+
+    // Fewest steps between cells `d` apart, which never overestimates the path length. For six
+    // this is the Manhattan distance, diagonal steps cover two or three axes at once
+    fn min_steps(self, (dx, dy, dz): (usize, usize, usize)) -> usize {
+        let manhattan = dx + dy + dz;
+        let chebyshev = dx.max(dy).max(dz);
+        match self {
+            Connectivity::Six => manhattan,
+            Connectivity::Eighteen => chebyshev.max(manhattan.div_ceil(2)),
+            Connectivity::TwentySix => chebyshev,
+        }
+    }
+}
+
+// A* over air cells with unit step cost, `parent` records where every reached cell was entered
+// from when given. Returns the number of steps from start to goal
+fn astar(
+    ctx: &CAContext,
+    start: (usize, usize, usize),
+    goal: (usize, usize, usize),
+    connectivity: Connectivity,
+    mut parent: Option<&mut Vec<usize>>,
+) -> Option<usize> {
+    let inside =
+        |(x, y, z): (usize, usize, usize)| x < ctx.width() && y < ctx.height() && z < ctx.depth();
+    if !inside(start) || !inside(goal) {
+        return None;
+    }
+    let (start, goal) = (
+        ctx.idx(start.0, start.1, start.2),
+        ctx.idx(goal.0, goal.1, goal.2),
+    );
+    if !ctx[start].is_air() || !ctx[goal].is_air() {
+        return None;
+    }
+
+    let (gx, gy, gz) = ctx.pos(goal);
+    let heuristic = |i: usize| {
+        let (x, y, z) = ctx.pos(i);
+        connectivity.min_steps((x.abs_diff(gx), y.abs_diff(gy), z.abs_diff(gz)))
+    };
+    let offsets = connectivity.offsets();
+    let solid = Boundary::default();
+
+    let mut cost = vec![usize::MAX; ctx.cells().len()];
+    cost[start] = 0;
+    // Ties on the estimate go to the cell furthest along, which reaches the goal sooner
+    let mut open = BinaryHeap::from([Reverse((heuristic(start), Reverse(0), start))]);
+
+    while let Some(Reverse((_, Reverse(g), i))) = open.pop() {
+        if i == goal {
+            return Some(g);
+        }
+        if g > cost[i] {
+            continue;
+        }
+
+        let (x, y, z) = ctx.pos(i);
+        for &d in &offsets {
+            let Some(j) = ctx.offset_index(x, y, z, d, &solid) else {
+                continue;
+            };
+            if !ctx[j].is_air() || g + 1 >= cost[j] {
+                continue;
+            }
+
+            cost[j] = g + 1;
+            if let Some(parent) = parent.as_deref_mut() {
+                parent[j] = i;
+            }
+            open.push(Reverse((g + 1 + heuristic(j), Reverse(g + 1), j)));
+        }
+    }
+
+    None
+}
+
+// Shortest path of air cells from `start` to `goal`, both included, None when either is solid
+// or outside the grid or no path exists. Paths do not wrap around the grid
+#[must_use]
+pub fn astar_path(
+    ctx: &CAContext,
+    start: (usize, usize, usize),
+    goal: (usize, usize, usize),
+    connectivity: Connectivity,
+) -> Option<Vec<(usize, usize, usize)>> {
+    let mut parent = vec![usize::MAX; ctx.cells().len()];
+    let steps = astar(ctx, start, goal, connectivity, Some(&mut parent))?;
+
+    let mut path = Vec::with_capacity(steps + 1);
+    let mut i = ctx.idx(goal.0, goal.1, goal.2);
+    path.push(goal);
+    for _ in 0..steps {
+        i = parent[i];
+        path.push(ctx.pos(i));
+    }
+    path.reverse();
+
+    Some(path)
+}
+
+// Steps of the shortest path from astar_path, without keeping the parents to rebuild it
+#[must_use]
+pub fn path_length(
+    ctx: &CAContext,
+    start: (usize, usize, usize),
+    goal: (usize, usize, usize),
+    connectivity: Connectivity,
+) -> Option<usize> {
+    astar(ctx, start, goal, connectivity, None)
+}
+
+// Air cells whose removal splits their component, found with Tarjan's low-link depth-first
+// search. The search keeps its own stack of (cell, next offset) so large caves cannot
+// overflow the call stack. Positions come in cell index order
+#[must_use]
+pub fn articulation_points(
+    ctx: &CAContext,
+    connectivity: Connectivity,
+) -> Vec<(usize, usize, usize)> {
+    let n = ctx.cells().len();
+    let offsets = connectivity.offsets();
+    let solid = Boundary::default();
+
+    let mut disc = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut is_cut = vec![false; n];
+    let mut time = 0;
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+
+    for root in 0..n {
+        if !ctx[root].is_air() || disc[root] != usize::MAX {
+            continue;
+        }
+
+        disc[root] = time;
+        low[root] = time;
+        time += 1;
+        let mut root_children = 0;
+        stack.push((root, 0));
+
+        while let Some(&(v, next)) = stack.last() {
+            let Some(&d) = offsets.get(next) else {
+                // Every neighbor of v is done, hand its low-link to the parent
+                stack.pop();
+                if let Some(&(u, _)) = stack.last() {
+                    low[u] = low[u].min(low[v]);
+                    if u != root && low[v] >= disc[u] {
+                        is_cut[u] = true;
+                    }
+                }
+                continue;
+            };
+            if let Some(top) = stack.last_mut() {
+                top.1 += 1;
+            }
+
+            let (x, y, z) = ctx.pos(v);
+            let Some(w) = ctx.offset_index(x, y, z, d, &solid) else {
+                continue;
+            };
+            if !ctx[w].is_air() {
+                continue;
+            }
+
+            if disc[w] == usize::MAX {
+                disc[w] = time;
+                low[w] = time;
+                time += 1;
+                if v == root {
+                    root_children += 1;
+                }
+                stack.push((w, 0));
+            } else {
+                low[v] = low[v].min(disc[w]);
+            }
+        }
+
+        // The root only splits the component when the search left it more than once
+        if root_children > 1 {
+            is_cut[root] = true;
+        }
+    }
+
+    (0..n).filter(|&i| is_cut[i]).map(|i| ctx.pos(i)).collect()
+}
+
+// Air cells with a single air neighbor under `connectivity`, the tips of dead-end passages
+#[must_use]
+pub fn count_dead_ends(ctx: &CAContext, connectivity: Connectivity) -> usize {
+    ctx.dead_end_cells(connectivity).len()
+}
+
+// Mean ratio of geodesic (6-connected air path) to Euclidean distance over `samples` random
+// pairs of distinct air cells taken from the same component. Returns 0.0 if no pair exists
+#[must_use]
+pub fn tortuosity(ctx: &CAContext, samples: usize, seed: u64) -> f64 {
+    let components: Vec<Vec<usize>> = ctx
+        .connected_components()
+        .into_iter()
+        .filter(|c| c.len() > 1)
+        .collect();
+
+    // Pick the first cell uniformly over all candidate cells, so larger components are
+    // sampled proportionally more often
+    let total: usize = components.iter().map(Vec::len).sum();
+    if total == 0 || samples == 0 {
+        return 0.0;
+    }
+
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut dist = vec![u32::MAX; ctx.cells().len()];
+    let mut ratios = Vec::with_capacity(samples);
+
+    for _ in 0..samples {
+        let mut pick = rng.random_range(0..total);
+        let comp = components
+            .iter()
+            .find(|c| {
+                if pick < c.len() {
+                    return true;
+                }
+                pick -= c.len();
+                false
+            })
+            .expect("pick is within the total cell count");
+
+        let a = comp[pick];
+        let mut b = comp[rng.random_range(0..comp.len() - 1)];
+        if b == a {
+            b = comp[comp.len() - 1];
+        }
+
+        let Some(geodesic) = geodesic_distance(ctx, a, b, &mut dist) else {
+            continue;
+        };
+
+        let (ax, ay, az) = ctx.pos(a);
+        let (bx, by, bz) = ctx.pos(b);
+        let euclidean = ((ax.abs_diff(bx).pow(2) + ay.abs_diff(by).pow(2) + az.abs_diff(bz).pow(2))
+            as f64)
+            .sqrt();
+
+        ratios.push(f64::from(geodesic) / euclidean);
+    }
+
+    ratios.iter().sum::<f64>() / ratios.len().max(1) as f64
+}
+
+// Number of air cell faces bordering solid rock or the edge of the grid. Faces on periodic axes
+// look at the opposite side instead of the edge
+#[must_use]
+pub fn surface_area(ctx: &CAContext, boundary: &Boundary) -> usize {
+    (0..ctx.cells().len())
+        .into_par_iter()
+        .filter(|&i| ctx[i].is_air())
+        .map(|i| {
+            let (x, y, z) = ctx.pos(i);
+            FACE_DIRS
+                .iter()
+                .filter(|&&d| {
+                    ctx.offset_index(x, y, z, d, boundary)
+                        .is_none_or(|j| !ctx[j].is_air())
+                })
+                .count()
+        })
+        .sum()
+}
+
+// Surface area per unit of air volume
+#[must_use]
+pub fn specific_surface_area(ctx: &CAContext, boundary: &Boundary) -> f64 {
+    let v_total = ctx.total_air_cells();
+    if v_total == 0 {
+        return 0.0;
+    }
+
+    surface_area(ctx, boundary) as f64 / v_total as f64
+}
+
+// V^(2/3) / A, the inverse of the roughness index
+#[must_use]
+pub fn compactness(ctx: &CAContext, boundary: &Boundary) -> f64 {
+    let area = surface_area(ctx, boundary);
+    if area == 0 {
+        return 0.0;
+    }
+
+    (ctx.total_air_cells() as f64).powf(2.0 / 3.0) / area as f64
+}
+
+// For each coordination number 0..=6, how many air cells have that many air face-neighbors
+#[must_use]
+pub fn coordination_number_distribution(ctx: &CAContext) -> [usize; 7] {
+    (0..ctx.cells().len())
+        .into_par_iter()
+        .filter(|&i| ctx[i].is_air())
+        .fold(
+            || [0usize; 7],
+            |mut dist, i| {
+                let (x, y, z) = ctx.pos(i);
+                let n = FACE_DIRS
+                    .iter()
+                    .filter(|&&d| is_air_at(ctx, x, y, z, d))
+                    .count();
+                dist[n] += 1;
+                dist
+            },
+        )
+        .reduce(
+            || [0usize; 7],
+            |mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a += b;
+                }
+                a
+            },
+        )
+}
+
+// Mean and standard deviation of a coordination number distribution
+#[must_use]
+pub fn coordination_stats(dist: &[usize; 7]) -> (f64, f64) {
+    let n = dist.iter().sum::<usize>().max(1) as f64;
+    let mean = dist
+        .iter()
+        .enumerate()
+        .map(|(k, &c)| (k * c) as f64)
+        .sum::<f64>()
+        / n;
+    let var = (dist.iter().enumerate())
+        .map(|(k, &c)| c as f64 * (k as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n;
+    (mean, var.sqrt())
+}
+
+// Two-pass chamfer distance transform: every air cell gets its distance to the nearest solid
+// cell (cells outside the grid count as solid), solid cells get 0. With unit weights the result
+// is exact for the metric matching the offset set (6 faces -> L1, 26 neighbors -> L∞)
+fn chamfer_distance(ctx: &CAContext, offsets: &[(i32, i32, i32)]) -> Vec<u32> {
+    let (w, h, d) = (ctx.width() as i32, ctx.height() as i32, ctx.depth() as i32);
+    let mut dist: Vec<u32> = (ctx.cells().iter())
+        .map(|c| if c.is_air() { u32::MAX } else { 0 })
+        .collect();
+
+    // Offsets that come earlier in scanline order, the backward pass uses their negation
+    let backward: Vec<_> = (offsets.iter())
+        .copied()
+        .filter(|&(dx, dy, dz)| (dz, dy, dx) < (0, 0, 0))
+        .collect();
+
+    let mut relax = |x: i32, y: i32, z: i32, sign: i32| {
+        let i = ctx.idx(x as usize, y as usize, z as usize);
+        if dist[i] == 0 {
+            return;
+        }
+
+        for &(dx, dy, dz) in &backward {
+            let (nx, ny, nz) = (x + sign * dx, y + sign * dy, z + sign * dz);
+            let nd = if nx < 0 || ny < 0 || nz < 0 || nx >= w || ny >= h || nz >= d {
+                0
+            } else {
+                dist[ctx.idx(nx as usize, ny as usize, nz as usize)]
+            };
+            dist[i] = dist[i].min(nd.saturating_add(1));
+        }
+    };
+
+    for z in 0..d {
+        for y in 0..h {
+            for x in 0..w {
+                relax(x, y, z, 1);
+            }
+        }
+    }
+
+    for z in (0..d).rev() {
+        for y in (0..h).rev() {
+            for x in (0..w).rev() {
+                relax(x, y, z, -1);
+            }
+        }
+    }
+
+    dist
+}
+
+// Chebyshev distance from each air cell to the nearest solid cell
+#[must_use]
+pub fn distance_transform_l_inf(ctx: &CAContext) -> Vec<u32> {
+    let mut offsets = Vec::with_capacity(26);
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            for dz in -1..=1 {
+                if dx != 0 || dy != 0 || dz != 0 {
+                    offsets.push((dx, dy, dz));
+                }
+            }
+        }
+    }
+    chamfer_distance(ctx, &offsets)
+}
+
+// Manhattan distance from each air cell to the nearest solid cell
+#[must_use]
+pub fn distance_transform_l1(ctx: &CAContext) -> Vec<u32> {
+    chamfer_distance(ctx, &FACE_DIRS)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MinkowskiFunctionals {
+    pub volume: usize,
+    pub surface: usize,
+    pub mean_breadth: f64,
+    pub euler: i64,
+}
+
+// Contributions of one 2x2x2 configuration around a lattice vertex to the vertex, edge, face and
+// cube counts of the union of closed air voxels, scaled by 8 to stay integral. Bit dx + 2dy + 4dz
+// is the voxel at offset (dx, dy, dz) from the vertex's lower corner
+fn minkowski_lut() -> [[i64; 4]; 256] {
+    let mut lut = [[0i64; 4]; 256];
+
+    for (config, entry) in lut.iter_mut().enumerate() {
+        let has = |dx: usize, dy: usize, dz: usize| config & (1 << (dx + 2 * dy + 4 * dz)) != 0;
+        let voxels = config.count_ones() as i64;
+
+        // Half-edge along an axis on side s is present if any voxel on that side is
+        let mut half_edges = 0;
+        for s in 0..2 {
+            let on_x = (0..2).any(|a| (0..2).any(|b| has(s, a, b)));
+            let on_y = (0..2).any(|a| (0..2).any(|b| has(a, s, b)));
+            let on_z = (0..2).any(|a| (0..2).any(|b| has(a, b, s)));
+            half_edges += i64::from(on_x) + i64::from(on_y) + i64::from(on_z);
+        }
+
+        // Quarter-face perpendicular to an axis is present if either voxel across it is
+        let mut quarter_faces = 0;
+        for a in 0..2 {
+            for b in 0..2 {
+                quarter_faces += i64::from(has(0, a, b) || has(1, a, b));
+                quarter_faces += i64::from(has(a, 0, b) || has(a, 1, b));
+                quarter_faces += i64::from(has(a, b, 0) || has(a, b, 1));
+            }
+        }
+
+        *entry = [
+            8 * i64::from(config != 0),
+            4 * half_edges,
+            2 * quarter_faces,
+            voxels,
+        ];
+    }
+
+    lut
+}
+
+// Volume, surface area, mean breadth and Euler characteristic of the air voxels, counted over the
+// cubical complex (n0 vertices, n1 edges, n2 faces, n3 cubes) with V = n3, S = 2n2 - 6n3,
+// 2B = 3n3 - 2n2 + n1 and χ = n0 - n1 + n2 - n3
+#[must_use]
+pub fn minkowski(ctx: &CAContext) -> MinkowskiFunctionals {
+    let lut = minkowski_lut();
+    let (w, h, d) = (ctx.width(), ctx.height(), ctx.depth());
+    let air = |x: usize, y: usize, z: usize| {
+        x > 0
+            && y > 0
+            && z > 0
+            && x <= w
+            && y <= h
+            && z <= d
+            && ctx.get(x - 1, y - 1, z - 1).is_air()
+    };
+
+    let counts = (0..=d)
+        .into_par_iter()
+        .map(|z| {
+            let mut counts = [0i64; 4];
+            for y in 0..=h {
+                for x in 0..=w {
+                    let mut config = 0;
+                    for bit in 0..8 {
+                        let (dx, dy, dz) = (bit & 1, (bit >> 1) & 1, bit >> 2);
+                        if air(x + dx, y + dy, z + dz) {
+                            config |= 1 << bit;
+                        }
+                    }
+                    for (c, v) in counts.iter_mut().zip(lut[config]) {
+                        *c += v;
+                    }
+                }
+            }
+            counts
+        })
+        .reduce(
+            || [0i64; 4],
+            |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]],
+        );
+
+    let [n0, n1, n2, n3] = counts.map(|c| c / 8);
+
+    MinkowskiFunctionals {
+        volume: n3 as usize,
+        surface: (2 * n2 - 6 * n3) as usize,
+        mean_breadth: (3 * n3 - 2 * n2 + n1) as f64 / 2.0,
+        euler: n0 - n1 + n2 - n3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::CACell;
+
+    #[test]
+    fn minkowski_of_single_voxels_and_a_bar() {
+        // A unit cube anywhere in the grid, corners included: V = 1, S = 6, B = 3/2, χ = 1
+        for (x, y, z) in [(2, 3, 1), (0, 0, 0), (4, 5, 3)] {
+            let mut ctx = CAContext::new(5, 6, 4);
+            ctx.set(x, y, z, CACell::new(1));
+            let expected = MinkowskiFunctionals {
+                volume: 1,
+                surface: 6,
+                mean_breadth: 1.5,
+                euler: 1,
+            };
+            assert_eq!(minkowski(&ctx), expected, "({x}, {y}, {z})");
+        }
+
+        // A 2x1x1 box has mean breadth (2 + 1 + 1) / 2
+        let mut bar = CAContext::new(5, 6, 4);
+        bar.set(1, 1, 1, CACell::new(1));
+        bar.set(2, 1, 1, CACell::new(1));
+        let m = minkowski(&bar);
+        assert_eq!(
+            (m.volume, m.surface, m.mean_breadth, m.euler),
+            (2, 10, 2.0, 1)
+        );
+
+        // Closed voxels that share only a corner still form one body
+        let mut corner = CAContext::new(5, 6, 4);
+        corner.set(1, 1, 1, CACell::new(1));
+        corner.set(2, 2, 2, CACell::new(1));
+        assert_eq!(minkowski(&corner).euler, 1);
+    }
+
+    #[test]
+    fn astar_follows_a_diagonal_staircase() {
+        // Cells (i, i, i) joined by face steps along x then y then z, plus a sealed pocket
+        let mut ctx = CAContext::new(8, 8, 8);
+        for i in 0..6 {
+            for cell in [(i, i, i), (i + 1, i, i), (i + 1, i + 1, i)] {
+                ctx.set(cell.0, cell.1, cell.2, CACell::new(1));
+            }
+        }
+        ctx.set(6, 6, 6, CACell::new(1));
+        ctx.set(0, 7, 7, CACell::new(1));
+
+        let (start, goal) = ((0, 0, 0), (6, 6, 6));
+        let expected = [
+            (Connectivity::Six, 18),
+            (Connectivity::Eighteen, 9),
+            (Connectivity::TwentySix, 6),
+        ];
+        for (connectivity, steps) in expected {
+            let path = ctx.find_path(start, goal, connectivity).unwrap();
+            assert_eq!(path_length(&ctx, start, goal, connectivity), Some(steps));
+            assert_eq!(path.len() - 1, steps);
+            assert_eq!((path[0], path[steps]), (start, goal));
+
+            let offsets = connectivity.offsets();
+            for pair in path.windows(2) {
+                let ((ax, ay, az), (bx, by, bz)) = (pair[0], pair[1]);
+                assert!(ctx.get(bx, by, bz).is_air());
+                let d = (
+                    bx as i32 - ax as i32,
+                    by as i32 - ay as i32,
+                    bz as i32 - az as i32,
+                );
+                assert!(offsets.contains(&d), "{connectivity:?} step {d:?}");
+            }
+
+            // Solid, outside the grid and sealed off
+            for goal in [(3, 0, 0), (8, 0, 0), (0, 7, 7)] {
+                assert_eq!(astar_path(&ctx, start, goal, connectivity), None);
+                assert_eq!(path_length(&ctx, goal, start, connectivity), None);
+            }
+        }
+    }
+}
@@ -1,49 +1,637 @@
-use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Instant};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use sysinfo::System;
 
 use crate::{
-    ca::{CAConfig, CAContext, CAEngine},
-    data::{ConfigKey, RunInfo, RunMetadata, RunResults},
+    ca::{
+        Axis, Boundary, CACell, CAConfig, CAContext, CAEngine, CAPipeline, CAStage, Composition,
+        CountStates, EngineMode, EngineReport, EntranceParams, InitFile, Initializer, Layer,
+        LayerError, MismatchReport, Region, RegionError, RuleProblem, RuleValidationError,
+        ShellSpec, Stamp, StopReason, VoxInit, hash_seed,
+    },
+    data::{
+        ConfigKey, GifExport, LogFormat, ResultsDb, RunInfo, RunMetadata, RunResults,
+        TEMPLATE_VARIABLES, VoxStyle, fill_template, load_results_csv, load_vox_grid,
+        template_variables, write_results_csv,
+    },
+    metrics,
+    topology::{self, CaveGraphParams},
 };
 
 pub struct RunnerConfig {
     pub width: usize,
     pub height: usize,
     pub depth: usize,
-    pub air_percentage: f64,
+    // Every run is repeated for each of these initial air fractions
+    pub air_probs: Vec<f64>,
+    pub initializer: Initializer,
     pub iterations: usize,
     pub seeds: Vec<u64>,
+    // Runs draw from a hash of their seed, neighborhood, ruleset and seed index instead of the
+    // seed itself, metadata records the hashed one
+    pub hash_seeds: bool,
     pub neighborhoods: Vec<crate::ca::CANeighborhood>,
     pub rulesets: Vec<crate::ca::CARule>,
+    // Multi-stage runs, each one is run once per seed next to the neighborhood x ruleset grid
+    pub pipelines: Vec<CAPipeline>,
     pub output_dir: PathBuf,
+    // Where each run and metrics.csv go below output_dir, see TEMPLATE_VARIABLES. The metrics
+    // path only knows the grid size
+    pub run_dir_template: String,
+    pub metrics_path_template: String,
+    // Results are also inserted into this SQLite database when set
+    pub db_path: Option<PathBuf>,
+    pub tortuosity_samples: usize,
+    // Label air components through the run's neighborhood offsets instead of shared faces
+    pub neighborhood_components: bool,
+    // Air components of at least this many cells count as rooms
+    pub room_threshold: usize,
+    pub vox_bands: Vec<(usize, u8)>,
+    pub vox_color: [u8; 4],
+    pub distance_vox: bool,
+    pub projections: Vec<Axis>,
+    // Write grid.ply next to grid.vox, colored by air component when ply_colors is set
+    pub ply: bool,
+    pub ply_colors: bool,
+    // Write the grid files of every run, otherwise only its metadata and log
+    pub save_grids: bool,
+    // log.txt or log.json with the per-iteration stats of every run
+    pub log_format: LogFormat,
+    // Write cave_graph.json with the rooms and corridors of every final grid
+    pub cave_graph: Option<CaveGraphParams>,
+    pub engine_mode: EngineMode,
+    // Applied to the neighborhood x ruleset runs, pipeline stages carry their own
+    pub boundary: Boundary,
+    // Which states count as alive for the neighborhood x ruleset runs, like boundary
+    pub count_states: CountStates,
+    // Counts every cell as one of its own neighbors, like boundary
+    pub include_self: bool,
+    pub clean_isolated: bool,
+    // Passes of CAContext::remove_dead_ends on the final grid, before carve_path
+    pub remove_dead_ends: usize,
+    // Carve a tunnel between the faces of this axis when the final grid doesn't percolate
+    pub carve_path: Option<Axis>,
+    // Only this box of the grid evolves, the initial cells outside it stay fixed
+    pub region: Option<Region>,
+    // Metrics only look at the cells inside this box, e.g. to leave out a padding margin
+    pub roi: Option<Region>,
+    // Solid border of the initial grid, a frozen one also shrinks the evolved box
+    pub shell: ShellSpec,
+    // Shapes set to air on the initial grid, after the shell
+    pub stamps: Vec<Stamp>,
+    // Shafts opened from the top face after the stamps
+    pub entrances: Option<EntranceParams>,
+    // Per-run wall-clock budget, runs exceeding it stop early and are marked as timed out
+    pub time_limit: Option<Duration>,
+    // Abort before running when any rule fails validation instead of only printing problems
+    pub strict: bool,
+    // Animate the z-slice at gif_z over all iterations in iterations.gif
+    pub save_gif: bool,
+    pub gif_z: usize,
+    pub gif_delay_cs: u16,
+    // Write a vox of every Nth iteration and the final one, 0 disables snapshots
+    pub snapshot_every: usize,
+    // Rerun runs already present in the output directory instead of reusing their results
+    pub overwrite: bool,
+    // Rerun degenerate grids with fresh seeds
+    pub retry: Option<RetryPolicy>,
+    // Problems found while turning a config file into this config, validate reports them with
+    // the rest
+    pub file_problems: Vec<ConfigProblem>,
+}
+
+pub const DEFAULT_RUN_DIR_TEMPLATE: &str = "runs/{run_id}";
+pub const DEFAULT_METRICS_PATH_TEMPLATE: &str = "metrics.csv";
+
+// Template variables that differ between the runs of one config
+const RUN_TEMPLATE_VARIABLES: [&str; 4] = ["run_id", "seed", "neighborhood", "ruleset"];
+
+// A final grid with an air fraction outside [min_air, max_air] is redone with a new seed, at
+// most max_retries times. The last attempt is kept either way
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RetryPolicy {
+    #[serde(default)]
+    pub min_air: f64,
+    #[serde(default = "default_max_air")]
+    pub max_air: f64,
+    pub max_retries: usize,
+}
+
+fn default_max_air() -> f64 {
+    1.0
+}
+
+impl RetryPolicy {
+    #[must_use]
+    pub fn is_degenerate(&self, air_fraction: f64) -> bool {
+        !(self.min_air..=self.max_air).contains(&air_fraction)
+    }
+}
+
+// Everything run_stages needs from evolving one seed
+struct Simulation {
+    engine: CAEngine,
+    report: EngineReport,
+    logs: Vec<String>,
+    frames: Vec<(usize, CAContext)>,
+    // Snapshot files written so far, by iteration
+    snapshots: Vec<(usize, PathBuf)>,
+}
+
+impl RunnerConfig {
+    // The configured region, narrowed to the inside of a frozen shell and to the layers that
+    // aren't frozen
+    #[must_use]
+    pub fn evolved_region(&self) -> Option<Region> {
+        let (width, height, depth) = (self.width, self.height, self.depth);
+        let mut bounds = Vec::new();
+        if self.shell.frozen && !self.shell.is_empty() {
+            bounds.push(self.shell.interior(width, height, depth));
+        }
+        bounds.extend(self.initializer.live_region(width, height, depth));
+
+        (bounds.into_iter()).fold(self.region, |region, bound| {
+            Some(region.map_or(bound, |r| r.intersect(&bound)))
+        })
+    }
+
+    // Metadata a run starts out with, before the engine fills in what it did
+    #[must_use]
+    pub fn run_metadata(
+        &self,
+        neighborhood: &str,
+        ruleset: &str,
+        iterations: usize,
+        air_prob: f64,
+        seed: u64,
+    ) -> RunMetadata {
+        RunMetadata::new(
+            seed,
+            neighborhood.to_string(),
+            self.width,
+            self.height,
+            self.depth,
+            iterations,
+            ruleset.to_string(),
+            air_prob,
+        )
+    }
+
+    #[must_use]
+    pub fn run_dir(&self, metadata: &RunMetadata) -> PathBuf {
+        let values = metadata.template_values();
+        (self.output_dir).join(fill_template(&self.run_dir_template, &values))
+    }
+
+    #[must_use]
+    pub fn metrics_path(&self) -> PathBuf {
+        let template = &self.metrics_path_template;
+        metrics_path(
+            &self.output_dir,
+            template,
+            self.width,
+            self.height,
+            self.depth,
+        )
+    }
+
+    // The metadata of every run, in the order they are scheduled
+    fn planned_runs(&self) -> impl Iterator<Item = RunMetadata> + '_ {
+        let singles = (self.neighborhoods.iter()).flat_map(move |n| {
+            (self.rulesets.iter()).map(move |r| (n.name.as_str(), r.name.as_str(), self.iterations))
+        });
+        let pipelines =
+            (self.pipelines.iter()).map(|p| ("pipeline", p.name.as_str(), p.total_iterations()));
+
+        singles
+            .chain(pipelines)
+            .flat_map(move |job| self.air_probs.iter().map(move |&p| (job, p)))
+            .flat_map(move |(job, p)| self.seeds.iter().map(move |&s| (job, p, s)))
+            .map(move |((n, r, iterations), p, s)| self.run_metadata(n, r, iterations, p, s))
+    }
+
+    fn template_problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        let run_vars = template_variables(&self.run_dir_template);
+        for var in &run_vars {
+            if !TEMPLATE_VARIABLES.contains(var) {
+                problems.push(ConfigProblem::TemplateVariable(
+                    self.run_dir_template.clone(),
+                    (*var).to_string(),
+                ));
+            }
+        }
+        if !run_vars
+            .iter()
+            .any(|var| RUN_TEMPLATE_VARIABLES.contains(var))
+        {
+            problems.push(ConfigProblem::AmbiguousRunDir(
+                self.run_dir_template.clone(),
+            ));
+        }
+        for var in template_variables(&self.metrics_path_template) {
+            if !["width", "height", "depth"].contains(&var) {
+                problems.push(ConfigProblem::TemplateVariable(
+                    self.metrics_path_template.clone(),
+                    var.to_string(),
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            return problems;
+        }
+
+        // Runs sharing a run_id are the same run, anything else may not share a directory
+        let mut dirs: HashMap<PathBuf, String> = HashMap::new();
+        for metadata in self.planned_runs() {
+            let dir = self.run_dir(&metadata);
+            match dirs.get(&dir) {
+                Some(run_id) if *run_id != metadata.run_id => {
+                    problems.push(ConfigProblem::RunDirCollision(dir));
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    dirs.insert(dir, metadata.run_id);
+                }
+            }
+        }
+        problems
+    }
+
+    // Every neighborhood x ruleset pair and every pipeline, once per air fraction and seed
+    #[must_use]
+    pub fn total_runs(&self) -> usize {
+        (self.neighborhoods.len() * self.rulesets.len() + self.pipelines.len())
+            * self.air_probs.len()
+            * self.seeds.len()
+    }
+
+    // Collects every problem instead of stopping at the first one. Rule problems such as
+    // counts above the neighbor count only count under strict, the bundled experiments cross
+    // every ruleset with every neighborhood on purpose
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.load().map(drop)
+    }
+
+    // Validates and returns the file of a file-based initializer, read here once for all runs
+    fn load(&self) -> Result<Option<InitFile>, ConfigError> {
+        let mut problems = self.file_problems.clone();
+        let mut file = None;
+
+        for (axis, len) in [
+            (Axis::X, self.width),
+            (Axis::Y, self.height),
+            (Axis::Z, self.depth),
+        ] {
+            if len == 0 {
+                problems.push(ConfigProblem::ZeroDimension(axis));
+            }
+        }
+        if self.air_probs.is_empty() {
+            problems.push(ConfigProblem::NoAirProbs);
+        }
+        let mut probabilities = self.air_probs.clone();
+        match &self.initializer {
+            Initializer::Gradient {
+                prob_top,
+                prob_bottom,
+            } => probabilities.extend([*prob_top, *prob_bottom]),
+            Initializer::DepthProfile { points } => {
+                if points.iter().any(|&(z, _)| !(0.0..=1.0).contains(&z)) || points.is_empty() {
+                    problems.push(ConfigProblem::DepthProfile);
+                }
+                probabilities.extend(points.iter().map(|&(_, p)| p));
+            }
+            Initializer::Walks(params) => {
+                let inside = |&(x, y, z): &(usize, usize, usize)| {
+                    x < self.width && y < self.height && z < self.depth
+                };
+                let valid = params.step_radius.is_finite()
+                    && params.step_radius >= 0.0
+                    && (0.0..=1.0).contains(&params.horizontal_bias)
+                    && params.starts.iter().all(inside);
+                if !valid {
+                    problems.push(ConfigProblem::Walks);
+                }
+                probabilities.push(params.background_air_prob);
+            }
+            Initializer::Vox(VoxInit { path, .. })
+            | Initializer::ProbMap { path }
+            | Initializer::Npy { path }
+            | Initializer::Raw { path } => {
+                match (self.initializer).load_file(self.width, self.height, self.depth) {
+                    Ok(loaded) => file = loaded,
+                    Err(e) => {
+                        problems.push(ConfigProblem::InitFile(format!("{}: {e}", path.display())))
+                    }
+                }
+            }
+            Initializer::Layers { layers } => {
+                if let Err(e) = Layer::check_stack(layers, self.depth) {
+                    problems.push(ConfigProblem::Layers(e));
+                }
+                probabilities.extend(layers.iter().map(|l| l.air_prob));
+            }
+            Initializer::Regions { regions } => {
+                for r in regions {
+                    if let Err(e) = r.region.validate(self.width, self.height, self.depth) {
+                        problems.push(ConfigProblem::Region(e));
+                    }
+                    probabilities.push(r.air_prob);
+                }
+            }
+            _ => {}
+        }
+        for p in probabilities {
+            if !(0.0..=1.0).contains(&p) {
+                problems.push(ConfigProblem::AirPercentageOutOfRange(p));
+            }
+        }
+        if self.seeds.is_empty() {
+            problems.push(ConfigProblem::NoSeeds);
+        }
+        for (i, stamp) in self.stamps.iter().enumerate() {
+            if !stamp.is_valid() {
+                problems.push(ConfigProblem::Stamp(i));
+            }
+        }
+        if (self.entrances).is_some_and(|e| !e.fits(self.width, self.height, self.depth)) {
+            problems.push(ConfigProblem::Entrances);
+        }
+        if self.save_gif && self.gif_z >= self.depth {
+            problems.push(ConfigProblem::GifSlice(self.gif_z));
+        }
+        if let Some(retry) = &self.retry {
+            let bounds = [retry.min_air, retry.max_air];
+            if retry.min_air > retry.max_air || bounds.iter().any(|b| !(0.0..=1.0).contains(b)) {
+                problems.push(ConfigProblem::RetryBounds(retry.min_air, retry.max_air));
+            }
+        }
+
+        // Pipelines bring their own neighborhoods, rules and iteration counts
+        if self.pipelines.is_empty() {
+            if self.iterations == 0 {
+                problems.push(ConfigProblem::ZeroIterations);
+            }
+            if self.neighborhoods.is_empty() {
+                problems.push(ConfigProblem::NoNeighborhoods);
+            }
+            if self.rulesets.is_empty() {
+                problems.push(ConfigProblem::NoRulesets);
+            }
+        }
+
+        if let Some(Err(e)) = (self.region).map(|r| r.validate(self.width, self.height, self.depth))
+        {
+            problems.push(ConfigProblem::Region(e));
+        }
+        if let Some(Err(e)) = (self.roi).map(|r| r.validate(self.width, self.height, self.depth)) {
+            problems.push(ConfigProblem::Roi(e));
+        }
+        if !self.shell.is_empty() {
+            let interior = self.shell.interior(self.width, self.height, self.depth);
+            let evolved = self.region.map_or(interior, |r| r.intersect(&interior));
+            let checked = if self.shell.frozen { evolved } else { interior };
+            if let Err(e) = checked.validate(self.width, self.height, self.depth) {
+                problems.push(ConfigProblem::Shell(e));
+            }
+        }
+        // Frozen layers may leave nothing of the configured region
+        let live = self
+            .initializer
+            .live_region(self.width, self.height, self.depth);
+        if let Some(Err(e)) = (live.and(self.evolved_region()))
+            .map(|r| r.validate(self.width, self.height, self.depth))
+        {
+            problems.push(ConfigProblem::Region(e));
+        }
+
+        problems.extend(self.template_problems());
+
+        // Erosion outside von_neumann no longer means exposed faces, so that one fails regardless
+        let erosion =
+            |e: &RuleValidationError| e.problems.contains(&RuleProblem::ErosionNeighborhood);
+        problems.extend(
+            (self.rule_problems().into_iter())
+                .filter(|e| self.strict || erosion(e))
+                .map(ConfigProblem::Rule),
+        );
+
+        if problems.is_empty() {
+            Ok(file)
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    // Validates every neighborhood x ruleset pair and every pipeline stage
+    #[must_use]
+    pub fn rule_problems(&self) -> Vec<RuleValidationError> {
+        let singles = (self.neighborhoods.iter())
+            .flat_map(|n| (self.rulesets.iter()).map(move |r| r.validate(n, self.include_self)));
+        let stages = (self.pipelines.iter())
+            .flat_map(|p| &p.stages)
+            .map(|stage| {
+                let config = &stage.config;
+                config
+                    .rule
+                    .validate(&config.neighborhood, config.include_self)
+            });
+
+        singles.chain(stages).filter_map(Result::err).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ConfigProblem {
+    ZeroDimension(Axis),
+    AirPercentageOutOfRange(f64),
+    ZeroIterations,
+    NoSeeds,
+    NoAirProbs,
+    NoNeighborhoods,
+    NoRulesets,
+    Region(RegionError),
+    Roi(RegionError),
+    Shell(RegionError),
+    Stamp(usize),
+    RetryBounds(f64, f64),
+    DepthProfile,
+    Layers(LayerError),
+    Walks,
+    Entrances,
+    GifSlice(usize),
+    InitFile(String),
+    SymmetryWithLayers,
+    // The symmetry or layers shorthand next to an initializer other than random
+    ShorthandInitializer(&'static str),
+    // (template, variable) for a name the template can't fill
+    TemplateVariable(String, String),
+    AmbiguousRunDir(String),
+    RunDirCollision(PathBuf),
+    Rule(RuleValidationError),
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigProblem::ZeroDimension(axis) => write!(f, "grid size along {axis:?} is 0"),
+            ConfigProblem::AirPercentageOutOfRange(p) => {
+                write!(f, "air percentage {p} is outside [0, 1]")
+            }
+            ConfigProblem::ZeroIterations => write!(f, "iterations must be at least 1"),
+            ConfigProblem::NoSeeds => write!(f, "no seeds to run"),
+            ConfigProblem::NoAirProbs => write!(
+                f,
+                "no air fractions to run, an air_probs range needs start <= stop and a step > 0"
+            ),
+            ConfigProblem::NoNeighborhoods => write!(f, "no neighborhoods and no pipelines"),
+            ConfigProblem::NoRulesets => write!(f, "no rulesets and no pipelines"),
+            ConfigProblem::Region(e) => write!(f, "{e}"),
+            ConfigProblem::Roi(e) => write!(f, "roi: {e}"),
+            ConfigProblem::Shell(e) => write!(f, "shell leaves no interior: {e}"),
+            ConfigProblem::Stamp(i) => {
+                write!(
+                    f,
+                    "seeds_geometry shape {i} has a negative or non-finite size"
+                )
+            }
+            ConfigProblem::DepthProfile => {
+                write!(
+                    f,
+                    "depth profile needs at least one point, at z fractions in [0, 1]"
+                )
+            }
+            ConfigProblem::Layers(e) => write!(f, "{e}"),
+            ConfigProblem::Walks => write!(
+                f,
+                "walks need a step radius >= 0, a horizontal bias in [0, 1] and starts inside the grid"
+            ),
+            ConfigProblem::Entrances => write!(
+                f,
+                "entrances need 2 * radius < width and height and a depth within the grid"
+            ),
+            ConfigProblem::GifSlice(z) => {
+                write!(f, "gif_z {z} is outside the grid depth")
+            }
+            ConfigProblem::InitFile(e) => write!(f, "initial grid {e}"),
+            ConfigProblem::SymmetryWithLayers => write!(f, "symmetry and layers can't be combined"),
+            ConfigProblem::ShorthandInitializer(key) => {
+                write!(f, "{key} only applies to the random initializer")
+            }
+            ConfigProblem::RetryBounds(min, max) => {
+                write!(
+                    f,
+                    "retry air bounds [{min}, {max}] are not a range within [0, 1]"
+                )
+            }
+            ConfigProblem::TemplateVariable(template, var) => {
+                write!(
+                    f,
+                    "output path template {template} has no variable {{{var}}}"
+                )
+            }
+            ConfigProblem::AmbiguousRunDir(template) => write!(
+                f,
+                "run_dir_template {template} names no run_id, seed, neighborhood or ruleset, runs \
+                 would overwrite each other"
+            ),
+            ConfigProblem::RunDirCollision(dir) => write!(
+                f,
+                "run_dir_template puts several runs into {}",
+                dir.display()
+            ),
+            ConfigProblem::Rule(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+// Every problem found by RunnerConfig::validate
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration problem(s)", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// One unit of work for a single seed
+#[derive(Clone, Copy)]
+enum Job<'a> {
+    Single(&'a crate::ca::CANeighborhood, &'a crate::ca::CARule),
+    Pipeline(&'a CAPipeline),
 }
 
 pub struct Runner {
     config: RunnerConfig,
     results: Mutex<Vec<RunResults>>,
+    // metrics.csv rows of an earlier run into the same output directory by run_id, reused
+    // for runs whose directory still exists unless overwrite is set
+    previous: HashMap<String, RunResults>,
+    // Set by the Ctrl-C handler, no new runs are started once it is raised
+    interrupted: Arc<AtomicBool>,
+    // The file of a file-based initializer, shared by every run
+    init_file: Option<InitFile>,
 }
 
 impl Runner {
-    #[must_use]
-    pub fn new(config: RunnerConfig) -> Self {
-        let total_runs = config.neighborhoods.len() * config.rulesets.len() * config.seeds.len();
+    pub fn new(config: RunnerConfig) -> Result<Self, ConfigError> {
+        let init_file = config.load()?;
+        let total_runs = config.total_runs();
+        let previous = if config.overwrite {
+            HashMap::new()
+        } else {
+            Self::load_previous(&config.metrics_path())
+        };
 
-        Runner {
+        Ok(Runner {
             config,
             results: Mutex::new(Vec::with_capacity(total_runs)),
-        }
+            previous,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            init_file,
+        })
     }
 
     pub fn run(&self) {
         // Ensure directory structure exists
-        std::fs::create_dir_all(self.config.output_dir.join("runs"))
-            .expect("Failed to create runs directory");
+        std::fs::create_dir_all(&self.config.output_dir)
+            .expect("Failed to create output directory");
+
+        self.check_rules();
+        self.check_probability_map();
 
-        let total_runs =
-            self.config.neighborhoods.len() * self.config.rulesets.len() * self.config.seeds.len();
+        let interrupted = Arc::clone(&self.interrupted);
+        if let Err(err) = ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst)) {
+            eprintln!("Warning: failed to install Ctrl-C handler: {err}");
+        }
+
+        let total_runs = self.config.total_runs();
+        println!("Running {total_runs} runs");
 
         let pb = ProgressBar::new(total_runs as u64);
         pb.set_style(
@@ -52,76 +640,487 @@ impl Runner {
                 .progress_chars("=> "),
         );
 
-        (self.config.neighborhoods.iter())
-            .flat_map(|n| {
-                self.config
-                    .rulesets
-                    .iter()
-                    .flat_map(move |r| self.config.seeds.iter().map(move |&s| (n, r, s)))
-            })
+        let singles = (self.config.neighborhoods.iter())
+            .flat_map(|n| self.config.rulesets.iter().map(move |r| Job::Single(n, r)));
+        let pipelines = self.config.pipelines.iter().map(Job::Pipeline);
+
+        singles
+            .chain(pipelines)
+            .flat_map(|job| self.config.air_probs.iter().map(move |&p| (job, p)))
+            .flat_map(|(job, p)| self.config.seeds.iter().map(move |&s| (job, p, s)))
+            .take_while(|_| !self.interrupted.load(Ordering::SeqCst))
             .par_bridge()
-            .for_each(|(n, r, s)| {
-                self.run_single(n, r, s);
+            .for_each(|(job, air_prob, s)| {
+                match job {
+                    Job::Single(n, r) => self.run_single(n, r, air_prob, s),
+                    Job::Pipeline(p) => self.run_pipeline(p, air_prob, s),
+                }
                 pb.inc(1);
             });
 
+        // Runs already in flight finish, everything collected so far is still written out
+        let interrupted = self.interrupted.load(Ordering::SeqCst);
+        if interrupted {
+            pb.abandon_with_message("Interrupted");
+        }
+
         self.write_results();
         self.write_diversity_stats();
+        let summary = self.write_summary();
         self.write_hardware_info()
             .expect("Failed to write hardware info");
-        pb.finish_with_message("Cavegen complete");
+
+        if interrupted {
+            eprintln!(
+                "Interrupted, wrote {} of {total_runs} runs",
+                self.results.lock().unwrap().len()
+            );
+        } else {
+            pb.finish_with_message("Cavegen complete");
+        }
+
+        print!("{summary}");
+    }
+
+    #[must_use]
+    pub fn rule_problems(&self) -> Vec<RuleValidationError> {
+        self.config.rule_problems()
+    }
+
+    // Rows of an existing metrics.csv, an unreadable one just means every run is redone
+    fn load_previous(path: &Path) -> HashMap<String, RunResults> {
+        if !path.exists() {
+            return HashMap::new();
+        }
+
+        match load_results_csv(path) {
+            Ok(rows) => rows.into_iter().map(|r| (r.run_id.clone(), r)).collect(),
+            Err(err) => {
+                eprintln!("Warning: ignoring existing {}: {err}", path.display());
+                HashMap::new()
+            }
+        }
+    }
+
+    // Clamped map values are not a configuration error, but likely a unit mix-up worth seeing
+    fn check_probability_map(&self) {
+        if let Initializer::ProbMap { path } = &self.config.initializer
+            && let Some(InitFile::ProbMap(map)) = &self.init_file
+            && map.clamped > 0
+        {
+            eprintln!(
+                "Warning: clamped {} of {} probabilities in {} to [0, 1]",
+                map.clamped,
+                map.probs.len(),
+                path.display()
+            );
+        }
+    }
+
+    // Prints one line per problem. Under strict validation these already failed Runner::new
+    fn check_rules(&self) {
+        let errors = self.rule_problems();
+        if errors.is_empty() {
+            return;
+        }
+
+        let rs_width = (errors.iter().map(|e| e.ruleset.len()))
+            .max()
+            .unwrap_or(0)
+            .max(7);
+        let nb_width = (errors.iter().map(|e| e.neighborhood.len()))
+            .max()
+            .unwrap_or(0)
+            .max(12);
+
+        eprintln!(
+            "Rule validation problems:\n{:<rs_width$}  {:<nb_width$}  {:>9}  problem",
+            "ruleset", "neighborhood", "neighbors"
+        );
+        for error in &errors {
+            for problem in &error.problems {
+                eprintln!(
+                    "{:<rs_width$}  {:<nb_width$}  {:>9}  {problem}",
+                    error.ruleset, error.neighborhood, error.neighbors
+                );
+            }
+        }
+    }
+
+    // Cross-checks the parallel update against the sequential reference on a small grid
+    // for every neighborhood and ruleset in the config
+    pub fn verify(&self, size: usize) -> Result<(), MismatchReport> {
+        let seed = self.config.seeds.first().copied().unwrap_or(0);
+
+        for neighborhood in &self.config.neighborhoods {
+            for rule in &self.config.rulesets {
+                let air_prob = self.config.air_probs.first().copied().unwrap_or(0.5);
+                let context = CAContext::random(size, size, size, seed, air_prob);
+                let config = CAConfig {
+                    neighborhood: neighborhood.clone(),
+                    rule: rule.clone(),
+                    boundary: self.config.boundary,
+                    count_states: self.config.count_states.clone(),
+                    include_self: self.config.include_self,
+                };
+
+                CAEngine::new(config, context)
+                    .with_mode(self.config.engine_mode)
+                    .with_seed(seed)
+                    .verify_against_reference(self.config.iterations)?;
+            }
+        }
+
+        Ok(())
     }
 
     fn run_single(
         &self,
         neighborhood: &crate::ca::CANeighborhood,
         rule: &crate::ca::CARule,
+        air_prob: f64,
         seed: u64,
     ) {
-        let context = CAContext::random(
+        let pipeline = CAPipeline {
+            name: rule.name.clone(),
+            stages: vec![CAStage {
+                config: CAConfig {
+                    neighborhood: neighborhood.clone(),
+                    rule: rule.clone(),
+                    boundary: self.config.boundary,
+                    count_states: self.config.count_states.clone(),
+                    include_self: self.config.include_self,
+                },
+                iterations: self.config.iterations,
+            }],
+            composition: Composition::Sequential,
+        };
+
+        self.run_stages(&neighborhood.name, &pipeline, air_prob, seed);
+    }
+
+    fn run_pipeline(&self, pipeline: &CAPipeline, air_prob: f64, seed: u64) {
+        self.run_stages("pipeline", pipeline, air_prob, seed);
+    }
+
+    fn run_stages(&self, neighborhood: &str, pipeline: &CAPipeline, air_prob: f64, seed: u64) {
+        if pipeline.stages.is_empty() {
+            return;
+        }
+
+        let mut metadata = self.config.run_metadata(
+            neighborhood,
+            &pipeline.name,
+            pipeline.total_iterations(),
+            air_prob,
+            seed,
+        );
+
+        metadata.initializer = self.config.initializer.clone();
+        metadata.stages.clone_from(&pipeline.stages);
+        metadata.composition = pipeline.composition;
+        metadata.clean_isolated = self.config.clean_isolated;
+        metadata.remove_dead_ends = self.config.remove_dead_ends;
+        metadata.carve_path = self.config.carve_path;
+        metadata.region = self.config.evolved_region();
+        metadata.roi = self.config.roi;
+        metadata.shell = self.config.shell;
+        metadata.stamps.clone_from(&self.config.stamps);
+        metadata.entrances = self.config.entrances;
+        metadata.config_hash = self.run_hash(&metadata, neighborhood, seed);
+
+        // The run_id only depends on a few parameters, a finished run is found again by name
+        // but only reused when the rest of its settings still match
+        let run_dir = self.config.run_dir(&metadata);
+        if let Some(previous) = self.previous.get(&metadata.run_id)
+            && RunMetadata::load(&run_dir).is_ok_and(|m| m.config_hash == metadata.config_hash)
+        {
+            self.results.lock().unwrap().push(previous.clone());
+            return;
+        }
+
+        // Snapshots are written as they are taken, next to the run directory until it is saved
+        let mut snapshot_dir = run_dir.file_name().unwrap_or_default().to_owned();
+        snapshot_dir.push(".snapshots");
+        let snapshot_dir = run_dir.with_file_name(snapshot_dir);
+
+        let mut used_seed = seed;
+        let mut rng_seed = self.rng_seed(neighborhood, &pipeline.name, seed, used_seed);
+        let mut retry_logs = Vec::new();
+        let Simulation {
+            engine,
+            report,
+            mut logs,
+            frames,
+            snapshots,
+        } = loop {
+            let simulation = self.simulate(pipeline, air_prob, rng_seed, &snapshot_dir);
+            let ctx = simulation.engine.context();
+            let air_fraction = ctx.total_air_cells() as f64 / ctx.cells().len().max(1) as f64;
+
+            match &self.config.retry {
+                Some(retry)
+                    if retry_logs.len() < retry.max_retries
+                        && retry.is_degenerate(air_fraction) =>
+                {
+                    retry_logs.push(format!(
+                        "degenerate seed={rng_seed} air_fraction={air_fraction:.4}"
+                    ));
+                    used_seed = self.retry_seed(seed, retry_logs.len());
+                    rng_seed = self.rng_seed(neighborhood, &pipeline.name, seed, used_seed);
+                }
+                _ => break simulation,
+            }
+        };
+        metadata.seed = rng_seed;
+        metadata.retries = retry_logs.len();
+        retry_logs.append(&mut logs);
+        logs = retry_logs;
+
+        metadata.engine_mode = engine.resolved_mode();
+        metadata.boundary = engine.config.boundary.name();
+        metadata.iterations_completed = report.iterations_run;
+        metadata.entrance_positions = (self.config.entrances)
+            .map(|e| e.positions(self.config.width, self.config.height, rng_seed))
+            .unwrap_or_default();
+
+        // The engine is done, its grid moves into the run info instead of being cloned
+        let mem_bytes = engine.memory_bytes();
+        let CAConfig {
+            neighborhood,
+            boundary,
+            ..
+        } = engine.config.clone();
+        let mut info = RunInfo::new(metadata, engine.into_context());
+        info.set_logs(logs);
+        let coordination = metrics::coordination_number_distribution(&info.context);
+        info.log(format!(
+            "coordination_numbers={}",
+            serde_json::to_string(&coordination).expect("Failed to serialize coordination numbers")
+        ));
+        info.set_vox_bands(self.config.vox_bands.clone());
+        info.set_vox_color(self.config.vox_color);
+        info.distance_vox = self.config.distance_vox;
+        info.projections.clone_from(&self.config.projections);
+        info.ply = self.config.ply;
+        info.ply_colors = self.config.ply_colors;
+        info.save_grids = self.config.save_grids;
+        info.log_format = self.config.log_format;
+        info.cave_graph = self.config.cave_graph.map(|p| {
+            topology::extract_cave_graph(&info.context, p.room_min_volume, p.corridor_max_width)
+        });
+        info.boundary = boundary;
+        info.snapshots = snapshots.into_iter().map(|(_, path)| path).collect();
+        if self.config.save_gif {
+            info.gif = Some(GifExport {
+                delay_cs: self.config.gif_delay_cs,
+                frames,
+            });
+        }
+
+        let results = RunResults::from_context(
+            &info.metadata,
+            &info.context,
+            &boundary,
+            &report,
+            mem_bytes,
+            self.config.tortuosity_samples,
+            self.config.neighborhood_components.then_some(&neighborhood),
+            self.config.room_threshold,
+        );
+        info.log(format!(
+            "articulation_points={}",
+            results.articulation_point_count
+        ));
+        if info.log_format == LogFormat::Json {
+            info.iterations = report.per_iter;
+        }
+
+        info.save(&run_dir).expect("Failed to save run info");
+        if snapshot_dir.exists() {
+            std::fs::remove_dir_all(&snapshot_dir).expect("Failed to remove snapshot directory");
+        }
+
+        let mut res_lock = self.results.lock().unwrap();
+        res_lock.push(results);
+    }
+
+    // Evolves and post-processes one grid from `seed`. Snapshots are written into
+    // `snapshot_dir`, which is cleared first
+    fn simulate(
+        &self,
+        pipeline: &CAPipeline,
+        air_prob: f64,
+        seed: u64,
+        snapshot_dir: &Path,
+    ) -> Simulation {
+        let mut context = self.config.initializer.build_with_shell(
+            self.init_file.as_ref(),
             self.config.width,
             self.config.height,
             self.config.depth,
             seed,
-            self.config.air_percentage,
+            air_prob,
+            &self.config.shell,
         );
+        let region = self.config.evolved_region();
+        for stamp in &self.config.stamps {
+            context.stamp_within(stamp, CACell::new(1), region);
+        }
+        let mut frozen = None;
+        if let Some(entrances) = &self.config.entrances {
+            let positions = entrances.positions(self.config.width, self.config.height, seed);
+            let carved = context.carve_entrances(entrances, &positions);
+            frozen = entrances.frozen.then_some(carved);
+        }
 
-        let config = CAConfig {
-            neighborhood: neighborhood.clone(),
-            rule: rule.clone(),
-        };
+        let first = &pipeline.stages[0];
+        let config = first.config.clone();
 
-        let mut engine = CAEngine::new(config, context);
+        let mut engine = CAEngine::new(config, context)
+            .with_mode(self.config.engine_mode)
+            .with_seed(seed);
+        engine
+            .set_region(region)
+            .expect("Region does not fit the grid");
+        engine.set_frozen(frozen);
         let mut logs = Vec::new();
 
-        // Time the run
-        let now = Instant::now();
-        engine.run(self.config.iterations, &mut logs);
-        let elapsed = now.elapsed();
-
-        let mut info = RunInfo::new(
-            RunMetadata::new(
-                seed,
-                neighborhood.name.clone(),
-                self.config.width,
-                self.config.height,
-                self.config.depth,
-                self.config.iterations,
-                rule.name.clone(),
-                self.config.air_percentage,
-            ),
-            engine.context.clone(),
-        );
+        let mut frames = Vec::new();
+        let mut snapshots = Vec::new();
+        // Frames and snapshots are only kept for files that will be written
+        let save_gif = self.config.save_gif && self.config.save_grids;
+        let every = if self.config.save_grids {
+            self.config.snapshot_every
+        } else {
+            0
+        };
+        let (width, height, z) = (self.config.width, self.config.height, self.config.gif_z);
+        let gif_slice = Region::new((0, 0, z), (width, height, z + 1));
 
-        info.set_logs(logs);
+        let mut bands = self.config.vox_bands.clone();
+        bands.sort_by_key(|&(threshold, _)| threshold);
+        let last_stage = pipeline.stages.last().unwrap_or(first);
+        let style = VoxStyle {
+            bands: &bands,
+            color: self.config.vox_color,
+            boundary: last_stage.config.boundary,
+        };
+        if every > 0 {
+            if snapshot_dir.exists() {
+                std::fs::remove_dir_all(snapshot_dir).expect("Failed to clear snapshots");
+            }
+            std::fs::create_dir_all(snapshot_dir).expect("Failed to create snapshot directory");
+        }
+        let write_snapshot = |iter: usize, ctx: &CAContext| {
+            let path = snapshot_dir.join(format!("iter_{iter:04}.vox"));
+            style
+                .write_vox(ctx, &path)
+                .expect("Failed to save snapshot");
+            (iter, path)
+        };
 
-        let runs_dir = self.config.output_dir.join("runs");
-        info.save(&runs_dir).expect("Failed to save run info");
+        let record = |iter: usize, ctx: &CAContext| {
+            if save_gif {
+                frames.push((iter, ctx.crop(&gif_slice)));
+            }
+            if every > 0 && iter.is_multiple_of(every) {
+                snapshots.push(write_snapshot(iter, ctx));
+            }
+        };
+        let report = if pipeline.stages.len() == 1 {
+            engine.run_with_hook(first.iterations, &mut logs, self.config.time_limit, record)
+        } else {
+            pipeline.run(&mut engine, &mut logs, self.config.time_limit, record)
+        };
 
-        let results =
-            RunResults::from_context(&info.metadata, &engine.context, elapsed.as_millis());
-        let mut res_lock = self.results.lock().unwrap();
-        res_lock.push(results);
+        // The final grid is always captured, even off the snapshot interval
+        let last = snapshots.last().map(|&(iter, _)| iter);
+        if every > 0 && last != Some(report.iterations_run) {
+            snapshots.push(write_snapshot(report.iterations_run, engine.context()));
+        }
+
+        if self.config.clean_isolated {
+            let removed = engine.remove_isolated_cells();
+            logs.push(format!("removed_isolated={removed}"));
+        }
+        if self.config.remove_dead_ends > 0 {
+            let removed = engine.remove_dead_ends(self.config.remove_dead_ends);
+            logs.push(format!("removed_dead_ends={removed}"));
+        }
+        if let Some(axis) = self.config.carve_path {
+            let carved = engine.ensure_percolates(axis);
+            logs.push(format!("carved_cells={carved}"));
+        }
+
+        Simulation {
+            engine,
+            report,
+            logs,
+            frames,
+            snapshots,
+        }
+    }
+
+    // RunMetadata::config_hash of a run whose metadata holds the configured settings so far.
+    // Metric settings and the seed derivation go in too, they change the results row
+    fn run_hash(&self, metadata: &RunMetadata, neighborhood: &str, seed: u64) -> String {
+        #[derive(Serialize)]
+        struct RunKey<'a> {
+            metadata: &'a RunMetadata,
+            rng_seed: u64,
+            engine_mode: EngineMode,
+            retry: Option<&'a RetryPolicy>,
+            tortuosity_samples: usize,
+            neighborhood_components: bool,
+            room_threshold: usize,
+        }
+
+        let key = RunKey {
+            metadata,
+            rng_seed: self.rng_seed(neighborhood, &metadata.ruleset, seed, seed),
+            engine_mode: self.config.engine_mode,
+            retry: self.config.retry.as_ref(),
+            tortuosity_samples: self.config.tortuosity_samples,
+            neighborhood_components: self.config.neighborhood_components,
+            room_threshold: self.config.room_threshold,
+        };
+        let json = serde_json::to_vec(&key).expect("Failed to serialize run settings");
+        format!("{:016x}", hash_seed(&[&json]))
+    }
+
+    // The seed a run of `configured` evolves from when it is on attempt `used`. Under
+    // hash_seeds two configurations never share a stream, even when their seed lists overlap
+    fn rng_seed(&self, neighborhood: &str, ruleset: &str, configured: u64, used: u64) -> u64 {
+        if !self.config.hash_seeds {
+            return used;
+        }
+
+        let index = self
+            .config
+            .seeds
+            .iter()
+            .position(|&s| s == configured)
+            .unwrap_or(0);
+        hash_seed(&[
+            &used.to_le_bytes(),
+            neighborhood.as_bytes(),
+            &[0],
+            ruleset.as_bytes(),
+            &[0],
+            &(index as u64).to_le_bytes(),
+        ])
+    }
+
+    // Seeds for retries continue past the largest configured seed, with a separate block of
+    // max_retries seeds per configured seed so no two runs of a job share one
+    fn retry_seed(&self, seed: u64, attempt: usize) -> u64 {
+        let seeds = &self.config.seeds;
+        let max_retries = self.config.retry.as_ref().map_or(0, |r| r.max_retries);
+        let largest = seeds.iter().copied().max().unwrap_or(seed);
+        let slot = seeds.iter().position(|&s| s == seed).unwrap_or(0);
+
+        largest.wrapping_add((slot * max_retries + attempt) as u64)
     }
 
     fn group_by_config(results: &[RunResults]) -> HashMap<ConfigKey, Vec<&RunResults>> {
@@ -142,15 +1141,19 @@ impl Runner {
     fn write_results(&self) {
         let results = self.results.lock().unwrap();
 
-        let path = self.config.output_dir.join("metrics.csv");
-        let file = std::fs::File::create(path).unwrap();
-        let mut writer = csv::Writer::from_writer(file);
-
-        for r in results.iter() {
-            writer.serialize(r).unwrap();
+        let path = self.config.metrics_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create the metrics.csv directory");
         }
+        write_results_csv(&path, &results).expect("Failed to write metrics.csv");
 
-        writer.flush().unwrap();
+        if let Some(db_path) = &self.config.db_path {
+            let mut db = ResultsDb::open(db_path).expect("Failed to open results database");
+            db.create_schema()
+                .expect("Failed to create results database schema");
+            db.insert_results(&results)
+                .expect("Failed to insert into results database");
+        }
     }
 
     fn write_diversity_stats(&self) {
@@ -169,6 +1172,23 @@ impl Runner {
         writer.flush().unwrap();
     }
 
+    // Writes summary.csv and returns the same rows as a printable table
+    fn write_summary(&self) -> String {
+        let results = self.results.lock().unwrap();
+        let rows = crate::data::summarize(&results);
+
+        let path = self.config.output_dir.join("summary.csv");
+        let file = std::fs::File::create(path).unwrap();
+        let mut writer = csv::Writer::from_writer(file);
+
+        for row in &rows {
+            writer.serialize(row).unwrap();
+        }
+
+        writer.flush().unwrap();
+        crate::data::summary_table(&rows)
+    }
+
     fn write_hardware_info(&self) -> std::io::Result<()> {
         let sys = System::new_all();
         let path = self.config.output_dir.join("hardware.json");
@@ -177,3 +1197,590 @@ impl Runner {
         Ok(())
     }
 }
+
+// metrics_path_template filled in for a grid size, below output_dir
+fn metrics_path(
+    output_dir: &Path,
+    template: &str,
+    width: usize,
+    height: usize,
+    depth: usize,
+) -> PathBuf {
+    let values = [
+        ("width", width.to_string()),
+        ("height", height.to_string()),
+        ("depth", depth.to_string()),
+    ];
+    output_dir.join(fill_template(template, &values))
+}
+
+// Runs recomputed by reanalyze, `replayed` of them had to be evolved again because their
+// grid.vox did not hold the full grid. `skipped` runs could not be read or replayed
+pub struct Reanalysis {
+    pub runs: usize,
+    pub replayed: usize,
+    pub skipped: usize,
+}
+
+// Rewrites the metrics files from the runs saved anywhere below output_dir, so any
+// run_dir_template is found. Every run goes back into the file `metrics_path_template` resolves
+// to for its grid size. Grids are read back from grid.vox where possible and replayed from
+// metadata.json otherwise, e.g. without save_grids. Timing and convergence are not stored with
+// a run, those columns are kept from the existing row. Runs that fail to load are
+// skipped with a warning
+pub fn reanalyze(
+    output_dir: &Path,
+    metrics_path_template: &str,
+    tortuosity_samples: usize,
+    room_threshold: usize,
+) -> std::io::Result<Reanalysis> {
+    let mut run_dirs = Vec::new();
+    find_run_dirs(output_dir, &mut run_dirs)?;
+    run_dirs.sort();
+
+    // One broken run should not cost the rows of all the others
+    let mut skipped = 0;
+    let mut skip = |run_dir: &Path, err: &dyn fmt::Display| {
+        eprintln!("Warning: skipping {}: {err}", run_dir.display());
+        skipped += 1;
+    };
+
+    let mut previous = HashMap::new();
+    let mut runs = Vec::with_capacity(run_dirs.len());
+    for run_dir in run_dirs {
+        match RunMetadata::load(&run_dir) {
+            Ok(metadata) => {
+                let (w, h, d) = (metadata.width, metadata.height, metadata.depth);
+                let path = metrics_path(output_dir, metrics_path_template, w, h, d);
+                if !previous.contains_key(&path) {
+                    previous.insert(path.clone(), Runner::load_previous(&path));
+                }
+                runs.push((run_dir, metadata, path));
+            }
+            Err(err) => skip(&run_dir, &err),
+        }
+    }
+
+    let pb = ProgressBar::new(runs.len() as u64);
+    pb.set_style(
+        ProgressStyle::with_template("[Reanalyze] {bar:40.cyan/blue} Run {pos}/{len}")
+            .expect("Failed to set progress bar style")
+            .progress_chars("=> "),
+    );
+
+    let rows: Vec<_> = (runs.par_iter())
+        .map(|(run_dir, metadata, path)| {
+            let old = previous[path].get(&metadata.run_id);
+            let row = reanalyze_run(run_dir, metadata, old, tortuosity_samples, room_threshold);
+            pb.inc(1);
+            row
+        })
+        .collect();
+    pb.finish_and_clear();
+
+    let mut files: HashMap<&Path, Vec<RunResults>> = HashMap::new();
+    let (mut done, mut replayed) = (0, 0);
+    for ((run_dir, _, path), row) in runs.iter().zip(rows) {
+        match row {
+            Ok((row, was_replayed)) => {
+                done += 1;
+                replayed += usize::from(was_replayed);
+                files.entry(path).or_default().push(row);
+            }
+            Err(err) => skip(run_dir, &err),
+        }
+    }
+    for (path, results) in files {
+        write_results_csv(path, &results).map_err(std::io::Error::other)?;
+    }
+
+    Ok(Reanalysis {
+        runs: done,
+        replayed,
+        skipped,
+    })
+}
+
+// Directories below `dir` holding a metadata.json, which are not searched any further
+fn find_run_dirs(dir: &Path, run_dirs: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        // Unfinished saves are left in .tmp siblings
+        if !path.is_dir() || path.extension().is_some_and(|ext| ext == "tmp") {
+            continue;
+        }
+        if path.join("metadata.json").exists() {
+            run_dirs.push(path);
+        } else {
+            find_run_dirs(&path, run_dirs)?;
+        }
+    }
+    Ok(())
+}
+
+// The results row of one run, `old` is the run's row in the existing metrics file
+fn reanalyze_run(
+    run_dir: &Path,
+    metadata: &RunMetadata,
+    old: Option<&RunResults>,
+    tortuosity_samples: usize,
+    room_threshold: usize,
+) -> std::io::Result<(RunResults, bool)> {
+    // Banded grids only hold air components. A grid whose hash differs from the recorded one
+    // lost cell states in the export
+    let grid_path = run_dir.join("grid.vox");
+    let loaded = if metadata.vox_bands.is_empty() && grid_path.exists() {
+        load_vox_grid(&grid_path, metadata.width, metadata.height, metadata.depth)?
+            .filter(|ctx| old.is_none_or(|r| r.grid_hash == ctx.fingerprint()))
+    } else {
+        None
+    };
+    let replayed = loaded.is_none();
+    let context = match loaded {
+        Some(ctx) => ctx,
+        None => RunInfo::replay(run_dir)?.context,
+    };
+
+    let boundary = (metadata.stages.first())
+        .map(|stage| stage.config.boundary)
+        .or_else(|| Boundary::preset(&metadata.boundary))
+        .unwrap_or_default();
+    let report = EngineReport {
+        iterations_run: metadata.iterations_completed,
+        per_iter: Vec::new(),
+        wall_time: Duration::ZERO,
+        stop_reason: if metadata.iterations_completed < metadata.iterations {
+            StopReason::TimeLimit
+        } else {
+            StopReason::Completed
+        },
+    };
+
+    // Keep the connectivity the row was computed with, the final neighborhood is the last
+    // stage's
+    let neighborhood = (metadata.stages.last())
+        .filter(|_| old.is_some_and(|r| r.component_connectivity != "faces"))
+        .map(|stage| &stage.config.neighborhood);
+
+    let mut results = RunResults::from_context(
+        metadata,
+        &context,
+        &boundary,
+        &report,
+        0,
+        tortuosity_samples,
+        neighborhood,
+        room_threshold,
+    );
+    if let Some(old) = old {
+        results.duration_ms = old.duration_ms;
+        results.mem_bytes = old.mem_bytes;
+        results.converged_at = old.converged_at;
+        results.final_activity = old.final_activity;
+    }
+
+    Ok((results, replayed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::{CANeighborhood, CARule, CARuleType};
+
+    // A fresh directory under the system temp dir, removed first if an earlier run left it
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gradwork-ca-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn test_config(output_dir: PathBuf) -> RunnerConfig {
+        RunnerConfig {
+            width: 12,
+            height: 12,
+            depth: 12,
+            air_probs: vec![0.5],
+            initializer: Initializer::Random,
+            iterations: 3,
+            seeds: vec![1],
+            hash_seeds: false,
+            neighborhoods: vec![CANeighborhood::moore()],
+            rulesets: vec![CARule {
+                name: "B".to_string(),
+                rule_type: CARuleType::Standard {
+                    birth: vec![5, 6, 7, 8],
+                    survival: vec![4, 5, 6, 7, 8],
+                },
+                temperature: None,
+            }],
+            pipelines: Vec::new(),
+            output_dir,
+            run_dir_template: DEFAULT_RUN_DIR_TEMPLATE.to_string(),
+            metrics_path_template: DEFAULT_METRICS_PATH_TEMPLATE.to_string(),
+            db_path: None,
+            tortuosity_samples: 0,
+            neighborhood_components: false,
+            room_threshold: 10,
+            vox_bands: Vec::new(),
+            vox_color: crate::data::DEFAULT_VOX_COLOR,
+            distance_vox: false,
+            projections: Vec::new(),
+            ply: false,
+            ply_colors: false,
+            save_grids: true,
+            log_format: LogFormat::Text,
+            cave_graph: None,
+            engine_mode: EngineMode::Dense,
+            boundary: Boundary::default(),
+            count_states: CountStates::default(),
+            include_self: false,
+            clean_isolated: false,
+            remove_dead_ends: 0,
+            carve_path: None,
+            region: None,
+            roi: None,
+            shell: ShellSpec::default(),
+            stamps: Vec::new(),
+            entrances: None,
+            time_limit: None,
+            strict: false,
+            save_gif: false,
+            gif_z: 0,
+            gif_delay_cs: 20,
+            snapshot_every: 0,
+            overwrite: false,
+            retry: None,
+            file_problems: Vec::new(),
+        }
+    }
+
+    fn only_run_dir(config: &RunnerConfig) -> PathBuf {
+        let mut dirs = Vec::new();
+        find_run_dirs(&config.output_dir, &mut dirs).unwrap();
+        assert_eq!(dirs.len(), 1);
+        dirs.remove(0)
+    }
+
+    #[test]
+    fn init_file_is_read_once_when_the_runner_is_built() {
+        let dir = scratch_dir("init-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let init_path = dir.join("init.npy");
+        CAContext::random(12, 12, 12, 4, 0.5)
+            .to_npy(&init_path)
+            .unwrap();
+
+        let mut config = test_config(dir.join("out"));
+        config.initializer = Initializer::Npy {
+            path: init_path.clone(),
+        };
+        config.seeds = vec![1, 2];
+        let runner = Runner::new(config).unwrap();
+
+        // Every run builds from the grid loaded by Runner::new
+        std::fs::remove_file(&init_path).unwrap();
+        runner.run();
+        assert_eq!(runner.results.lock().unwrap().len(), 2);
+        assert!(runner.config.validate().is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn reanalyze_skips_runs_that_fail_to_load() {
+        let dir = scratch_dir("reanalyze-skip");
+        let mut config = test_config(dir.clone());
+        config.seeds = vec![1, 2];
+        Runner::new(config).unwrap().run();
+
+        let mut run_dirs = Vec::new();
+        find_run_dirs(&dir, &mut run_dirs).unwrap();
+        run_dirs.sort();
+        std::fs::write(run_dirs[0].join("metadata.json"), "{}").unwrap();
+
+        let done = reanalyze(&dir, DEFAULT_METRICS_PATH_TEMPLATE, 0, 10).unwrap();
+        assert_eq!((done.runs, done.skipped), (1, 1));
+        let rows = load_results_csv(&dir.join("metrics.csv")).unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn room_counts_follow_the_component_connectivity() {
+        let dir = scratch_dir("room-connectivity");
+        let mut config = test_config(dir.clone());
+        let boundary = Boundary::preset("periodic").unwrap();
+        config.boundary = boundary;
+        config.neighborhood_components = true;
+        config.room_threshold = 3;
+        Runner::new(config).unwrap().run();
+
+        let rows = load_results_csv(&dir.join("metrics.csv")).unwrap();
+        let run_dir = only_run_dir(&test_config(dir.clone()));
+        let ctx = load_vox_grid(&run_dir.join("grid.vox"), 12, 12, 12)
+            .unwrap()
+            .unwrap();
+        let components = ctx.connected_components_via(&CANeighborhood::moore(), &boundary);
+        let rooms = topology::rooms_in(&ctx, &components, 3);
+        assert_eq!(rows[0].room_count, rooms.len());
+        assert_eq!(
+            rows[0].largest_room_volume,
+            rooms.iter().map(|r| r.volume).max().unwrap_or(0)
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn stamps_leave_frozen_layers_solid() {
+        let dir = scratch_dir("stamps-frozen");
+        let mut config = test_config(dir.clone());
+        let layer = |z_from, z_to, air_prob, frozen| crate::ca::Layer {
+            z_from,
+            z_to,
+            air_prob,
+            frozen,
+            units: crate::ca::StampUnits::Cells,
+        };
+        config.initializer = Initializer::Layers {
+            layers: vec![layer(0.0, 4.0, 0.0, true), layer(4.0, 12.0, 0.5, false)],
+        };
+        config.stamps = vec![Stamp {
+            shape: crate::ca::StampShape::Box {
+                min: [2.0, 2.0, 0.0],
+                max: [10.0, 10.0, 8.0],
+            },
+            units: crate::ca::StampUnits::Cells,
+        }];
+        Runner::new(config).unwrap().run();
+
+        let run_dir = only_run_dir(&test_config(dir.clone()));
+        let ctx = load_vox_grid(&run_dir.join("grid.vox"), 12, 12, 12)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ctx.cells()[..4 * 12 * 12]
+                .iter()
+                .filter(|c| c.is_air())
+                .count(),
+            0
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn invalid_layer_stacks_are_config_problems() {
+        let layer = |z_from, z_to, frozen| crate::ca::Layer {
+            z_from,
+            z_to,
+            air_prob: 0.5,
+            frozen,
+            units: crate::ca::StampUnits::Cells,
+        };
+        let stacks = [
+            (
+                vec![layer(0.0, 4.0, false), layer(6.0, 12.0, false)],
+                LayerError::Gap(4..6),
+            ),
+            (
+                vec![layer(0.0, 7.0, false), layer(5.0, 12.0, false)],
+                LayerError::Overlap(5..7),
+            ),
+            (
+                vec![
+                    layer(0.0, 4.0, false),
+                    layer(4.0, 8.0, true),
+                    layer(8.0, 12.0, false),
+                ],
+                LayerError::FrozenBetween(4..8),
+            ),
+        ];
+
+        for (layers, expected) in stacks {
+            let mut config = test_config(scratch_dir("layers"));
+            config.initializer = Initializer::Layers { layers };
+            let problems = config.validate().unwrap_err().problems;
+            assert!(
+                problems
+                    .iter()
+                    .any(|p| matches!(p, ConfigProblem::Layers(e) if *e == expected)),
+                "{problems:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reanalyze_writes_the_templated_metrics_file() {
+        let dir = scratch_dir("reanalyze-template");
+        let mut config = test_config(dir.clone());
+        config.metrics_path_template = "metrics/{width}x{depth}.csv".to_string();
+        let runner = Runner::new(config).unwrap();
+        runner.run();
+        let path = runner.config.metrics_path();
+        assert_eq!(path, dir.join("metrics/12x12.csv"));
+        let before = load_results_csv(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        reanalyze(&dir, &runner.config.metrics_path_template, 0, 10).unwrap();
+        assert!(!dir.join("metrics.csv").exists());
+        let after = load_results_csv(&path).unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].grid_hash, before[0].grid_hash);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn finished_runs_are_only_reused_with_matching_settings() {
+        let dir = scratch_dir("reuse");
+        Runner::new(test_config(dir.clone())).unwrap().run();
+        let run_dir = only_run_dir(&test_config(dir.clone()));
+        let first = RunMetadata::load(&run_dir).unwrap();
+        let written = std::fs::metadata(run_dir.join("metadata.json"))
+            .and_then(|m| m.modified())
+            .unwrap();
+
+        // Same settings, the run directory is left alone
+        Runner::new(test_config(dir.clone())).unwrap().run();
+        let rewritten = std::fs::metadata(run_dir.join("metadata.json"))
+            .and_then(|m| m.modified())
+            .unwrap();
+        assert_eq!(written, rewritten);
+
+        // include_self is not part of the run_id, the run still has to be redone
+        let mut changed = test_config(dir.clone());
+        changed.include_self = true;
+        Runner::new(changed).unwrap().run();
+        let second = RunMetadata::load(&run_dir).unwrap();
+        assert_eq!(first.run_id, second.run_id);
+        assert_ne!(first.config_hash, second.config_hash);
+        assert!(second.stages[0].config.include_self);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn gif_slice_must_lie_inside_the_grid() {
+        let mut config = test_config(scratch_dir("gif-bounds"));
+        config.save_gif = true;
+        config.gif_z = config.depth;
+        let problems = config.validate().unwrap_err().problems;
+        assert!(matches!(problems[..], [ConfigProblem::GifSlice(12)]));
+
+        config.gif_z = config.depth - 1;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn snapshots_are_written_as_they_are_taken() {
+        let dir = scratch_dir("snapshots");
+        for (every, expected) in [(0, vec![]), (1, vec![0, 1, 2, 3, 4]), (3, vec![0, 3, 4])] {
+            let mut config = test_config(dir.clone());
+            config.iterations = 4;
+            config.snapshot_every = every;
+            let runner = Runner::new(config).unwrap();
+            let run = &runner.config.rulesets[0];
+            let pipeline = CAPipeline {
+                name: run.name.clone(),
+                stages: vec![CAStage {
+                    config: CAConfig {
+                        neighborhood: CANeighborhood::moore(),
+                        rule: run.clone(),
+                        boundary: Boundary::default(),
+                        count_states: CountStates::default(),
+                        include_self: false,
+                    },
+                    iterations: 4,
+                }],
+                composition: Composition::Sequential,
+            };
+
+            let snapshots = runner.simulate(&pipeline, 0.5, 1, &dir).snapshots;
+            let iters: Vec<usize> = snapshots.iter().map(|&(iter, _)| iter).collect();
+            assert_eq!(iters, expected);
+            assert!(snapshots.iter().all(|(_, path)| path.is_file()));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn gif_keeps_one_slice_per_iteration() {
+        let dir = scratch_dir("gif");
+        let mut config = test_config(dir.clone());
+        config.save_gif = true;
+        config.gif_z = 4;
+        let pipeline = CAPipeline {
+            name: "B".to_string(),
+            stages: vec![CAStage {
+                config: CAConfig {
+                    neighborhood: config.neighborhoods[0].clone(),
+                    rule: config.rulesets[0].clone(),
+                    boundary: config.boundary,
+                    count_states: config.count_states.clone(),
+                    include_self: false,
+                },
+                iterations: config.iterations,
+            }],
+            composition: Composition::Sequential,
+        };
+        let runner = Runner::new(config).unwrap();
+        let simulation = runner.simulate(&pipeline, 0.5, 1, &dir.join("snapshots"));
+
+        assert_eq!(simulation.frames.len(), runner.config.iterations + 1);
+        for (iter, frame) in &simulation.frames {
+            assert_eq!((frame.width(), frame.height(), frame.depth()), (12, 12, 1));
+            let full = runner.config.initializer.build(12, 12, 12, 1, 0.5);
+            let mut engine = CAEngine::new(pipeline.stages[0].config.clone(), full);
+            engine.run(*iter, &mut Vec::new());
+            let expected = Region::new((0, 0, 4), (12, 12, 5));
+            assert_eq!(*frame, engine.context().crop(&expected));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn results_csv_round_trips() {
+        let dir = scratch_dir("csv-round-trip");
+        let mut config = test_config(dir.clone());
+        config.seeds = vec![1, 2, 3];
+        config.tortuosity_samples = 20;
+        let runner = Runner::new(config).unwrap();
+        runner.run();
+
+        let mut results = runner.results.lock().unwrap().clone();
+        results.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+        let path = dir.join("round-trip.csv");
+        write_results_csv(&path, &results).unwrap();
+        assert_eq!(load_results_csv(&path).unwrap(), results);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn replay_reproduces_the_saved_grid() {
+        let dir = scratch_dir("replay-grid");
+        let mut config = test_config(dir.clone());
+        config.rulesets[0].temperature = Some(0.6);
+        config.clean_isolated = true;
+        config.stamps = vec![Stamp {
+            shape: crate::ca::StampShape::Sphere {
+                center: [0.5, 0.5, 0.5],
+                radius: 0.2,
+            },
+            units: crate::ca::StampUnits::Fraction,
+        }];
+        Runner::new(config).unwrap().run();
+
+        let run_dir = only_run_dir(&test_config(dir.clone()));
+        let replayed = dir.join("replayed");
+        RunInfo::replay(&run_dir).unwrap().save(&replayed).unwrap();
+        let original = std::fs::read(run_dir.join("grid.vox")).unwrap();
+        assert_eq!(std::fs::read(replayed.join("grid.vox")).unwrap(), original);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::ca::{Boundary, CAContext, FACE_OFFSETS, Region};
+use crate::metrics;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::path::Path;
+
+// Settings of extract_cave_graph
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CaveGraphParams {
+    pub room_min_volume: usize,
+    pub corridor_max_width: usize,
+}
+
+// Rooms of a cave and the corridors between them
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaveGraph {
+    pub rooms: Vec<Room>,
+    pub corridors: Vec<Corridor>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Room {
+    // Face-connected air component holding the room, in CAContext::connected_components order
+    pub component: usize,
+    pub centroid: [f64; 3],
+    pub volume: usize,
+    pub bounds: Region,
+}
+
+// The shortest face-connected passage found between two rooms. The path starts on a cell of
+// rooms.0 and ends on a cell of rooms.1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Corridor {
+    pub rooms: (usize, usize),
+    pub path: Vec<(usize, usize, usize)>,
+}
+
+impl Room {
+    fn from_cells(ctx: &CAContext, component: usize, cells: &[usize]) -> Self {
+        let mut centroid = [0.0; 3];
+        let mut bounds = Region::new((usize::MAX, usize::MAX, usize::MAX), (0, 0, 0));
+        for &i in cells {
+            let (x, y, z) = ctx.pos(i);
+            centroid[0] += x as f64;
+            centroid[1] += y as f64;
+            centroid[2] += z as f64;
+            bounds.min = (
+                bounds.min.0.min(x),
+                bounds.min.1.min(y),
+                bounds.min.2.min(z),
+            );
+            bounds.max = (
+                bounds.max.0.max(x + 1),
+                bounds.max.1.max(y + 1),
+                bounds.max.2.max(z + 1),
+            );
+        }
+
+        Self {
+            component,
+            centroid: centroid.map(|c| c / cells.len() as f64),
+            volume: cells.len(),
+            bounds,
+        }
+    }
+}
+
+impl CaveGraph {
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self).map_err(std::io::Error::other)
+    }
+}
+
+// Every face-connected air component of at least `min_room_volume` cells as a room
+#[must_use]
+pub fn detect_rooms(ctx: &CAContext, min_room_volume: usize) -> Vec<Room> {
+    rooms_in(ctx, &ctx.connected_components(), min_room_volume)
+}
+
+// Rooms among already labeled components, so room counts follow whatever connectivity and
+// boundary the components were found with
+#[must_use]
+pub fn rooms_in(ctx: &CAContext, components: &[Vec<usize>], min_room_volume: usize) -> Vec<Room> {
+    (components.iter().enumerate())
+        .filter(|(_, cells)| cells.len() >= min_room_volume)
+        .map(|(c, cells)| Room::from_cells(ctx, c, cells))
+        .collect()
+}
+
+// Air component volumes in powers of two: bin k counts the components of 2^k up to
+// 2^(k+1) - 1 cells, the last bin also takes every larger one
+#[must_use]
+pub fn room_size_histogram(ctx: &CAContext, bins: usize) -> Vec<usize> {
+    let mut histogram = vec![0; bins];
+    if bins == 0 {
+        return histogram;
+    }
+    for cells in ctx.connected_components() {
+        let bin = cells.len().ilog2() as usize;
+        histogram[bin.min(bins - 1)] += 1;
+    }
+    histogram
+}
+
+// Abstracts the air of a grid into rooms joined by corridors. Air wider than
+// `corridor_max_width` (twice the Chebyshev distance to the rock, minus one) forms room cores,
+// each core of at least `room_min_volume` cells grows back out to the walls it is that far from.
+// Every room then floods the remaining air, and wherever two floods meet the rooms get a
+// corridor along the shortest meeting found
+#[must_use]
+pub fn extract_cave_graph(
+    ctx: &CAContext,
+    room_min_volume: usize,
+    corridor_max_width: usize,
+) -> CaveGraph {
+    let n = ctx.cells().len();
+    let dist = metrics::distance_transform_l_inf(ctx);
+    let wide =
+        |i: usize| ctx[i].is_air() && (2 * dist[i] as usize).saturating_sub(1) > corridor_max_width;
+
+    // Face-connected room cores, too small ones are left to the corridors
+    let mut room_of = vec![usize::MAX; n];
+    let mut cores: Vec<Vec<usize>> = Vec::new();
+    let mut seen = vec![false; n];
+    for start in 0..n {
+        if seen[start] || !wide(start) {
+            continue;
+        }
+        seen[start] = true;
+        let mut core = vec![start];
+        let mut k = 0;
+        while let Some(&v) = core.get(k) {
+            k += 1;
+            for w in face_neighbors(ctx, v) {
+                if !seen[w] && wide(w) {
+                    seen[w] = true;
+                    core.push(w);
+                }
+            }
+        }
+        if core.len() >= room_min_volume {
+            for &i in &core {
+                room_of[i] = cores.len();
+            }
+            cores.push(core);
+        }
+    }
+
+    // Grow every core cell by its distance to the rock, largest reach first, so a room covers
+    // the union of the cubes that fit around its core. Overlaps go to the room that got there first
+    let max_dist = cores.iter().flatten().map(|&i| dist[i]).max().unwrap_or(0) as usize;
+    let mut buckets: Vec<Vec<usize>> = vec![Vec::new(); max_dist + 1];
+    let mut reach = vec![0; n];
+    let cube = metrics::Connectivity::TwentySix.offsets();
+    for &i in cores.iter().flatten() {
+        reach[i] = dist[i] as usize - 1;
+        buckets[reach[i]].push(i);
+    }
+    for r in (1..buckets.len()).rev() {
+        while let Some(v) = buckets[r].pop() {
+            if reach[v] != r {
+                continue;
+            }
+            let (x, y, z) = ctx.pos(v);
+            for &d in &cube {
+                let Some(w) = ctx.offset_index(x, y, z, d, &Boundary::default()) else {
+                    continue;
+                };
+                if !ctx[w].is_air() || (room_of[w] != usize::MAX && reach[w] >= r - 1) {
+                    continue;
+                }
+                if room_of[w] == usize::MAX {
+                    room_of[w] = room_of[v];
+                }
+                if room_of[w] == room_of[v] {
+                    reach[w] = r - 1;
+                    buckets[r - 1].push(w);
+                }
+            }
+        }
+    }
+
+    let mut component_of = vec![0; n];
+    for (c, cells) in ctx.connected_components().iter().enumerate() {
+        for &i in cells {
+            component_of[i] = c;
+        }
+    }
+    let mut members = vec![Vec::new(); cores.len()];
+    for i in (0..n).filter(|&i| room_of[i] != usize::MAX) {
+        members[room_of[i]].push(i);
+    }
+    let rooms: Vec<Room> = (members.iter())
+        .map(|cells| Room::from_cells(ctx, component_of[cells[0]], cells))
+        .collect();
+
+    // Flood the other air from every room at once. An edge between cells owned by different
+    // rooms closes a passage of depth(v) + depth(w) + 1 steps, only the shortest per pair is kept
+    let mut owner = room_of;
+    let mut depth = vec![0; n];
+    let mut parent = vec![usize::MAX; n];
+    let mut queue: VecDeque<usize> = (0..n).filter(|&i| owner[i] != usize::MAX).collect();
+    let mut meetings: BTreeMap<(usize, usize), (usize, usize, usize)> = BTreeMap::new();
+    while let Some(v) = queue.pop_front() {
+        for w in face_neighbors(ctx, v) {
+            if !ctx[w].is_air() {
+                continue;
+            }
+            if owner[w] == usize::MAX {
+                owner[w] = owner[v];
+                depth[w] = depth[v] + 1;
+                parent[w] = v;
+                queue.push_back(w);
+            } else if owner[w] != owner[v] {
+                let (a, b) = if owner[v] < owner[w] { (v, w) } else { (w, v) };
+                let len = depth[v] + depth[w] + 1;
+                let best = meetings.entry((owner[a], owner[b])).or_insert((len, a, b));
+                if len < best.0 {
+                    *best = (len, a, b);
+                }
+            }
+        }
+    }
+
+    let corridors = meetings
+        .into_iter()
+        .map(|(rooms, (_, a, b))| {
+            let mut path = trace(&parent, a);
+            path.reverse();
+            path.extend(trace(&parent, b));
+            Corridor {
+                rooms,
+                path: path.into_iter().map(|i| ctx.pos(i)).collect(),
+            }
+        })
+        .collect();
+
+    CaveGraph { rooms, corridors }
+}
+
+// Cells from `i` back to the room its flood started in
+fn trace(parent: &[usize], mut i: usize) -> Vec<usize> {
+    let mut path = vec![i];
+    while parent[i] != usize::MAX {
+        i = parent[i];
+        path.push(i);
+    }
+    path
+}
+
+fn face_neighbors(ctx: &CAContext, i: usize) -> impl Iterator<Item = usize> + '_ {
+    let (x, y, z) = ctx.pos(i);
+    FACE_OFFSETS
+        .into_iter()
+        .filter_map(move |d| ctx.offset_index(x, y, z, d, &Boundary::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::CACell;
+
+    fn fill(ctx: &mut CAContext, region: Region) {
+        for z in region.min.2..region.max.2 {
+            for y in region.min.1..region.max.1 {
+                for x in region.min.0..region.max.0 {
+                    ctx.set(x, y, z, CACell::new(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn two_rooms_joined_by_a_tunnel() {
+        // Two 5x5x5 rooms joined by a one cell wide tunnel along x, plus a sealed 3x3x3 pocket
+        let mut ctx = CAContext::new(18, 9, 13);
+        let bounds = [
+            Region::new((1, 2, 2), (6, 7, 7)),
+            Region::new((12, 2, 2), (17, 7, 7)),
+        ];
+        for region in bounds {
+            fill(&mut ctx, region);
+        }
+        fill(&mut ctx, Region::new((6, 4, 4), (12, 5, 5)));
+        let pocket = Region::new((8, 2, 8), (11, 5, 11));
+        fill(&mut ctx, pocket);
+
+        let graph = extract_cave_graph(&ctx, 2, 2);
+        let found: Vec<_> = (graph.rooms.iter())
+            .map(|room| (room.volume, room.bounds))
+            .collect();
+        assert_eq!(found, [(125, bounds[0]), (125, bounds[1])]);
+        assert_eq!(graph.corridors.len(), 1);
+
+        let corridor = &graph.corridors[0];
+        assert_eq!(corridor.rooms, (0, 1));
+        let (first, last) = (corridor.path[0], corridor.path[corridor.path.len() - 1]);
+        assert!(bounds[0].contains(first.0, first.1, first.2));
+        assert!(bounds[1].contains(last.0, last.1, last.2));
+        for pair in corridor.path.windows(2) {
+            let ((ax, ay, az), (bx, by, bz)) = (pair[0], pair[1]);
+            assert!(ctx.get(bx, by, bz).is_air());
+            assert_eq!(ax.abs_diff(bx) + ay.abs_diff(by) + az.abs_diff(bz), 1);
+        }
+
+        // The pocket's core is a single cell, so it only counts once that is big enough
+        let graph = extract_cave_graph(&ctx, 1, 2);
+        assert_eq!(graph.rooms.len(), 3);
+        assert_eq!((graph.rooms[2].volume, graph.rooms[2].bounds), (27, pocket));
+        assert_eq!(graph.corridors.len(), 1);
+    }
+}